@@ -0,0 +1,72 @@
+// Benchmarks for the parts of a collection run that grow with the size of the network: parsing the
+// router's responses, and encoding the metrics for InfluxDB. Run with `cargo bench`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use fios_stats::metrics::{self, Metric};
+use fios_stats::router::g1100;
+use serde_json::{json, Value};
+
+fn devices_response(count: usize) -> String {
+    let devices: Vec<Value> = (0..count)
+        .map(|i| {
+            json!({
+                "id": i,
+                "name": format!("device-{}", i),
+                "mac": format!("00:11:22:33:{:02x}:{:02x}", i / 256 % 256, i % 256),
+                "ip": format!("10.0.{}.{}", i / 256 % 256, i % 256),
+                "status": i % 3 != 0,
+                "connectionType": "802.11ac",
+            })
+        })
+        .collect();
+    Value::from(devices).to_string()
+}
+
+// The G1100 keeps a sample per minute for the last day
+fn network_response(samples: usize) -> String {
+    let minutes: Vec<u64> = (0..samples as u64).map(|i| 1_000_000 + i * 977).collect();
+    json!({
+        "bandwidth": {"minutesRx": minutes, "minutesTx": minutes},
+        "rxErrors": 0,
+        "rxDropped": 12,
+    })
+    .to_string()
+}
+
+fn metrics_batch(count: usize) -> Vec<Metric> {
+    (0..count)
+        .map(|i| {
+            Metric::bytes("net_rx", i as u64 * 1000)
+                .tag("host", "myfiosgateway.com")
+                .tag("channel", &(i % 165).to_string())
+        })
+        .collect()
+}
+
+fn parsing(c: &mut Criterion) {
+    let devices = devices_response(1000);
+    c.bench_function("parse 1000 devices", |b| {
+        b.iter(|| {
+            let data: Value = serde_json::from_str(black_box(&devices)).unwrap();
+            g1100::parse_devices(&data).unwrap()
+        })
+    });
+
+    let network = network_response(24 * 60);
+    c.bench_function("parse a day of bandwidth samples", |b| {
+        b.iter(|| {
+            let data: Value = serde_json::from_str(black_box(&network)).unwrap();
+            g1100::parse_network_stats(&data).unwrap()
+        })
+    });
+}
+
+fn encoding(c: &mut Criterion) {
+    let batch = metrics_batch(10_000);
+    c.bench_function("line protocol for 10000 metrics", |b| {
+        b.iter(|| metrics::to_line_protocol(black_box(&batch)))
+    });
+}
+
+criterion_group!(benches, parsing, encoding);
+criterion_main!(benches);
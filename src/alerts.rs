@@ -0,0 +1,191 @@
+// Threshold alerts, evaluated on the collected data in daemon mode
+//
+// A rule fires once its condition has held for its whole duration, and resolves as soon as it no
+// longer holds. Both are logged, stored as an alert_firing data point, and sent as notifications
+// (see notify.rs). Rules whose metric wasn't collected in a cycle are left as they were.
+
+use crate::config::{AlertRule, Config};
+use crate::metrics::Metric;
+use crate::notify::{self, Event, Notifier};
+use crate::scheduler;
+use tracing::{error, info, warn};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Above,
+    AtLeast,
+    Below,
+    AtMost,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    fn parse(condition: &str) -> Option<Comparison> {
+        Some(match condition {
+            ">" => Comparison::Above,
+            ">=" => Comparison::AtLeast,
+            "<" => Comparison::Below,
+            "<=" => Comparison::AtMost,
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            _ => return None,
+        })
+    }
+
+    fn holds(&self, value: u64, threshold: u64) -> bool {
+        match self {
+            Comparison::Above => value > threshold,
+            Comparison::AtLeast => value >= threshold,
+            Comparison::Below => value < threshold,
+            Comparison::AtMost => value <= threshold,
+            Comparison::Equal => value == threshold,
+            Comparison::NotEqual => value != threshold,
+        }
+    }
+}
+
+struct Rule {
+    rule: AlertRule,
+    comparison: Comparison,
+    duration: Duration,
+    // When the condition started holding
+    since: Option<Instant>,
+    firing: bool,
+    // The previous reading, for per minute rules
+    last: Option<(Instant, u64)>,
+}
+
+pub struct Alerts {
+    rules: Vec<Rule>,
+    notifier: Notifier,
+}
+
+impl Alerts {
+    pub fn from_config(config: &Config) -> Result<Alerts, String> {
+        let mut rules = Vec::new();
+        for rule in &config.alerts {
+            let comparison = Comparison::parse(&rule.condition)
+                .ok_or_else(|| format!("Alert {}: unknown condition {}, use >, >=, <, <=, ==, or !=", rule.name, rule.condition))?;
+            let duration = match &rule.duration {
+                Some(duration) => scheduler::parse_duration(duration)
+                    .ok_or_else(|| format!("Alert {}: invalid duration {}", rule.name, duration))?,
+                None => Duration::from_secs(0),
+            };
+            rules.push(Rule {
+                rule: rule.clone(),
+                comparison,
+                duration,
+                since: None,
+                firing: false,
+                last: None,
+            });
+        }
+        Ok(Alerts {
+            rules,
+            notifier: Notifier::from_config(config)?,
+        })
+    }
+
+    // Updates the rules with a cycle's data, and returns the alert_firing data points for the rules
+    // that were evaluated
+    pub fn evaluate(&mut self, client: &reqwest::blocking::Client, data: &[Metric]) -> Vec<Metric> {
+        let now = Instant::now();
+        let mut states = Vec::new();
+        for rule in self.rules.iter_mut() {
+            // With several series, e.g. one per device, the one closest to crossing the threshold is what
+            // matters: the smallest for < and <=, and the largest otherwise
+            let values = data.iter().filter(|m| m.name == rule.rule.metric).map(|m| m.value);
+            let value = match rule.comparison {
+                Comparison::Below | Comparison::AtMost => values.min(),
+                _ => values.max(),
+            };
+            let value = match value {
+                Some(value) => value,
+                None => continue,
+            };
+            let value = if rule.rule.per_minute {
+                let previous = rule.last.replace((now, value));
+                match previous {
+                    Some((at, previous)) => {
+                        let millis = (now - at).as_millis().max(1);
+                        (u128::from(value.saturating_sub(previous)) * 60_000 / millis) as u64
+                    }
+                    None => continue,
+                }
+            } else {
+                value
+            };
+
+            if rule.comparison.holds(value, rule.rule.threshold) {
+                let since = *rule.since.get_or_insert(now);
+                if !rule.firing && now - since >= rule.duration {
+                    rule.firing = true;
+                    warn!("Alert {} firing: {} is {} (threshold {} {})",
+                          rule.rule.name, rule.rule.metric, value, rule.rule.condition, rule.rule.threshold);
+                    notify(&self.notifier, client, &rule.rule, "firing", value);
+                }
+            } else {
+                rule.since = None;
+                if rule.firing {
+                    rule.firing = false;
+                    info!("Alert {} resolved: {} is {}", rule.rule.name, rule.rule.metric, value);
+                    notify(&self.notifier, client, &rule.rule, "resolved", value);
+                }
+            }
+            states.push(Metric::new("alert_firing", u64::from(rule.firing)).tag("alert", &rule.rule.name));
+        }
+        states
+    }
+}
+
+// A failed notification is only logged, it shouldn't stop the collection
+fn notify(notifier: &Notifier, client: &reqwest::blocking::Client, rule: &AlertRule, state: &str, value: u64) {
+    let event = Event::new(
+        notify::ALERT,
+        &format!("Alert {} {}", rule.name, state),
+        &format!("{} is {}, the threshold is {} {}", rule.metric, value, rule.condition, rule.threshold),
+    )
+    .field("alert", rule.name.as_str())
+    .field("state", state)
+    .field("metric", rule.metric.as_str())
+    .field("value", value)
+    .field("condition", rule.condition.as_str())
+    .field("threshold", rule.threshold);
+    if let Err(e) = notifier.send_to(client, &event, &rule.notify) {
+        error!("Could not send alert {}: {}", rule.name, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alerts(condition: &str, threshold: u64) -> Alerts {
+        let rule = AlertRule {
+            name: "test".to_string(),
+            metric: "device_present".to_string(),
+            condition: condition.to_string(),
+            threshold,
+            duration: None,
+            per_minute: false,
+            notify: Vec::new(),
+        };
+        Alerts::from_config(&Config { alerts: vec![rule], ..Config::default() }).unwrap()
+    }
+
+    #[test]
+    fn several_series() {
+        let client = reqwest::blocking::Client::new();
+        let data = [
+            Metric::new("device_present", 1).tag("device", "laptop"),
+            Metric::new("device_present", 0).tag("device", "phone"),
+        ];
+        let firing = |alerts: &mut Alerts| alerts.evaluate(&client, &data)[0].value;
+        assert_eq!(firing(&mut alerts("<", 1)), 1);
+        assert_eq!(firing(&mut alerts("<=", 0)), 1);
+        assert_eq!(firing(&mut alerts(">", 0)), 1);
+        assert_eq!(firing(&mut alerts(">=", 2)), 0);
+    }
+}
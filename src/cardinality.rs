@@ -0,0 +1,53 @@
+// Keeping the number of per-device series bounded
+//
+// Every device with a MAC tag is a series of each per-device measurement, and with guests coming and
+// going and devices making up MACs that keeps growing, which InfluxDB and Prometheus don't take well.
+// With max_devices, the data of the devices past the limit goes to one made up device instead: their
+// data points are added up (the *_last_seen ones take the latest) and tagged mac = "other", without
+// the names. Which devices get series of their own is first come, first served since the start, so a
+// device's series don't come and go. The first time the limit is hit it's logged as a warning.
+
+use crate::metrics::Metric;
+use tracing::warn;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+pub const OTHER: &str = "other";
+
+// The tags naming a device, which "other" doesn't have
+const DEVICE_TAGS: [&str; 5] = ["mac", "device", "hostname", "vendor", "category"];
+
+// The MACs with series of their own, and whether the limit has been hit
+static ADMITTED: Mutex<(Option<HashSet<String>>, bool)> = Mutex::new((None, false));
+
+pub fn limit(data: &mut Vec<Metric>, max_devices: usize) {
+    let mut admitted = ADMITTED.lock().unwrap();
+    let (seen, warned) = &mut *admitted;
+    let seen = seen.get_or_insert_with(HashSet::new);
+
+    let mut others: Vec<Metric> = Vec::new();
+    data.retain(|metric| {
+        let mac = match metric.tags.iter().find(|(key, _)| key == "mac") {
+            Some((_, mac)) => mac.clone(),
+            None => return true,
+        };
+        if seen.contains(&mac) || (seen.len() < max_devices && seen.insert(mac)) {
+            return true;
+        }
+        let mut other = metric.clone();
+        other.tags.retain(|(key, _)| !DEVICE_TAGS.contains(&key.as_str()));
+        other.tags.push(("mac".to_string(), OTHER.to_string()));
+        match others.iter_mut().find(|o| o.name == other.name && o.tags == other.tags) {
+            Some(existing) if other.name.ends_with("_last_seen") => existing.value = existing.value.max(other.value),
+            Some(existing) => existing.value += other.value,
+            None => others.push(other),
+        }
+        false
+    });
+
+    if !others.is_empty() && !*warned {
+        warn!("More than max_devices = {} devices, putting the data of the others under mac = {}", max_devices, OTHER);
+        *warned = true;
+    }
+    data.extend(others);
+}
@@ -0,0 +1,500 @@
+// Command line definition
+//
+// The connection, password, and sink options are global, so they can be given both before and
+// after the subcommand. Without a subcommand, `collect` is run (or `daemon` if the config file says
+// so).
+
+use fios_stats::client::Address;
+use fios_stats::collectors::{self, bandwidth, Collector};
+use fios_stats::output::Format;
+use fios_stats::router::Model;
+use fios_stats::{grafana, ratelimit, scheduler, Config};
+use clap::{App, AppSettings, Arg, ArgMatches, Shell, SubCommand};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub fn app() -> App<'static, 'static> {
+    App::new("Fios Gateway Stats Retriever")
+        .version(VERSION)
+        .author("Allan Beaufour <allan@beaufour.dk>")
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(Arg::with_name("verbose")
+             .short("v")
+             .long("verbose")
+             .multiple(true)
+             .global(true)
+             .help("Log more, -v for debug output, -vv to include the HTTP libraries, -vvv for everything"))
+        .arg(Arg::with_name("quiet")
+             .short("q")
+             .long("quiet")
+             .global(true)
+             .conflicts_with("verbose")
+             .help("Only log errors"))
+        .arg(Arg::with_name("log_format")
+             .long("log-format")
+             .value_name("FORMAT")
+             .help("Log as human readable text, or as JSON for log collectors [default: text]")
+             .possible_values(&["text", "json"])
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("docker")
+             .long("docker")
+             .help("Take the settings from FIOS_* variables and /run/secrets, log JSON to stdout, and run the daemon with a health check")
+             .env("FIOS_DOCKER")
+             .global(true))
+        .arg(Arg::with_name("config")
+             .short("c")
+             .long("config")
+             .value_name("FILE")
+             .help("TOML or YAML configuration file. Command line flags override its values")
+             .env("FIOS_CONFIG")
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("profile")
+             .short("P")
+             .long("profile")
+             .value_name("NAME")
+             .help("Use the settings of this router profile from the config file")
+             .env("FIOS_PROFILE")
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("all")
+             .long("all")
+             .help("Run the command for every router profile in the config file. Collection runs in parallel")
+             .global(true)
+             .conflicts_with("profile"))
+        .arg(Arg::with_name("gateway")
+             .short("g")
+             .long("gateway")
+             .value_name("HOST[:PORT]")
+             .help("Address of the router [default: myfiosgateway.com]")
+             .env("FIOS_GATEWAY")
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("model")
+             .long("model")
+             .value_name("MODEL")
+             .help("Router model [default: detected]")
+             .possible_values(&Model::NAMES)
+             .case_insensitive(true)
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("resolve")
+             .long("resolve")
+             .value_name("ADDRESS")
+             .help("Connect to this IP address instead of looking up the gateway name, e.g. fe80::1%eth0")
+             .validator(|v| Address::parse(&v).map(|_| ()))
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("rate_limit")
+             .long("rate-limit")
+             .value_name("N")
+             .help("Send at most N requests per second to the router, e.g. 0.5")
+             .global(true)
+             .takes_value(true)
+             .validator(|v| ratelimit::RateLimiter::new(v.parse().map_err(|_| format!("Invalid rate limit: {}", v))?).map(|_| ())))
+        .arg(Arg::with_name("insecure")
+             .short("k")
+             .long("insecure")
+             .global(true)
+             .help("Don't verify the router's TLS certificate"))
+        .arg(Arg::with_name("ca_cert")
+             .long("ca-cert")
+             .value_name("FILE")
+             .help("PEM file with a CA certificate to trust, e.g. the router's own certificate")
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("pin_sha256")
+             .long("pin-sha256")
+             .value_name("FINGERPRINT")
+             .help("Only accept a router certificate with this SHA-256 fingerprint")
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("tofu")
+             .long("tofu")
+             .help("Trust the router certificate seen on first connect, and fail if it ever changes")
+             .global(true)
+             .conflicts_with_all(&["insecure", "pin_sha256"]))
+        .arg(Arg::with_name("state_dir")
+             .long("state-dir")
+             .value_name("DIR")
+             .help("Directory for the files kept between runs, e.g. the known router certificates")
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("password")
+             .short("p")
+             .long("password")
+             .value_name("PASSWORD")
+             .help("Password for router. Prompted for if no password source is given")
+             .env("FIOS_PASSWORD")
+             .hide_env_values(true)
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("password_file")
+             .long("password-file")
+             .value_name("FILE")
+             .help("Read the router password from a file")
+             .conflicts_with_all(&["password", "password_cmd"])
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("password_cmd")
+             .long("password-cmd")
+             .value_name("COMMAND")
+             .help("Command whose output is the router password, e.g. 'pass show fios'")
+             .conflicts_with("password")
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("username")
+             .long("username")
+             .value_name("NAME")
+             .help("Router user to log in as, for firmwares with several admin users")
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("influx_db")
+             .short("i")
+             .long("influxdb")
+             .value_name("URI")
+             .help("URI to InfluxDB including databasename")
+             .env("FIOS_INFLUX_URI")
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("remote_write")
+             .long("remote-write")
+             .value_name("URI")
+             .help("Prometheus remote_write endpoint to push the data to. Needs the remote-write feature")
+             .env("FIOS_REMOTE_WRITE_URI")
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("proxy")
+             .long("proxy")
+             .value_name("URI")
+             .help("Proxy for InfluxDB, the router is always connected to directly [default: $HTTPS_PROXY or $HTTP_PROXY]")
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("watch_wan_ip")
+             .long("watch-wan-ip")
+             .help("Report changes of the router's public IP address as a wan_ip_changed data point")
+             .global(true))
+        .arg(Arg::with_name("wan_ip_webhook")
+             .long("wan-ip-webhook")
+             .value_name("URI")
+             .help("Post changes of the router's public IP address here, as JSON. Implies --watch-wan-ip")
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("watch_firmware")
+             .long("watch-firmware")
+             .help("Report updates of the router's firmware as a firmware_changed data point")
+             .global(true))
+        .arg(Arg::with_name("dry_run")
+             .short("n")
+             .long("dry-run")
+             .global(true)
+             .help("Print what would be written to the sinks instead of writing it"))
+        .arg(Arg::with_name("format")
+             .short("f")
+             .long("format")
+             .value_name("FORMAT")
+             .help("Print the collected data on stdout, as a table, JSON, line protocol, or for collectd")
+             .possible_values(&Format::NAMES)
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("units")
+             .long("units")
+             .value_name("UNITS")
+             .help("Units for the traffic counters [default: bits]")
+             .possible_values(&["bits", "bytes"])
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("metrics")
+             .long("metrics")
+             .value_name("PATTERN")
+             .help("Only collect measurements matching these glob patterns, e.g. 'net_*'")
+             .use_delimiter(true)
+             .multiple(true)
+             .global(true)
+             .takes_value(true))
+        .arg(Arg::with_name("exclude_metrics")
+             .long("exclude-metrics")
+             .value_name("PATTERN")
+             .help("Don't collect measurements matching these glob patterns")
+             .use_delimiter(true)
+             .multiple(true)
+             .global(true)
+             .takes_value(true))
+        .subcommands(subcommands())
+}
+
+fn subcommands() -> Vec<App<'static, 'static>> {
+    vec![
+        SubCommand::with_name("collect")
+            .about("Collects data once and stores it (the default)")
+            .arg(Arg::with_name("collector")
+                 .long("collector")
+                 .value_name("NAME")
+                 .help("Collector to run [default: the collectors in the config, or bandwidth]")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1)
+                 .possible_values(collectors::names()))
+            .arg(Arg::with_name("sparkline")
+                 .long("sparkline")
+                 .help("Also show the traffic of the last hour as sparklines (G1100 only)")),
+        SubCommand::with_name("daemon")
+            .about("Keeps running, polling each collector on its own interval")
+            .arg(Arg::with_name("interval")
+                 .long("interval")
+                 .value_name("COLLECTOR=DURATION")
+                 .help("Polling interval for a collector, e.g. devices=5m. 0 disables it")
+                 .takes_value(true)
+                 .multiple(true)
+                 .number_of_values(1)
+                 .validator(|v| scheduler::parse_interval(&v).map(|_| ())))
+            .arg(Arg::with_name("dashboard")
+                 .long("dashboard")
+                 .value_name("ADDRESS")
+                 .help("Serve a web dashboard here, e.g. 127.0.0.1:8080. Needs the web feature")
+                 .takes_value(true))
+            .arg(Arg::with_name("health")
+                 .long("health")
+                 .value_name("ADDRESS")
+                 .help("Answer health checks here, e.g. 0.0.0.0:8080 [default in Docker mode: 0.0.0.0:8080]")
+                 .takes_value(true)),
+        SubCommand::with_name("watch")
+            .about("Shows the traffic, devices, and WAN status live in the terminal")
+            .arg(Arg::with_name("refresh")
+                 .long("refresh")
+                 .value_name("DURATION")
+                 .help("How often to read the router, e.g. 10s [default: the bandwidth interval]")
+                 .takes_value(true)
+                 .validator(|v| scheduler::parse_duration(&v).map(|_| ()).ok_or_else(|| format!("Invalid duration: {}", v)))),
+        SubCommand::with_name("list-collectors")
+            .about("Lists the collectors, what they collect, and which routers they work on"),
+        SubCommand::with_name("schema")
+            .about("Lists every measurement the tool writes, with its unit and tags"),
+        SubCommand::with_name("dump")
+            .about("Prints the raw response of a router API endpoint")
+            .arg(Arg::with_name("api")
+                 .value_name("API")
+                 .help("API path, e.g. network/1")
+                 .required(true)),
+        SubCommand::with_name("diagnostics")
+            .about("Shows the last ping and traceroute run on the router, G3100 and CR1000A only"),
+        SubCommand::with_name("check")
+            .about("Checks that the router and the sinks can be reached"),
+        SubCommand::with_name("selftest")
+            .about("Runs each collector once and reports whether it works, without storing anything"),
+        SubCommand::with_name("backup")
+            .about("Downloads a backup of the router configuration")
+            .arg(Arg::with_name("output")
+                 .short("o")
+                 .long("output")
+                 .value_name("FILE")
+                 .help("File to save the backup to")
+                 .required(true)
+                 .takes_value(true)),
+        SubCommand::with_name("reboot")
+            .about("Reboots the router")
+            .arg(Arg::with_name("yes")
+                 .long("yes")
+                 .help("Confirm that you really want to reboot the router")
+                 .required(true)),
+        SubCommand::with_name("init-influx")
+            .about("Creates the InfluxDB database given with -i"),
+        SubCommand::with_name("report")
+            .about("Prints a summary of the collected data from InfluxDB")
+            .arg(Arg::with_name("period")
+                 .long("period")
+                 .value_name("PERIOD")
+                 .help("How far back to go")
+                 .possible_values(&["day", "week"])
+                 .default_value("day"))
+            .arg(Arg::with_name("send")
+                 .long("send")
+                 .help("Also send the report to the notification channels with the report event, e.g. email")),
+        SubCommand::with_name("grafana-dashboard")
+            .about("Prints a Grafana dashboard for the collected data, to import in Grafana")
+            .arg(Arg::with_name("datasource")
+                 .long("datasource")
+                 .value_name("TYPE")
+                 .help("The kind of datasource the data is in")
+                 .possible_values(&grafana::DATASOURCES)
+                 .default_value("influx")),
+        SubCommand::with_name("update-oui")
+            .about("Downloads the list of MAC address vendors, for vendor_tags"),
+        SubCommand::with_name("login")
+            .about("Checks that the password works")
+            .arg(Arg::with_name("save")
+                 .long("save")
+                 .help("Save the password in the OS keyring, where later runs will find it")),
+        SubCommand::with_name("discover")
+            .about("Looks for routers on the local network")
+            .arg(Arg::with_name("save")
+                 .long("save")
+                 .help("Write the router found into the configuration file given with -c [default: fios-stats.toml]")),
+        SubCommand::with_name("setup")
+            .about("Asks for the router and InfluxDB details, and writes a configuration file"),
+        SubCommand::with_name("startup")
+            .about("Prints a launchd plist (macOS) or systemd unit (Linux) running the daemon at boot with these flags")
+            .arg(Arg::with_name("launchd")
+                 .long("launchd")
+                 .help("Print a launchd plist, the default on macOS"))
+            .arg(Arg::with_name("systemd")
+                 .long("systemd")
+                 .help("Print a systemd unit, the default elsewhere")
+                 .conflicts_with("launchd")),
+        SubCommand::with_name("service")
+            .about("Installs or removes the Windows service running the daemon with the config file given with -c")
+            .arg(Arg::with_name("action")
+                 .value_name("ACTION")
+                 .help("What to do, `run` is used by the service manager")
+                 .possible_values(&["install", "uninstall", "run"])
+                 .required(true)),
+        SubCommand::with_name("completions")
+            .about("Prints a shell completion script")
+            .arg(Arg::with_name("shell")
+             .value_name("SHELL")
+             .possible_values(&Shell::variants())
+             .required(true)),
+        SubCommand::with_name("man")
+            .about("Prints a man page"),
+    ]
+}
+
+// A man page made from the help texts, so it's always up to date with the flags
+pub fn man_page() -> String {
+    let mut page = format!(".TH FIOS-STATS 1 \"\" \"fios-stats {}\"\n", VERSION);
+    page.push_str(".SH NAME\nfios-stats \\- fetches network stats from the Fios Quantum Gateway router\n");
+    page.push_str(".SH OPTIONS\n");
+    page.push_str(&help_section(&mut app()));
+    for mut subcommand in subcommands() {
+        page.push_str(&format!(".SH COMMAND: {}\n", subcommand.get_name().to_uppercase()));
+        page.push_str(&help_section(&mut subcommand));
+    }
+    page
+}
+
+fn help_section(app: &mut App) -> String {
+    let mut help = Vec::new();
+    // Writing to a Vec can't fail
+    let _ = app.write_long_help(&mut help);
+    let mut section = String::from(".nf\n");
+    for line in String::from_utf8_lossy(&help).lines() {
+        let line = line.replace('\\', "\\e");
+        if line.starts_with('.') || line.starts_with('\'') {
+            section.push_str("\\&");
+        }
+        section.push_str(&line);
+        section.push('\n');
+    }
+    section.push_str(".fi\n");
+    section
+}
+
+// Overrides the config file values with the ones given on the command line
+pub fn apply(config: &mut Config, args: &ArgMatches) {
+    if let Some(gateway) = args.value_of("gateway") {
+        config.gateway = Some(gateway.to_string());
+    }
+    if let Some(model) = args.value_of("model") {
+        config.model = Some(model.to_string());
+    }
+    if let Some(resolve) = args.value_of("resolve") {
+        config.resolve = Some(resolve.to_string());
+    }
+    if let Some(rate_limit) = args.value_of("rate_limit").and_then(|v| v.parse().ok()) {
+        config.rate_limit = Some(rate_limit);
+    }
+    if args.is_present("insecure") {
+        config.insecure = true;
+    }
+    if let Some(ca_cert) = args.value_of("ca_cert") {
+        config.ca_cert = Some(ca_cert.to_string());
+    }
+    if let Some(pin) = args.value_of("pin_sha256") {
+        config.pin_sha256 = Some(pin.to_string());
+    }
+    if args.is_present("tofu") {
+        config.tofu = true;
+    }
+    if let Some(dir) = args.value_of("state_dir") {
+        config.state_dir = Some(dir.to_string());
+    }
+    // A password source on the command line replaces any from the config file
+    if let Some(password) = args.value_of("password") {
+        config.password = Some(password.to_string());
+    }
+    if let Some(path) = args.value_of("password_file") {
+        config.password = None;
+        config.password_file = Some(path.to_string());
+    }
+    if let Some(command) = args.value_of("password_cmd") {
+        config.password = None;
+        config.password_file = None;
+        config.password_cmd = Some(command.to_string());
+    }
+    if let Some(username) = args.value_of("username") {
+        config.username = Some(username.to_string());
+    }
+    if let Some(influx_db) = args.value_of("influx_db") {
+        config.influxdb = Some(influx_db.to_string());
+    }
+    if let Some(remote_write) = args.value_of("remote_write") {
+        config.remote_write = Some(remote_write.to_string());
+    }
+    if let Some(proxy) = args.value_of("proxy") {
+        config.proxy = Some(proxy.to_string());
+    }
+    if args.is_present("watch_wan_ip") {
+        config.watch_wan_ip = true;
+    }
+    if let Some(webhook) = args.value_of("wan_ip_webhook") {
+        config.wan_ip_webhook = Some(webhook.to_string());
+    }
+    if args.is_present("watch_firmware") {
+        config.watch_firmware = true;
+    }
+    if args.is_present("dry_run") {
+        config.dry_run = true;
+    }
+    if let Some(format) = args.value_of("format") {
+        config.format = Some(format.to_string());
+    }
+    if let Some(units) = args.value_of("units") {
+        config.units = Some(units.to_string());
+    }
+    if let Some(patterns) = args.values_of("metrics") {
+        config.metrics = patterns.map(String::from).collect();
+    }
+    if let Some(patterns) = args.values_of("exclude_metrics") {
+        config.exclude_metrics = patterns.map(String::from).collect();
+    }
+    if let Some(address) = args.value_of("dashboard") {
+        config.dashboard = Some(address.to_string());
+    }
+    if let Some(address) = args.value_of("health") {
+        config.health = Some(address.to_string());
+    }
+    for value in args.values_of("interval").into_iter().flatten() {
+        let mut parts = value.splitn(2, '=');
+        if let (Some(name), Some(interval)) = (parts.next(), parts.next()) {
+            config.intervals.insert(name.to_string(), interval.to_string());
+        }
+    }
+}
+
+// The collectors given with --collector, defaulting to the ones in the config and otherwise just bandwidth
+pub fn collectors(args: &ArgMatches, config: &Config) -> Result<Vec<&'static dyn Collector>, String> {
+    let collectors: Vec<&'static dyn Collector> = args
+        .values_of("collector")
+        .into_iter()
+        .flatten()
+        .filter_map(collectors::by_name)
+        .collect();
+    if !collectors.is_empty() {
+        Ok(collectors)
+    } else if !config.collectors.is_empty() {
+        config.enabled_collectors()
+    } else {
+        Ok(vec![&bandwidth::Bandwidth])
+    }
+}
@@ -0,0 +1,464 @@
+// The router API client
+//
+// Gateway holds where the router is and how to connect to it, and FiosClient is a logged in
+// connection to it. What the API looks like depends on the router model, see router/.
+
+use crate::config::Config;
+use crate::ratelimit::RateLimiter;
+use crate::router::auth::PasswordHash;
+use crate::router::{self, cr1000a, g1100, g3100, Model, RouterClient};
+use crate::scheduler;
+use crate::state;
+use crate::tofu;
+use crate::FetchError;
+use tracing::{info, warn};
+use sha2::{Digest, Sha256};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const DEFAULT_GATEWAY: &str = "myfiosgateway.com";
+
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+pub const HTTP_VERSIONS: [&str; 3] = ["auto", "1", "2"];
+
+// Where to find the router, and how to connect to it
+#[derive(Clone)]
+pub struct Gateway {
+    pub(crate) base_uri: reqwest::Url,
+    // Connect here instead of resolving the gateway name, while still using the name for TLS
+    resolve: Option<Address>,
+    pub(crate) insecure: bool,
+    ca_cert: Option<reqwest::Certificate>,
+    // Lowercase hex, without colons
+    pub(crate) pin_sha256: Option<String>,
+    // Known gateway certificates, when trusting on first use
+    tofu_file: Option<PathBuf>,
+    // Detected at login when not given
+    pub(crate) model: Option<Model>,
+    // The model's usual one when not given
+    password_hash: Option<PasswordHash>,
+    pub(crate) username: Option<String>,
+    // For the sinks, from --proxy or the environment
+    proxy: Option<reqwest::Url>,
+    // Shared by the clones, so it covers every session with the router
+    limiter: Option<Arc<RateLimiter>>,
+    // "auto", "1", or "2"
+    http_version: String,
+    // None turns them off
+    tcp_keepalive: Option<Duration>,
+    pool_idle_timeout: Option<Duration>,
+}
+
+impl Gateway {
+    pub fn from_config(config: &Config) -> Result<Gateway, FetchError> {
+        let (uri, zoned) = gateway_uri(config.gateway())?;
+        let resolve = match &config.resolve {
+            Some(addr) => match Address::parse(addr) {
+                Ok(addr) => Some(addr),
+                Err(e) => bail!("Invalid resolve address: {}", e),
+            },
+            None => zoned,
+        };
+        let ca_cert = match &config.ca_cert {
+            Some(path) => {
+                let pem = match std::fs::read(path) {
+                    Ok(pem) => pem,
+                    Err(e) => bail!("Could not read CA certificate {}: {}", path, e),
+                };
+                Some(reqwest::Certificate::from_pem(&pem)?)
+            }
+            None => None,
+        };
+        let model = match &config.model {
+            Some(name) => match Model::from_name(name) {
+                Some(model) => Some(model),
+                None => bail!("Unknown router model {}, supported ones are: {:?}", name, Model::NAMES),
+            },
+            None => None,
+        };
+        let password_hash = match &config.password_hash {
+            Some(name) => match PasswordHash::from_name(name) {
+                Some(hash) => Some(hash),
+                None => bail!("Unknown password_hash {}, use one of {:?}", name, PasswordHash::NAMES),
+            },
+            None => None,
+        };
+        let proxy = match &config.proxy {
+            Some(proxy) => match reqwest::Url::parse(proxy) {
+                Ok(proxy) => Some(proxy),
+                Err(e) => bail!("Invalid proxy {}: {}", proxy, e),
+            },
+            None => None,
+        };
+        let http_version = config.http_version.clone().unwrap_or_else(|| "auto".to_string());
+        if !HTTP_VERSIONS.contains(&http_version.as_str()) {
+            bail!("Unknown http_version {}, use one of {:?}", http_version, HTTP_VERSIONS);
+        }
+        // A duration of 0 turns it off
+        let duration = |name: &str, value: &Option<String>, default: Duration| match value {
+            Some(value) => match scheduler::parse_duration(value) {
+                Some(duration) if duration.as_secs() == 0 => Ok(None),
+                Some(duration) => Ok(Some(duration)),
+                None => Err(simple_error::SimpleError::new(format!("Invalid {}: {}", name, value))),
+            },
+            None => Ok(Some(default)),
+        };
+        let tcp_keepalive = duration("tcp_keepalive", &config.tcp_keepalive, TCP_KEEPALIVE)?;
+        let pool_idle_timeout = duration("pool_idle_timeout", &config.pool_idle_timeout, POOL_IDLE_TIMEOUT)?;
+        let limiter = match config.rate_limit {
+            Some(per_second) => Some(Arc::new(RateLimiter::new(per_second).map_err(simple_error::SimpleError::new)?)),
+            None => None,
+        };
+        Ok(Gateway {
+            base_uri: reqwest::Url::parse(&uri)?,
+            resolve,
+            insecure: config.insecure,
+            ca_cert,
+            pin_sha256: config.pin_sha256.as_ref().map(|pin| pin.replace(':', "").to_lowercase()),
+            tofu_file: if config.tofu {
+                Some(config.tofu_file.as_ref().map(PathBuf::from).unwrap_or_else(|| state::file(config, "known-gateways")))
+            } else {
+                None
+            },
+            model,
+            password_hash,
+            username: config.username.clone(),
+            proxy,
+            limiter,
+            http_version,
+            tcp_keepalive,
+            pool_idle_timeout,
+        })
+    }
+
+    // A page of the newer routers, e.g. loginStatus.cgi, which are next to the G1100's api/ rather than
+    // in it. Joined relative to the gateway, so a path in it, e.g. behind a reverse proxy, is kept.
+    pub(crate) fn root_uri(&self, path: &str) -> Result<reqwest::Url, FetchError> {
+        Ok(self.base_uri.join("..")?.join(path.trim_start_matches('/'))?)
+    }
+
+    pub fn client_builder(&self) -> reqwest::blocking::ClientBuilder {
+        // The routers use a self-signed certificate, so pinning means skipping the normal checks and
+        // instead comparing the fingerprint of the certificate we got (see check_certificate())
+        let mut builder = reqwest::blocking::Client::builder()
+            .danger_accept_invalid_certs(self.insecure || self.pin_sha256.is_some() || self.tofu_file.is_some())
+            .tls_info(true)
+            // The routers keep the session in a cookie
+            .cookie_store(true)
+            // Device lists are big, and often come over WiFi to a Pi
+            .gzip(true)
+            // The TLS handshake with the router is slow, so connections are kept open between the
+            // daemon's polls, and reused for every request in between. Some firmwares drop idle
+            // connections sooner, which the keep-alive probes help with.
+            .tcp_keepalive(self.tcp_keepalive);
+        builder = match self.pool_idle_timeout {
+            Some(timeout) => builder.pool_idle_timeout(timeout),
+            None => builder.pool_max_idle_per_host(0),
+        };
+        match self.http_version.as_str() {
+            "1" => builder = builder.http1_only(),
+            "2" => builder = builder.http2_prior_knowledge(),
+            _ => (),
+        }
+        if let Some(cert) = &self.ca_cert {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+        #[cfg(feature = "rustls")]
+        {
+            builder = builder.use_rustls_tls();
+        }
+        if let Some(addr) = self.resolve {
+            if let (Some(host), Some(port)) = (self.base_uri.host_str(), self.base_uri.port_or_known_default()) {
+                builder = builder.resolve(host, addr.socket_addr(port));
+            }
+        }
+        builder.proxy(self.sink_proxy())
+    }
+
+    // The router is on the local network, so only the sinks go through a proxy. Like curl, it's
+    // taken from HTTPS_PROXY, HTTP_PROXY, or ALL_PROXY when not given, and NO_PROXY is honored.
+    fn sink_proxy(&self) -> reqwest::Proxy {
+        let router = self.base_uri.host_str().map(String::from);
+        let explicit = self.proxy.clone();
+        reqwest::Proxy::custom(move |uri| {
+            if uri.host_str().map(String::from) == router {
+                return None;
+            }
+            match &explicit {
+                Some(proxy) => Some(proxy.clone()),
+                None => proxy_from_env(uri.scheme()),
+            }
+        })
+        .no_proxy(reqwest::NoProxy::from_env())
+    }
+
+    // Pins the certificate recorded for this gateway, or records the current one if it's new
+    pub fn trust_on_first_use(&mut self, client: &reqwest::blocking::Client) -> Result<(), FetchError> {
+        let path = match &self.tofu_file {
+            Some(path) => path.clone(),
+            None => return Ok(()),
+        };
+        let host = format!("{}:{}",
+                           self.base_uri.host_str().unwrap_or_default(),
+                           self.base_uri.port_or_known_default().unwrap_or_default());
+        let mut known = tofu::load(&path).map_err(simple_error::SimpleError::new)?;
+        if let Some(fingerprint) = known.get(&host) {
+            self.pin_sha256 = Some(fingerprint.clone());
+            return Ok(());
+        }
+
+        self.throttle();
+        let response = client.get(self.base_uri.join("login")?).send()?;
+        let fingerprint = match certificate_fingerprint(&response) {
+            Some(fingerprint) => fingerprint,
+            None => bail!("Could not get the router certificate for {}", response.url()),
+        };
+        // Reading the body lets the connection go back to the pool
+        response.text()?;
+        warn!("First connection to {}, trusting certificate with SHA-256 fingerprint {}", host, fingerprint);
+        known.insert(host, fingerprint.clone());
+        tofu::save(&path, &known).map_err(simple_error::SimpleError::new)?;
+        self.pin_sha256 = Some(fingerprint);
+        Ok(())
+    }
+
+    // How to hash the password for logging in
+    pub(crate) fn password_hash(&self) -> PasswordHash {
+        self.password_hash.unwrap_or(PasswordHash::Sha512)
+    }
+
+    // Waits for the rate limit, if any, before a request to the router
+    pub(crate) fn throttle(&self) {
+        if let Some(limiter) = &self.limiter {
+            limiter.wait();
+        }
+    }
+
+    pub(crate) fn check_certificate(&self, response: &reqwest::blocking::Response) -> Result<(), FetchError> {
+        if let Some(pin) = &self.pin_sha256 {
+            match certificate_fingerprint(response) {
+                Some(ref fingerprint) if fingerprint == pin => (),
+                Some(fingerprint) => bail!("Router certificate fingerprint {} does not match the pinned {}! \
+                                            If the router was replaced or reset, remove it from the known gateways file",
+                                           fingerprint, pin),
+                None => bail!("Could not get the router certificate for {}", response.url()),
+            }
+        }
+        Ok(())
+    }
+}
+
+// An IP address to connect to, with the zone (interface) for link-local IPv6 addresses, e.g.
+// fe80::1%eth0
+#[derive(Debug, Clone, Copy)]
+pub struct Address {
+    ip: IpAddr,
+    scope_id: u32,
+}
+
+impl Address {
+    pub fn parse(address: &str) -> Result<Address, String> {
+        let unbracketed = address.trim_start_matches('[').trim_end_matches(']');
+        let (ip, zone) = match unbracketed.split_once('%') {
+            Some((ip, zone)) => (ip, Some(zone)),
+            None => (unbracketed, None),
+        };
+        let ip: IpAddr = ip.parse().map_err(|e| format!("{}: {}", address, e))?;
+        let scope_id = match zone {
+            None => 0,
+            Some(_) if ip.is_ipv4() => return Err(format!("{}: only IPv6 addresses have a zone", address)),
+            Some(zone) => zone_index(zone)?,
+        };
+        Ok(Address { ip, scope_id })
+    }
+
+    fn socket_addr(&self, port: u16) -> SocketAddr {
+        match self.ip {
+            IpAddr::V6(ip) => SocketAddr::V6(SocketAddrV6::new(ip, port, 0, self.scope_id)),
+            IpAddr::V4(_) => SocketAddr::new(self.ip, port),
+        }
+    }
+}
+
+// The zone can be the interface's number, or on Linux its name
+fn zone_index(zone: &str) -> Result<u32, String> {
+    if let Ok(index) = zone.parse() {
+        return Ok(index);
+    }
+    match std::fs::read_to_string(format!("/sys/class/net/{}/ifindex", zone)) {
+        Ok(index) => index.trim().parse().map_err(|e| format!("Invalid index for interface {}: {}", zone, e)),
+        Err(_) => Err(format!("Unknown network interface {}, try giving its number instead", zone)),
+    }
+}
+
+// The gateway is given as "host", "host:port", or a full URI, where the host can also be an IPv6
+// address, with or without brackets. URIs can't hold the zone of a link-local address, so then the
+// URI gets the router's usual name, and the address to connect to instead is returned too.
+fn gateway_uri(gateway: &str) -> Result<(String, Option<Address>), FetchError> {
+    let (scheme, rest) = match gateway.split_once("://") {
+        Some((scheme, rest)) => (scheme, rest),
+        None => ("https", gateway),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], rest[i..].trim_end_matches('/')),
+        None => (rest, ""),
+    };
+    let (host, port) = if let Some(bracketed) = authority.strip_prefix('[') {
+        match bracketed.split_once(']') {
+            Some(parts) => parts,
+            None => bail!("Missing ] in the gateway address {}", gateway),
+        }
+    } else if authority.matches(':').count() > 1 {
+        // A bare IPv6 address, which can't have a port
+        (authority, "")
+    } else {
+        match authority.find(':') {
+            Some(i) => (&authority[..i], &authority[i..]),
+            None => (authority, ""),
+        }
+    };
+
+    let host = host.replace("%25", "%");
+    let (host, zoned) = if host.contains('%') {
+        match Address::parse(&host) {
+            Ok(address) => (DEFAULT_GATEWAY.to_string(), Some(address)),
+            Err(e) => bail!("Invalid gateway address {}", e),
+        }
+    } else if host.parse::<Ipv6Addr>().is_ok() {
+        (format!("[{}]", host), None)
+    } else {
+        (host, None)
+    };
+    Ok((format!("{}://{}{}{}/api/", scheme, host, port, path), zoned))
+}
+
+fn proxy_from_env(scheme: &str) -> Option<reqwest::Url> {
+    let name = format!("{}_proxy", scheme);
+    [name.to_uppercase(), name, "ALL_PROXY".to_string(), "all_proxy".to_string()]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| reqwest::Url::parse(&value).ok())
+}
+
+pub(crate) fn certificate_fingerprint(response: &reqwest::blocking::Response) -> Option<String> {
+    response.extensions()
+        .get::<reqwest::tls::TlsInfo>()
+        .and_then(|info| info.peer_certificate())
+        .map(|der| format!("{:x}", Sha256::digest(der)))
+}
+
+// An authenticated connection to the router, the way into its API. The methods come from the
+// RouterClient trait of the router's backend
+pub struct FiosClient {
+    router: Box<dyn RouterClient>,
+    model: Model,
+}
+
+impl FiosClient {
+    // Connects to the router described by the config, and logs in
+    pub fn login(config: &Config, password: &str) -> Result<FiosClient, FetchError> {
+        let mut gateway = Gateway::from_config(config)?;
+        let client = gateway.client_builder().build()?;
+        gateway.trust_on_first_use(&client)?;
+        FiosClient::login_with(&client, &gateway, password)
+    }
+
+    // Like login(), but with an existing client, which the session then uses. It must be made with
+    // Gateway::client_builder(), which turns on the cookie store the session lives in
+    pub fn login_with(client: &reqwest::blocking::Client, gateway: &Gateway, password: &str) -> Result<FiosClient, FetchError> {
+        let model = match gateway.model {
+            Some(model) => model,
+            None => {
+                let model = router::detect(client, gateway)?;
+                info!("Detected a {} router, use --model to override", model.name());
+                model
+            }
+        };
+        let router: Box<dyn RouterClient> = match model {
+            Model::G1100 => Box::new(g1100::G1100::login(client, gateway, password)?),
+            Model::G3100 => Box::new(g3100::G3100::login(client, gateway, password)?),
+            Model::Cr1000a => Box::new(cr1000a::Cr1000a::login(client, gateway, password)?),
+        };
+        Ok(FiosClient { router, model })
+    }
+
+    // The router's model, as configured or detected
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn logout(self) -> Result<(), FetchError> {
+        self.router.logout()
+    }
+}
+
+impl Deref for FiosClient {
+    type Target = dyn RouterClient;
+
+    fn deref(&self) -> &Self::Target {
+        self.router.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uri(gateway: &str) -> String {
+        gateway_uri(gateway).unwrap().0
+    }
+
+    #[test]
+    fn gateway_uris() {
+        assert_eq!(uri("myfiosgateway.com"), "https://myfiosgateway.com/api/");
+        assert_eq!(uri("192.168.1.1:8443"), "https://192.168.1.1:8443/api/");
+        assert_eq!(uri("http://192.168.1.1"), "http://192.168.1.1/api/");
+        assert_eq!(uri("https://router.lan:8443/fios/"), "https://router.lan:8443/fios/api/");
+        assert_eq!(uri("fd00::1"), "https://[fd00::1]/api/");
+        assert_eq!(uri("[fd00::1]:8443"), "https://[fd00::1]:8443/api/");
+        assert_eq!(uri("http://[fd00::1]"), "http://[fd00::1]/api/");
+        assert!(gateway_uri("[fd00::1").is_err());
+    }
+
+    #[test]
+    fn root_uris() {
+        let gateway = Gateway::from_config(&Config {
+            gateway: Some("https://router.lan:8443/fios/".to_string()),
+            ..Config::default()
+        })
+        .unwrap();
+        assert_eq!(gateway.root_uri("loginStatus.cgi").unwrap().as_str(), "https://router.lan:8443/fios/loginStatus.cgi");
+        assert_eq!(gateway.root_uri("/login.cgi").unwrap().as_str(), "https://router.lan:8443/fios/login.cgi");
+        let gateway = Gateway::from_config(&Config::default()).unwrap();
+        assert_eq!(gateway.root_uri("loginStatus.cgi").unwrap().as_str(), format!("https://{}/loginStatus.cgi", DEFAULT_GATEWAY));
+    }
+
+    #[test]
+    fn zoned_gateway_connects_to_the_address() {
+        let (uri, address) = gateway_uri("[fe80::1%251]:8443").unwrap();
+        assert_eq!(uri, format!("https://{}:8443/api/", DEFAULT_GATEWAY));
+        let address = address.unwrap();
+        assert_eq!(address.socket_addr(8443), "[fe80::1%1]:8443".parse::<SocketAddr>().unwrap());
+
+        let (_, address) = gateway_uri("fe80::1%2").unwrap();
+        assert_eq!(address.unwrap().scope_id, 2);
+    }
+
+    #[test]
+    fn addresses() {
+        let address = Address::parse("192.168.1.1").unwrap();
+        assert_eq!(address.socket_addr(443), "192.168.1.1:443".parse::<SocketAddr>().unwrap());
+        let address = Address::parse("[fd00::1]").unwrap();
+        assert_eq!(address.socket_addr(443), "[fd00::1]:443".parse::<SocketAddr>().unwrap());
+        assert_eq!(Address::parse("fe80::1%3").unwrap().scope_id, 3);
+        assert!(Address::parse("192.168.1.1%3").is_err());
+        assert!(Address::parse("fe80::1%no-such-interface").is_err());
+        assert!(Address::parse("router.lan").is_err());
+    }
+}
@@ -0,0 +1,51 @@
+// Traffic and errors on the WAN interface
+
+use super::{Collector, MetricBatch};
+use crate::config::Config;
+use crate::metrics::{Measurement, Metric};
+use crate::{FetchError, FiosClient};
+use std::time::Duration;
+
+pub const NAME: &str = "bandwidth";
+
+static MEASUREMENTS: [Measurement; 4] = [
+    Measurement::bytes("net_rx", &[], "Received in the last minute"),
+    Measurement::bytes("net_tx", &[], "Sent in the last minute"),
+    Measurement::counter("net_rx_errors", "count", &[], "Receive errors since the router started"),
+    Measurement::counter("net_rx_dropped", "count", &[], "Dropped packets since the router started"),
+];
+
+pub struct Bandwidth;
+
+impl Collector for Bandwidth {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "Traffic and errors on the WAN interface"
+    }
+
+    fn support(&self) -> &'static str {
+        "All models. /api/network/1 on the G1100, the WAN Device.IP.Interface stats on the G3100 and CR1000A"
+    }
+
+    fn default_interval(&self) -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn measurements(&self) -> &'static [Measurement] {
+        &MEASUREMENTS
+    }
+
+    fn collect(&self, session: &FiosClient, _config: &Config) -> Result<MetricBatch, FetchError> {
+        let stats = session.network_stats()?;
+
+        // TODO: there's also natEntriesUsed from /api/settings/system, which might be interesting to pull
+
+        let mut data = vec![Metric::bytes("net_rx", stats.rx_bytes), Metric::bytes("net_tx", stats.tx_bytes)];
+        data.extend(stats.rx_errors.map(|n| Metric::new("net_rx_errors", n)));
+        data.extend(stats.rx_dropped.map(|n| Metric::new("net_rx_dropped", n)));
+        Ok(data)
+    }
+}
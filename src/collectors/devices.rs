@@ -0,0 +1,51 @@
+// How many devices the router knows about, and how many of them are connected
+
+use super::{Collector, MetricBatch};
+use crate::config::Config;
+use crate::metrics::{Measurement, Metric};
+use crate::{FetchError, FiosClient};
+use std::time::Duration;
+
+pub const NAME: &str = "devices";
+
+static MEASUREMENTS: [Measurement; 2] = [
+    Measurement::new("devices_known", "count", &[], "Devices the router knows about"),
+    Measurement::new("devices_connected", "count", &[], "Devices connected right now"),
+];
+
+pub struct Devices;
+
+impl Collector for Devices {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "How many devices the router knows about, and how many are connected"
+    }
+
+    fn support(&self) -> &'static str {
+        "All models. /api/devices on the G1100, Device.Hosts.Host on the G3100 and CR1000A"
+    }
+
+    fn default_interval(&self) -> Duration {
+        Duration::from_secs(5 * 60)
+    }
+
+    fn measurements(&self) -> &'static [Measurement] {
+        &MEASUREMENTS
+    }
+
+    // TODO: a `top` command listing the devices using the most traffic needs per device byte counters,
+    // which none of the backends read yet. On the TR-181 routers they might be in
+    // Device.WiFi.AccessPoint.{i}.AssociatedDevice.{i}.Stats, for WiFi devices only.
+    fn collect(&self, session: &FiosClient, _config: &Config) -> Result<MetricBatch, FetchError> {
+        let devices = session.devices()?;
+        let connected = devices.iter().filter(|d| d.connected).count();
+
+        Ok(vec![
+            Metric::new("devices_known", devices.len() as u64),
+            Metric::new("devices_connected", connected as u64),
+        ])
+    }
+}
@@ -0,0 +1,129 @@
+// How long the router's DNS takes to answer, to tell whether "the internet is slow" is down to DNS
+
+use super::{Collector, MetricBatch};
+use crate::client::Gateway;
+use crate::config::Config;
+use crate::metrics::{Measurement, Metric};
+use crate::{FetchError, FiosClient};
+use tracing::{debug, warn};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const NAME: &str = "dns_probe";
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+static MEASUREMENTS: [Measurement; 2] = [
+    Measurement::new("dns_latency_ms", "ms", &["server"], "How long the lookup took"),
+    Measurement::new("dns_probe_success", "0/1", &["server"], "Whether the lookup got an answer"),
+];
+
+pub struct DnsProbe;
+
+impl Collector for DnsProbe {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "How long looking up the dns_probe name through the router's DNS takes"
+    }
+
+    fn support(&self) -> &'static str {
+        "All models. A DNS query from this host to the router, or to dns_server"
+    }
+
+    fn default_interval(&self) -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn measurements(&self) -> &'static [Measurement] {
+        &MEASUREMENTS
+    }
+
+    // Only when there's a name to look up
+    fn configured(&self, config: &Config) -> bool {
+        config.dns_probe.is_some()
+    }
+
+    // A lookup that fails or times out is data too, dns_probe_success = 0, rather than the collector
+    // failing. Not finding the server is a failure though.
+    fn collect(&self, _session: &FiosClient, config: &Config) -> Result<MetricBatch, FetchError> {
+        let name = match &config.dns_probe {
+            Some(name) => name,
+            None => return Ok(Vec::new()),
+        };
+        let server = server(config)?;
+        let mut data = Vec::new();
+        match lookup(server, name) {
+            Ok(latency) => {
+                debug!("Looked up {} at {} in {}ms", name, server, latency.as_millis());
+                data.push(Metric::new("dns_latency_ms", latency.as_millis() as u64).tag("server", &server.ip().to_string()));
+                data.push(Metric::new("dns_probe_success", 1).tag("server", &server.ip().to_string()));
+            }
+            Err(e) => {
+                warn!("Could not look up {} at {}: {}", name, server, e);
+                data.push(Metric::new("dns_probe_success", 0).tag("server", &server.ip().to_string()));
+            }
+        }
+        Ok(data)
+    }
+}
+
+// dns_server, which can have a port, or else the router
+fn server(config: &Config) -> Result<SocketAddr, FetchError> {
+    let host = match &config.dns_server {
+        Some(server) => match server.parse::<SocketAddr>() {
+            Ok(address) => return Ok(address),
+            Err(_) => server.clone(),
+        },
+        None => {
+            let gateway = Gateway::from_config(config)?;
+            gateway.base_uri.host_str().unwrap_or_default().trim_matches(|c| c == '[' || c == ']').to_string()
+        }
+    };
+    match (host.as_str(), 53).to_socket_addrs().ok().and_then(|mut addresses| addresses.next()) {
+        Some(address) => Ok(address),
+        None => bail!("Could not find the address of the DNS server {}", host),
+    }
+}
+
+// Sends an A query and waits for the answer to it, which counts whatever it says, e.g. NXDOMAIN
+// is still the server answering
+fn lookup(server: SocketAddr, name: &str) -> Result<Duration, String> {
+    let id = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos() as u16).unwrap_or(1);
+    let query = query(id, name)?;
+    let bind = if server.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+    let socket = UdpSocket::bind(bind).map_err(|e| e.to_string())?;
+    socket.set_read_timeout(Some(TIMEOUT)).map_err(|e| e.to_string())?;
+
+    let started = Instant::now();
+    socket.send_to(&query, server).map_err(|e| e.to_string())?;
+    let mut buffer = [0u8; 512];
+    loop {
+        let (length, from) = socket.recv_from(&mut buffer).map_err(|e| e.to_string())?;
+        // Only the answer to this query, from the server
+        if from == server && length >= 12 && buffer[0..2] == id.to_be_bytes() && buffer[2] & 0x80 != 0 {
+            return Ok(started.elapsed());
+        }
+        if started.elapsed() > TIMEOUT {
+            return Err("no answer".to_string());
+        }
+    }
+}
+
+// A standard query with recursion desired, for one A record
+fn query(id: u16, name: &str) -> Result<Vec<u8>, String> {
+    let mut packet = Vec::with_capacity(12 + name.len() + 6);
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("Invalid name to look up: {}", name));
+        }
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.extend_from_slice(&[0, 0, 1, 0, 1]);
+    Ok(packet)
+}
@@ -0,0 +1,107 @@
+// Whether the router's Ethernet and MoCA interfaces are up, and when they go up or down
+//
+// The last state of each interface is kept in `interfaces` in the state directory, so changes are
+// noticed across one-off runs as well as in daemon mode. A change is written as interface_state_change
+// tagged with the new state, with how long the interface had been in the old one as the value. Where
+// the router says when an interface last changed, going down and back up between two polls shows up
+// too, as state = "flap", rather than going unnoticed.
+
+use super::{Collector, MetricBatch};
+use crate::config::Config;
+use crate::metrics::{Measurement, Metric};
+use crate::router::{Interface, Model};
+use crate::state;
+use crate::{FetchError, FiosClient};
+use tracing::{info, warn};
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const NAME: &str = "interfaces";
+
+static MEASUREMENTS: [Measurement; 2] = [
+    Measurement::new("interface_up", "0/1", &["interface", "kind"], "Whether the link is up"),
+    Measurement::new("interface_state_change", "seconds", &["interface", "kind", "state"], "How long the link was in the state it left"),
+];
+
+pub struct Interfaces;
+
+impl Collector for Interfaces {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "Whether the WAN, Ethernet, and MoCA interfaces are up, and when that changes"
+    }
+
+    fn support(&self) -> &'static str {
+        "G3100 and CR1000A, Device.Ethernet.Interface and Device.MoCA.Interface. Not the G1100"
+    }
+
+    fn default_interval(&self) -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn measurements(&self) -> &'static [Measurement] {
+        &MEASUREMENTS
+    }
+
+    // The G1100 doesn't have the status
+    fn supports(&self, model: Model) -> bool {
+        model != Model::G1100
+    }
+
+    fn collect(&self, session: &FiosClient, config: &Config) -> Result<MetricBatch, FetchError> {
+        let interfaces = session.interfaces()?;
+        let path = state::file(config, "interfaces");
+        let previous = state::load_values(&path).map_err(simple_error::SimpleError::new)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let (data, current) = changes(config.gateway(), &previous, &interfaces, now);
+        state::save_values(&path, &current).map_err(simple_error::SimpleError::new)?;
+        Ok(data)
+    }
+}
+
+// Compares the interfaces with their last states, "gateway/interface" -> "state since checked" with
+// Unix times, returning the data and the new states
+pub fn changes(
+    gateway: &str,
+    previous: &BTreeMap<String, String>,
+    interfaces: &[Interface],
+    now: u64,
+) -> (Vec<Metric>, BTreeMap<String, String>) {
+    let mut states = previous.clone();
+    let mut data = Vec::new();
+    for interface in interfaces {
+        let state = if interface.up { "up" } else { "down" };
+        let tag = |metric: Metric| metric.tag("interface", &interface.name).tag("kind", &interface.kind);
+        data.push(tag(Metric::new("interface_up", u64::from(interface.up))));
+
+        let key = format!("{}/{}", gateway, interface.name.replace(char::is_whitespace, "_"));
+        let changed = interface.last_change.map(|seconds| now.saturating_sub(seconds));
+        let mut since = changed.unwrap_or(now);
+        match previous.get(&key).and_then(|value| parse(value)) {
+            Some((old, old_since, _)) if old != state => {
+                let lasted = since.saturating_sub(old_since);
+                info!("Interface {} went {} after {}s {}", interface.name, state, lasted, old);
+                data.push(tag(Metric::new("interface_state_change", lasted)).tag("state", state));
+            }
+            // The same state, but it changed since the last poll, so it went the other way and back
+            Some((_, old_since, checked)) => match changed {
+                Some(changed) if changed > checked => {
+                    warn!("Interface {} flapped, it's been {} for {}s", interface.name, state, now - changed);
+                    data.push(tag(Metric::new("interface_state_change", changed.saturating_sub(old_since))).tag("state", "flap"));
+                }
+                _ => since = old_since,
+            },
+            None => (),
+        }
+        states.insert(key, format!("{} {} {}", state, since, now));
+    }
+    (data, states)
+}
+
+fn parse(value: &str) -> Option<(&str, u64, u64)> {
+    let mut parts = value.split_whitespace();
+    Some((parts.next()?, parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
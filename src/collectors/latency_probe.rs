@@ -0,0 +1,111 @@
+// Latency and packet loss from this host to the internet, next to the router's numbers
+//
+// ICMP pings need raw sockets, i.e. root or extra capabilities, so this times TCP connections
+// instead: the handshake takes one round trip. A connection that isn't made within the timeout counts
+// as lost. A target that refuses connections still answers, so that counts as a round trip too.
+
+use super::{Collector, MetricBatch};
+use crate::config::Config;
+use crate::metrics::{Measurement, Metric};
+use crate::{FetchError, FiosClient};
+use tracing::debug;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const NAME: &str = "latency_probe";
+
+const DEFAULT_PORT: u16 = 443;
+const DEFAULT_COUNT: u32 = 5;
+const TIMEOUT: Duration = Duration::from_secs(2);
+// Between the attempts, so they don't all hit the same hiccup
+const SPACING: Duration = Duration::from_millis(200);
+
+static MEASUREMENTS: [Measurement; 3] = [
+    Measurement::new("probe_latency_ms", "ms", &["target"], "Mean time to connect"),
+    Measurement::new("probe_latency_max_ms", "ms", &["target"], "Longest time to connect"),
+    Measurement::new("probe_loss_percent", "percent", &["target"], "Connections that failed"),
+];
+
+pub struct LatencyProbe;
+
+impl Collector for LatencyProbe {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "Round trip time and loss from this host to probe_target, over TCP"
+    }
+
+    fn support(&self) -> &'static str {
+        "All models. TCP connections from this host, the router isn't asked"
+    }
+
+    fn default_interval(&self) -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn measurements(&self) -> &'static [Measurement] {
+        &MEASUREMENTS
+    }
+
+    // Only when there's a target
+    fn configured(&self, config: &Config) -> bool {
+        config.probe_target.is_some()
+    }
+
+    fn collect(&self, _session: &FiosClient, config: &Config) -> Result<MetricBatch, FetchError> {
+        let target = match &config.probe_target {
+            Some(target) => target,
+            None => return Ok(Vec::new()),
+        };
+        let address = address(target)?;
+        let count = config.probe_count.unwrap_or(DEFAULT_COUNT).max(1);
+
+        let mut round_trips = Vec::new();
+        for attempt in 0..count {
+            if attempt > 0 {
+                thread::sleep(SPACING);
+            }
+            if let Some(round_trip) = connect(address) {
+                round_trips.push(round_trip);
+            }
+        }
+        debug!("{} of {} connections to {} made: {:?}", round_trips.len(), count, address, round_trips);
+
+        let lost = count - round_trips.len() as u32;
+        let mut data = vec![Metric::new("probe_loss_percent", u64::from(lost * 100 / count)).tag("target", target)];
+        if !round_trips.is_empty() {
+            let total: Duration = round_trips.iter().sum();
+            let mean = total / round_trips.len() as u32;
+            let max = round_trips.iter().max().cloned().unwrap_or_default();
+            data.push(Metric::new("probe_latency_ms", mean.as_millis() as u64).tag("target", target));
+            data.push(Metric::new("probe_latency_max_ms", max.as_millis() as u64).tag("target", target));
+        }
+        Ok(data)
+    }
+}
+
+// "1.1.1.1", "1.1.1.1:53", or "example.com:80"
+fn address(target: &str) -> Result<SocketAddr, FetchError> {
+    let addresses = target.to_socket_addrs().or_else(|_| (target, DEFAULT_PORT).to_socket_addrs());
+    match addresses.ok().and_then(|mut addresses| addresses.next()) {
+        Some(address) => Ok(address),
+        None => bail!("Could not find the address of {}", target),
+    }
+}
+
+// The round trip, or None when it timed out
+fn connect(address: SocketAddr) -> Option<Duration> {
+    let started = Instant::now();
+    match TcpStream::connect_timeout(&address, TIMEOUT) {
+        Ok(_) => Some(started.elapsed()),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => Some(started.elapsed()),
+        Err(e) => {
+            debug!("Connecting to {} failed: {}", address, e);
+            None
+        }
+    }
+}
@@ -0,0 +1,156 @@
+// The different sets of data we can pull from the router
+//
+// Each collector maps to one API endpoint, and lives in its own module implementing Collector. They
+// change at very different rates, so the daemon polls each of them on its own interval (see
+// scheduler.rs). Adding one is a matter of a new module and an entry in REGISTRY.
+
+pub mod bandwidth;
+pub mod devices;
+pub mod dns_probe;
+pub mod interfaces;
+pub mod latency_probe;
+pub mod presence;
+pub mod site_survey;
+pub mod ssid_traffic;
+pub mod wifi_radios;
+
+use crate::cardinality;
+use crate::config::Config;
+use crate::hostnames;
+use crate::metrics::{self, Measurement, Metric};
+use crate::oui;
+use crate::router::{schema, Model};
+use crate::{FetchError, FiosClient};
+use tracing::{debug, info_span, warn};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+pub type MetricBatch = Vec<Metric>;
+
+pub trait Collector: Sync {
+    fn name(&self) -> &'static str;
+
+    // One line for list-collectors
+    fn description(&self) -> &'static str;
+
+    // Which router models it works on, and the API it reads there
+    fn support(&self) -> &'static str;
+
+    fn default_interval(&self) -> Duration;
+
+    // The measurements the collector produces, with the tags it puts on them
+    fn measurements(&self) -> &'static [Measurement];
+
+    // Whether the config gives the collector anything to do
+    fn configured(&self, _config: &Config) -> bool {
+        true
+    }
+
+    // Whether the router model has what it reads. It's skipped on the others, whether the model was
+    // configured or detected.
+    fn supports(&self, _model: Model) -> bool {
+        true
+    }
+
+    fn collect(&self, session: &FiosClient, config: &Config) -> Result<MetricBatch, FetchError>;
+}
+
+// Collectors are told apart by name
+impl PartialEq for dyn Collector {
+    fn eq(&self, other: &dyn Collector) -> bool {
+        self.name() == other.name()
+    }
+}
+
+impl fmt::Debug for dyn Collector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+pub static REGISTRY: [&dyn Collector; 9] = [
+    &bandwidth::Bandwidth,
+    &devices::Devices,
+    &site_survey::SiteSurvey,
+    &presence::Presence,
+    &ssid_traffic::SsidTraffic,
+    &wifi_radios::WifiRadios,
+    &interfaces::Interfaces,
+    &dns_probe::DnsProbe,
+    &latency_probe::LatencyProbe,
+];
+
+pub fn by_name(name: &str) -> Option<&'static dyn Collector> {
+    REGISTRY.iter().cloned().find(|c| c.name() == name)
+}
+
+// A measurement's description, from the collector taking it or the daemon's own
+pub fn measurement(name: &str) -> Option<&'static Measurement> {
+    REGISTRY
+        .iter()
+        .flat_map(|c| c.measurements().iter())
+        .chain(metrics::DAEMON_MEASUREMENTS.iter())
+        .find(|m| m.name == name)
+}
+
+// Static, for the command line's possible values
+pub fn names() -> &'static [&'static str] {
+    static NAMES: OnceLock<Vec<&'static str>> = OnceLock::new();
+    NAMES.get_or_init(|| REGISTRY.iter().map(|c| c.name()).collect())
+}
+
+// How often each collector has failed since the start, for collector_errors
+#[derive(Debug, Default)]
+pub struct ErrorCounts(HashMap<&'static str, u64>);
+
+// Runs the collectors one after the other. Those the router's model doesn't support are left out, and
+// one that fails, e.g. on a firmware answering differently, is logged and skipped, so the data of the
+// others still gets through. Besides their data, each
+// collector gives collector_success (1 or 0) and collector_errors, tagged with its name, and then
+// there's schema_unknown_fields, see router/schema.rs. Returns the errors too, for the callers that
+// care why.
+pub fn run(
+    collectors: &[&'static dyn Collector],
+    session: &FiosClient,
+    config: &Config,
+    errors: &mut ErrorCounts,
+) -> (MetricBatch, Vec<FetchError>) {
+    let mut data = Vec::new();
+    let mut failures = Vec::new();
+    for collector in collectors {
+        let _collector = info_span!("collector", name = collector.name()).entered();
+        if !collector.supports(session.model()) {
+            debug!("Skipping collector, the {} doesn't support it", session.model().name());
+            continue;
+        }
+        debug!("Running collector");
+        let count = errors.0.entry(collector.name()).or_insert(0);
+        let success = match collector.collect(session, config) {
+            Ok(metrics) => {
+                data.extend(metrics);
+                true
+            }
+            Err(e) => {
+                warn!("Collector {} failed: {}", collector.name(), e);
+                *count += 1;
+                failures.push(e);
+                false
+            }
+        };
+        data.push(Metric::new("collector_success", u64::from(success)).tag("collector", collector.name()));
+        data.push(Metric::new("collector_errors", *count).tag("collector", collector.name()));
+    }
+    if let Err(e) = hostnames::tag(&mut data, session, config) {
+        warn!("Could not get the device host names: {}", e);
+    }
+    if let Err(e) = oui::tag(&mut data, config) {
+        warn!("Could not look up the device vendors: {}", e);
+    }
+    if let Some(max_devices) = config.max_devices {
+        cardinality::limit(&mut data, max_devices);
+    }
+    data.extend(schema::metrics());
+    (data, failures)
+}
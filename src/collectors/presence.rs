@@ -0,0 +1,71 @@
+// Whether the devices listed in the config are connected, e.g. for home automation
+
+use super::{Collector, MetricBatch};
+use crate::config::Config;
+use crate::correlate::{self, Correlator};
+use crate::hostnames::normalize_mac;
+use crate::metrics::{Measurement, Metric};
+use crate::{FetchError, FiosClient};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const NAME: &str = "presence";
+
+static MEASUREMENTS: [Measurement; 2] = [
+    Measurement::new("device_present", "0/1", &["device", "mac"], "Whether the device is connected"),
+    Measurement::new("device_last_seen", "unix time", &["device", "mac"], "When the device was last connected"),
+];
+
+pub struct Presence;
+
+impl Collector for Presence {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "Whether the devices in the presence config are connected"
+    }
+
+    fn support(&self) -> &'static str {
+        "All models. /api/devices on the G1100, Device.Hosts.Host on the G3100 and CR1000A"
+    }
+
+    fn default_interval(&self) -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn measurements(&self) -> &'static [Measurement] {
+        &MEASUREMENTS
+    }
+
+    // Only when there are devices to look for
+    fn configured(&self, config: &Config) -> bool {
+        !config.presence.is_empty()
+    }
+
+    // The last seen time is only sent while a device is present, so the latest value stored is when it
+    // was last seen
+    fn collect(&self, session: &FiosClient, config: &Config) -> Result<MetricBatch, FetchError> {
+        let devices = correlate::devices(session, config)?;
+        let first_macs = match Correlator::from_config(config) {
+            Some(correlator) => correlator.first_macs().map_err(simple_error::SimpleError::new)?,
+            None => HashMap::new(),
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+        let mut data = Vec::new();
+        for (name, mac) in &config.presence {
+            let mac = normalize_mac(mac);
+            let mac = first_macs.get(&mac).cloned().unwrap_or(mac);
+            let present = devices
+                .iter()
+                .any(|d| d.connected && d.mac.as_deref().map(normalize_mac).as_deref() == Some(mac.as_str()));
+            data.push(Metric::new("device_present", u64::from(present)).tag("device", name).tag("mac", &mac));
+            if present {
+                data.push(Metric::new("device_last_seen", now).tag("device", name).tag("mac", &mac));
+            }
+        }
+        Ok(data)
+    }
+}
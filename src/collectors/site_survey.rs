@@ -0,0 +1,55 @@
+// The neighboring WiFi networks, per channel
+
+use super::{Collector, MetricBatch};
+use crate::config::Config;
+use crate::metrics::{Measurement, Metric};
+use crate::{FetchError, FiosClient};
+use std::collections::HashMap;
+use std::time::Duration;
+
+pub const NAME: &str = "site_survey";
+
+static MEASUREMENTS: [Measurement; 1] = [
+    Measurement::new("wifi_neighbors", "count", &["channel"], "Other WiFi networks on the channel"),
+];
+
+pub struct SiteSurvey;
+
+impl Collector for SiteSurvey {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "The neighboring WiFi networks per channel"
+    }
+
+    fn support(&self) -> &'static str {
+        "All models. /api/wireless/sitesurvey on the G1100, Device.WiFi.NeighboringWiFiDiagnostic.Result on the G3100 and CR1000A"
+    }
+
+    fn default_interval(&self) -> Duration {
+        Duration::from_secs(60 * 60)
+    }
+
+    fn measurements(&self) -> &'static [Measurement] {
+        &MEASUREMENTS
+    }
+
+    fn collect(&self, session: &FiosClient, _config: &Config) -> Result<MetricBatch, FetchError> {
+        let networks = session.wifi_networks()?;
+
+        // Neighboring networks per channel is what matters when picking a channel
+        let mut per_channel: HashMap<u64, u64> = HashMap::new();
+        for network in networks {
+            if let Some(channel) = network.channel {
+                *per_channel.entry(channel).or_insert(0) += 1;
+            }
+        }
+
+        Ok(per_channel
+            .iter()
+            .map(|(channel, count)| Metric::new("wifi_neighbors", *count).tag("channel", &channel.to_string()))
+            .collect())
+    }
+}
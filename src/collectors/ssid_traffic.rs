@@ -0,0 +1,54 @@
+// Traffic per WiFi network, e.g. to tell the guest network apart from the main one
+
+use super::{Collector, MetricBatch};
+use crate::config::Config;
+use crate::metrics::{Measurement, Metric};
+use crate::router::Model;
+use crate::{FetchError, FiosClient};
+use std::time::Duration;
+
+pub const NAME: &str = "ssid_traffic";
+
+static MEASUREMENTS: [Measurement; 2] = [
+    Measurement::counter("ssid_rx_bytes", "bytes", &["ssid"], "Received on the network since the router started"),
+    Measurement::counter("ssid_tx_bytes", "bytes", &["ssid"], "Sent on the network since the router started"),
+];
+
+pub struct SsidTraffic;
+
+impl Collector for SsidTraffic {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "Bytes received and sent on each of the router's WiFi networks"
+    }
+
+    fn support(&self) -> &'static str {
+        "G3100 and CR1000A, the Device.WiFi.SSID stats. The G1100 doesn't break the traffic down"
+    }
+
+    fn default_interval(&self) -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn measurements(&self) -> &'static [Measurement] {
+        &MEASUREMENTS
+    }
+
+    // The G1100 doesn't have the counters
+    fn supports(&self, model: Model) -> bool {
+        model != Model::G1100
+    }
+
+    // Totals since the router started, as the router counts them, so not converted to bits
+    fn collect(&self, session: &FiosClient, _config: &Config) -> Result<MetricBatch, FetchError> {
+        let mut data = Vec::new();
+        for traffic in session.ssid_traffic()? {
+            data.push(Metric::new("ssid_rx_bytes", traffic.rx_bytes).tag("ssid", &traffic.ssid));
+            data.push(Metric::new("ssid_tx_bytes", traffic.tx_bytes).tag("ssid", &traffic.ssid));
+        }
+        Ok(data)
+    }
+}
@@ -0,0 +1,64 @@
+// Retries, failed frames, and airtime of each WiFi radio, which show congestion better than the
+// throughput does
+
+use super::{Collector, MetricBatch};
+use crate::config::Config;
+use crate::metrics::{Measurement, Metric};
+use crate::router::Model;
+use crate::{FetchError, FiosClient};
+use std::time::Duration;
+
+pub const NAME: &str = "wifi_radios";
+
+static MEASUREMENTS: [Measurement; 3] = [
+    Measurement::counter("wifi_retries", "count", &["band"], "Retransmissions since the router started"),
+    Measurement::counter("wifi_failed_frames", "count", &["band"], "Frames given up on since the router started"),
+    Measurement::new("wifi_airtime_percent", "percent", &["band"], "How busy the channel is"),
+];
+
+pub struct WifiRadios;
+
+impl Collector for WifiRadios {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn description(&self) -> &'static str {
+        "Retries, failed frames, and airtime utilization of each WiFi radio"
+    }
+
+    fn support(&self) -> &'static str {
+        "G3100 and CR1000A, the Device.WiFi.Radio stats, where the firmware has them. Not the G1100"
+    }
+
+    fn default_interval(&self) -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn measurements(&self) -> &'static [Measurement] {
+        &MEASUREMENTS
+    }
+
+    // The G1100 doesn't have the stats
+    fn supports(&self, model: Model) -> bool {
+        model != Model::G1100
+    }
+
+    // The retries and failed frames are totals since the router started
+    fn collect(&self, session: &FiosClient, _config: &Config) -> Result<MetricBatch, FetchError> {
+        let mut data = Vec::new();
+        for radio in session.radio_stats()? {
+            let values = [
+                ("wifi_retries", radio.retries),
+                ("wifi_failed_frames", radio.failed_frames),
+                ("wifi_airtime_percent", radio.airtime_percent),
+            ];
+            for (name, value) in values.iter() {
+                if let Some(value) = value {
+                    data.push(Metric::new(name, *value).tag("band", &radio.band));
+                }
+            }
+        }
+        Ok(data)
+    }
+}
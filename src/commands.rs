@@ -0,0 +1,405 @@
+// The subcommands, other than `daemon` which lives in scheduler.rs
+
+use crate::{password, setup};
+use fios_stats::collectors::{self, Collector, ErrorCounts};
+use fios_stats::discover;
+use fios_stats::grafana;
+use fios_stats::metrics;
+use fios_stats::oui;
+use fios_stats::output;
+use fios_stats::{firmware, sinks, usage, wan, Config, FetchError, FiosClient, Gateway};
+use tracing::{info, info_span, warn};
+use serde_json::Value;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::Duration;
+
+type Client = reqwest::blocking::Client;
+
+// How long to wait for SSDP answers
+const DISCOVER_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub fn collect(
+    client: &Client,
+    gateway: &Gateway,
+    password: &str,
+    config: &Config,
+    collectors: &[&'static dyn Collector],
+    sparkline: bool,
+) -> Result<(), FetchError> {
+    let format = config.output_format().map_err(simple_error::SimpleError::new)?;
+    let unit = config.unit().map_err(simple_error::SimpleError::new)?;
+    let _collect = info_span!("collect").entered();
+    let session = FiosClient::login_with(client, gateway, password)?;
+
+    let filter = config.metric_filter();
+    let wanted: Vec<&'static dyn Collector> = collectors.iter().cloned().filter(|c| config.wants(*c)).collect();
+    let (mut data, errors) = collectors::run(&wanted, &session, config, &mut ErrorCounts::default());
+    if let Some(tracker) = usage::Tracker::from_config(config) {
+        let usage = tracker.update(&data)?;
+        data.extend(usage);
+    }
+    if let Some(watcher) = wan::Watcher::from_config(config).map_err(simple_error::SimpleError::new)? {
+        data.extend(watcher.check(client, &session)?);
+    }
+    if let Some(watcher) = firmware::Watcher::from_config(config).map_err(simple_error::SimpleError::new)? {
+        data.extend(watcher.check(client, &session)?);
+    }
+    data.retain(|m| filter.matches(&m.name));
+    metrics::convert_units(&mut data, unit);
+    metrics::add_tags(&mut data, &config.all_tags());
+    if let Some(format) = format {
+        output::print(format, &data, config);
+    }
+    if sparkline {
+        match session.minute_history() {
+            Ok((rx, tx)) => print_sparklines(&rx, &tx),
+            Err(e) => warn!("Can't show the traffic history: {}", e),
+        }
+    }
+
+    sinks::write(client, config, &data)?;
+
+    session.logout()?;
+    // The data of the collectors that worked is stored, but the exit code still tells of the failure
+    match errors.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+// The last minutes of traffic as sparklines, with the peak in bits per second
+fn print_sparklines(rx: &[u64], tx: &[u64]) {
+    println!("\nLast {} minutes, oldest first:", rx.len());
+    for (name, minutes) in [("rx", rx), ("tx", tx)] {
+        let peak = minutes.iter().cloned().max().unwrap_or(0) as f64 * 8.0 / 60.0;
+        println!("{}  {}  peak {:.1} Mbit/s", name, output::sparkline(minutes), peak / 1e6);
+    }
+}
+
+pub fn dump(client: &Client, gateway: &Gateway, password: &str, api: &str) -> Result<(), FetchError> {
+    let session = FiosClient::login_with(client, gateway, password)?;
+    let body = session.fetch(api)?;
+    match serde_json::from_str::<Value>(&body) {
+        Ok(json) => println!("{}", serde_json::to_string_pretty(&json)?),
+        Err(_) => println!("{}", body),
+    }
+    session.logout()?;
+    Ok(())
+}
+
+// The results of the last ping and traceroute run on the router, e.g. from its admin page. From the
+// router, a problem past it is on the WAN side rather than in the LAN.
+pub fn diagnostics(client: &Client, gateway: &Gateway, password: &str) -> Result<(), FetchError> {
+    let session = FiosClient::login_with(client, gateway, password)?;
+    let diagnostics = session.diagnostics()?;
+    session.logout()?;
+
+    let ms = |time: Option<u64>| time.map(|t| format!("{}ms", t)).unwrap_or_else(|| "-".to_string());
+    match &diagnostics.ping {
+        Some(ping) => println!(
+            "Ping {} ({}): {} replies, {} lost, min/avg/max {}/{}/{}",
+            ping.host,
+            ping.state,
+            ping.successes,
+            ping.failures,
+            ms(ping.min_ms),
+            ms(ping.average_ms),
+            ms(ping.max_ms)
+        ),
+        None => println!("No ping has been run on the router"),
+    }
+    if diagnostics.traceroute.is_empty() {
+        println!("No traceroute has been run on the router");
+    }
+    for (number, hop) in diagnostics.traceroute.iter().enumerate() {
+        let times: Vec<String> = hop.rtt_ms.iter().map(|t| format!("{}ms", t)).collect();
+        println!("{:>2}  {} ({})  {}", number + 1, hop.host, hop.address, times.join(" "));
+    }
+    Ok(())
+}
+
+pub fn check(client: &Client, gateway: &Gateway, password: &str, config: &Config) -> Result<(), FetchError> {
+    let session = FiosClient::login_with(client, gateway, password)?;
+    session.logout()?;
+    info!("Router: OK");
+
+    if let Some(influx_db) = &config.influxdb {
+        let uri = reqwest::Url::parse(influx_db)?.join("ping")?;
+        let response = client.get(uri).send()?;
+        if !response.status().is_success() {
+            bail!("Unexpected status from InfluxDB ping: {}", response.status());
+        }
+        info!("InfluxDB: OK");
+    }
+    Ok(())
+}
+
+// Runs each wanted collector once and reports how it went, without writing anything. For checking
+// whether a router's firmware is supported, and for pasting into issue reports.
+pub fn selftest(client: &Client, gateway: &Gateway, password: &str, config: &Config) -> Result<(), FetchError> {
+    println!("fios-stats {}, router at {}", env!("CARGO_PKG_VERSION"), config.gateway());
+    let session = match FiosClient::login_with(client, gateway, password) {
+        Ok(session) => session,
+        Err(e) => {
+            println!("FAIL  login: {}", e);
+            return Err(e);
+        }
+    };
+    println!("PASS  login");
+
+    let enabled = config.enabled_collectors().map_err(simple_error::SimpleError::new)?;
+    let collectors: Vec<&dyn Collector> = enabled.into_iter().filter(|c| config.wants(*c)).collect();
+    let mut failed = 0;
+    for collector in &collectors {
+        match collector.collect(&session, config) {
+            Ok(data) => println!("PASS  {} ({} metrics)", collector.name(), data.len()),
+            Err(e) => {
+                println!("FAIL  {}: {}", collector.name(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    match session.logout() {
+        Ok(()) => println!("PASS  logout"),
+        Err(e) => {
+            println!("FAIL  logout: {}", e);
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        bail!("{} of {} checks failed", failed, collectors.len() + 2);
+    }
+    Ok(())
+}
+
+pub fn backup(client: &Client, gateway: &Gateway, password: &str, output: &Path) -> Result<(), FetchError> {
+    let session = FiosClient::login_with(client, gateway, password)?;
+    let backup = session.backup()?;
+    session.logout()?;
+
+    if let Err(e) = std::fs::write(output, &backup) {
+        bail!("Could not write backup to {}: {}", output.display(), e);
+    }
+    info!("Saved {} bytes of router configuration to {}", backup.len(), output.display());
+    Ok(())
+}
+
+pub fn reboot(client: &Client, gateway: &Gateway, password: &str) -> Result<(), FetchError> {
+    let session = FiosClient::login_with(client, gateway, password)?;
+    // No logout, the session goes away with the reboot
+    session.reboot()?;
+    info!("Router is rebooting");
+    Ok(())
+}
+
+pub fn init_influx(client: &Client, config: &Config) -> Result<(), FetchError> {
+    let influx_db = match &config.influxdb {
+        Some(influx_db) => influx_db,
+        None => bail!("No InfluxDB given, use -i or FIOS_INFLUX_URI"),
+    };
+    let uri = reqwest::Url::parse(influx_db)?;
+    let database = match uri.query_pairs().find(|(key, _)| key == "db") {
+        Some((_, database)) => database.into_owned(),
+        None => bail!("No database name (db=) in the InfluxDB URI: {}", influx_db),
+    };
+
+    let mut query_uri = uri.join("query")?;
+    query_uri.set_query(None);
+    let response = client.post(query_uri)
+        .form(&[("q", format!("CREATE DATABASE \"{}\"", database))])
+        .send()?;
+    if !response.status().is_success() {
+        bail!("Could not create InfluxDB database: {}", response.status());
+    }
+    info!("Created InfluxDB database {}", database);
+    Ok(())
+}
+
+// Prints a summary of the last day or week from InfluxDB, and with send also sends it to the
+// notification channels that want reports
+#[cfg_attr(not(feature = "influx"), allow(unused_variables))]
+pub fn report(client: &Client, config: &Config, period: &str, send: bool) -> Result<(), FetchError> {
+    let influx_db = match &config.influxdb {
+        Some(influx_db) => influx_db,
+        None => bail!("No InfluxDB given to read the data from, use -i or FIOS_INFLUX_URI"),
+    };
+    #[cfg(feature = "influx")]
+    {
+        use fios_stats::notify::{self, Event, Notifier};
+        use fios_stats::report::{Period, Report};
+
+        let unit = config.unit().map_err(simple_error::SimpleError::new)?;
+        let period = Period::from_name(period).ok_or_else(|| simple_error::SimpleError::new(format!("Unknown period: {}", period)))?;
+        let host = config.all_tags().remove("host").unwrap_or_default();
+        let report = Report::build(client, influx_db, &host, unit, period)?;
+        print!("{}", report);
+        if send {
+            let notifier = Notifier::from_config(config).map_err(simple_error::SimpleError::new)?;
+            if !notifier.wants(notify::REPORT) {
+                bail!("No notification channel wants reports");
+            }
+            let event = Event::new(notify::REPORT, &format!("fios-stats report for {}", host), &report.to_string());
+            notifier.send(client, &event)?;
+            info!("Report sent");
+        }
+        Ok(())
+    }
+    #[cfg(not(feature = "influx"))]
+    bail!("Built without the influx feature, can't read from {}", influx_db);
+}
+
+// Prints a Grafana dashboard for the data, to import in Grafana
+pub fn grafana_dashboard(config: &Config, datasource: &str) -> Result<(), FetchError> {
+    let unit = config.unit().map_err(simple_error::SimpleError::new)?;
+    let dashboard = grafana::dashboard(datasource, unit).map_err(simple_error::SimpleError::new)?;
+    println!("{}", serde_json::to_string_pretty(&dashboard)?);
+    Ok(())
+}
+
+pub fn update_oui(client: &Client, config: &Config) -> Result<(), FetchError> {
+    let count = oui::download(client, config)?;
+    println!("Saved {} vendors to {}", count, oui::path(config).display());
+    Ok(())
+}
+
+// The collectors there are, and which of them the config enables
+pub fn list_collectors(config: &Config) -> Result<(), FetchError> {
+    let enabled = config.enabled_collectors().map_err(simple_error::SimpleError::new)?;
+    for collector in collectors::REGISTRY.iter() {
+        let status = if enabled.contains(collector) && config.wants(*collector) { "enabled" } else { "disabled" };
+        println!("{:<14} {:<9} every {:<5} {}", collector.name(), status, duration_name(collector.default_interval()), collector.description());
+        println!("{:<14} {}", "", collector.support());
+    }
+    Ok(())
+}
+
+// Everything that can get written, from the collectors' own descriptions of their measurements
+pub fn schema(config: &Config) -> Result<(), FetchError> {
+    let unit = config.unit().map_err(simple_error::SimpleError::new)?;
+    println!("Every point has one integer field, value, and the host tag and the configured tags.");
+    println!("Points with a mac tag can also have hostname and category tags with hostname_tags, and vendor with vendor_tags.");
+    println!("With hourly_rollups, each measurement also gets <measurement>_hourly and <measurement>_hourly_max.");
+    let groups = collectors::REGISTRY
+        .iter()
+        .map(|c| (c.name(), c.measurements()))
+        .chain(Some(("daemon", &metrics::DAEMON_MEASUREMENTS[..])));
+    for (group, measurements) in groups {
+        println!();
+        println!("{}", group);
+        for measurement in measurements {
+            let tags = if measurement.tags.is_empty() { "-".to_string() } else { measurement.tags.join(",") };
+            println!(
+                "  {:<30} {:<10} {:<8} {:<21} {}",
+                measurement.name,
+                measurement.unit_in(unit),
+                measurement.kind.name(),
+                tags,
+                measurement.description
+            );
+        }
+    }
+    Ok(())
+}
+
+// "90s", "5m", "1h"
+fn duration_name(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds.is_multiple_of(3600) && seconds > 0 {
+        format!("{}h", seconds / 3600)
+    } else if seconds.is_multiple_of(60) && seconds > 0 {
+        format!("{}m", seconds / 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+pub fn save_login(
+    client: &Client,
+    gateway: &Gateway,
+    password: &str,
+    config: &Config,
+    save: bool,
+) -> Result<(), FetchError> {
+    let session = FiosClient::login_with(client, gateway, password)?;
+    session.logout()?;
+    info!("Login successful");
+    if save {
+        password::save_to_keyring(config.gateway(), password).map_err(simple_error::SimpleError::new)?;
+        info!("Password saved to keyring");
+    }
+    Ok(())
+}
+
+pub fn discover(config_file: Option<&str>, save: bool) -> Result<(), FetchError> {
+    info!("Looking for routers on the local network");
+    let candidates = discover::discover(DISCOVER_TIMEOUT);
+    if candidates.is_empty() {
+        bail!("No routers found, give the address with -g");
+    }
+    for (i, candidate) in candidates.iter().enumerate() {
+        let model = candidate.model.map(|m| m.name()).unwrap_or("unknown");
+        let server = candidate.server.as_ref().map(|s| format!(" ({})", s)).unwrap_or_default();
+        println!("{}) {:<16} {:<8} found by {}{}", i + 1, candidate.address, model, candidate.sources.join(", "), server);
+    }
+    if !save {
+        return Ok(());
+    }
+
+    let chosen = if candidates.len() == 1 {
+        &candidates[0]
+    } else {
+        let answer = setup::ask("\nWhich one to use", Some("1"))?;
+        match answer.parse::<usize>().ok().and_then(|i| candidates.get(i.wrapping_sub(1))) {
+            Some(candidate) => candidate,
+            None => bail!("No router number {}", answer),
+        }
+    };
+    let path = config_file.unwrap_or(setup::DEFAULT_CONFIG_FILE);
+    save_gateway(Path::new(path), &chosen.address.to_string())?;
+    info!("Saved gateway {} to {}", chosen.address, path);
+    Ok(())
+}
+
+// Sets the gateway in a config file, creating the file if needed. The rest of the settings are kept,
+// but not any comments.
+fn save_gateway(path: &Path, gateway: &str) -> Result<(), FetchError> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => bail!("Could not read {}: {}", path.display(), e),
+    };
+    let updated = match path.extension().and_then(|e| e.to_str()) {
+        Some("yaml") | Some("yml") => {
+            let mut file = if contents.trim().is_empty() {
+                serde_yaml::Mapping::new()
+            } else {
+                match serde_yaml::from_str(&contents) {
+                    Ok(file) => file,
+                    Err(e) => bail!("Could not parse {}: {}", path.display(), e),
+                }
+            };
+            file.insert("gateway".into(), gateway.into());
+            serde_yaml::to_string(&file).map_err(|e| e.to_string())
+        }
+        _ => {
+            let mut file: toml::value::Table = match toml::from_str(&contents) {
+                Ok(file) => file,
+                Err(e) => bail!("Could not parse {}: {}", path.display(), e),
+            };
+            file.insert("gateway".to_string(), toml::Value::String(gateway.to_string()));
+            toml::to_string(&toml::Value::Table(file)).map_err(|e| e.to_string())
+        }
+    };
+    match updated {
+        Ok(updated) => {
+            if let Err(e) = std::fs::write(path, updated) {
+                bail!("Could not write {}: {}", path.display(), e);
+            }
+        }
+        Err(e) => bail!("Could not update {}: {}", path.display(), e),
+    }
+    Ok(())
+}
@@ -0,0 +1,435 @@
+// Configuration file support
+//
+// Everything that can be given on the command line can also be put in a TOML or YAML file
+// (picked by the file extension), for example:
+//
+//   gateway = "192.168.1.1"
+//   password = "secret_password"  # or password_file / password_cmd
+//   influxdb = "http://192.168.0.12:8086/write?db=fios_data"
+//   daemon = true
+//
+//   collectors = ["bandwidth", "devices"]
+//
+//   [tags]
+//   location = "basement"
+//
+//   [intervals]
+//   devices = "10m"
+//   site_survey = "0"
+//
+//   # Per router settings, used with --profile or --all
+//   [profiles.extender]
+//   gateway = "192.168.1.2"
+//   password_file = "/etc/fios-stats/extender-password"
+//
+// Command line flags override the values from the file.
+
+use crate::collectors::{self, Collector};
+use crate::metrics::{Filter, Unit};
+use crate::output::Format;
+use crate::router::Model;
+use crate::scheduler;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+use toml::value::{Table, Value};
+
+// Docker mode, see Config::from_env
+pub const SECRETS_DIR: &str = "/run/secrets";
+const ENV_PREFIX: &str = "FIOS_";
+const SECRET_PREFIX: &str = "fios_";
+// Read by the command line flags, or not settings at all
+const FLAG_VARIABLES: [&str; 4] = ["FIOS_CONFIG", "FIOS_PROFILE", "FIOS_INFLUX_URI", "FIOS_DOCKER"];
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub gateway: Option<String>,
+    // Router model, e.g. "g3100". Detected when not given
+    pub model: Option<String>,
+    pub resolve: Option<String>,
+    // Most requests per second to the router, see ratelimit.rs
+    pub rate_limit: Option<f64>,
+    pub insecure: bool,
+    pub ca_cert: Option<String>,
+    pub pin_sha256: Option<String>,
+    pub tofu: bool,
+    pub tofu_file: Option<String>,
+    // Where the files kept between runs go, see state.rs
+    pub state_dir: Option<String>,
+    // Only sent when given, most firmwares have just the one admin user
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub password_file: Option<String>,
+    pub password_cmd: Option<String>,
+    // "sha512", "sha256", or "hmac-sha256", for firmwares that don't hash the password the usual way
+    pub password_hash: Option<String>,
+    pub influxdb: Option<String>,
+    // A Prometheus remote_write endpoint, e.g. http://mimir:9009/api/v1/push
+    pub remote_write: Option<String>,
+    // Read the data back after writing it, to catch points InfluxDB drops silently
+    pub verify_writes: bool,
+    // Also write hourly means and maximums in daemon mode, see rollup.rs
+    pub hourly_rollups: bool,
+    // Proxy for the sinks, the router is always connected to directly
+    pub proxy: Option<String>,
+    // Connection tuning, see client.rs: "auto", "1", or "2", and durations where "0" turns it off
+    pub http_version: Option<String>,
+    pub tcp_keepalive: Option<String>,
+    pub pool_idle_timeout: Option<String>,
+    // Report changes of the router's public address, see wan.rs
+    pub watch_wan_ip: bool,
+    pub wan_ip_file: Option<String>,
+    pub wan_ip_webhook: Option<String>,
+    // Report firmware updates, see firmware.rs
+    pub watch_firmware: bool,
+    // Dynamic DNS name to update when the WAN IP changes
+    pub ddns: Option<Ddns>,
+    // Month to date data usage, see usage.rs. The cap is in GB
+    pub track_usage: bool,
+    pub usage_file: Option<String>,
+    pub data_cap: Option<u64>,
+    pub daemon: bool,
+    // Wait for the system clock to be set before collecting in daemon mode, see scheduler.rs
+    pub wait_for_clock: bool,
+    pub dry_run: bool,
+    // How to print collected data on stdout
+    pub format: Option<String>,
+    // "bits" (the default) or "bytes", for the byte counters
+    pub units: Option<String>,
+    // Glob patterns for the measurements to collect, and those not to
+    pub metrics: Vec<String>,
+    pub exclude_metrics: Vec<String>,
+    // Extra tags added to every data point
+    pub tags: BTreeMap<String, String>,
+    // Device name -> MAC address, for the presence collector
+    pub presence: BTreeMap<String, String>,
+    // Take devices with a made up MAC and a known host name to be the same device, see correlate.rs
+    pub correlate_devices: bool,
+    // Tag the per-device data with the device's host name, see hostnames.rs
+    pub hostname_tags: bool,
+    // Nicknames for devices, over the router's names, see hostnames.rs. The file has "MAC,name[,category]"
+    // lines, and device_names is MAC address -> name
+    pub device_file: Option<String>,
+    pub device_names: BTreeMap<String, String>,
+    // A name to look up each cycle, timing the router's DNS, and another DNS server to ask instead
+    // (an address, with or without a port), see collectors/dns_probe.rs
+    pub dns_probe: Option<String>,
+    pub dns_server: Option<String>,
+    // Where to measure the latency and loss to, as host[:port], and how many connections to time each
+    // cycle, see collectors/latency_probe.rs
+    pub probe_target: Option<String>,
+    pub probe_count: Option<u32>,
+    // The most devices to write series for, the others are put together, see cardinality.rs
+    pub max_devices: Option<usize>,
+    // Tag the per-device data with the device's vendor, see oui.rs
+    pub vendor_tags: bool,
+    pub oui_file: Option<String>,
+    // Threshold alerts in daemon mode, see alerts.rs
+    pub alerts: Vec<AlertRule>,
+    pub alert_webhook: Option<String>,
+    // Where to send events, see notify.rs
+    pub notifications: Vec<Notification>,
+    // The collectors to run, all of them when empty. See list-collectors
+    pub collectors: Vec<String>,
+    // Collector name -> polling interval in daemon mode
+    pub intervals: BTreeMap<String, String>,
+    // Where to serve the web dashboard in daemon mode, e.g. 127.0.0.1:8080
+    pub dashboard: Option<String>,
+    // Where to answer health checks in daemon mode, see health.rs
+    pub health: Option<String>,
+    // How many cycles of data to hold on to in daemon mode while the sinks are slow or down, and
+    // what to do once there are more: "drop-oldest" (the default) or "block"
+    pub queue_size: Option<usize>,
+    pub queue_overflow: Option<String>,
+    // How long the router session can go unused in daemon mode before it's used to keep it alive,
+    // "5m" by default, "0" turns it off
+    pub session_keepalive: Option<String>,
+    // Named routers, selected with --profile
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+// The per router settings, which override the top level ones when the profile is selected
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub gateway: Option<String>,
+    pub model: Option<String>,
+    pub resolve: Option<String>,
+    pub insecure: Option<bool>,
+    pub ca_cert: Option<String>,
+    pub pin_sha256: Option<String>,
+    pub tofu: Option<bool>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub password_file: Option<String>,
+    pub password_cmd: Option<String>,
+    pub tags: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ddns {
+    // "cloudflare", "duckdns", or "url"
+    pub provider: String,
+    pub hostname: String,
+    pub token: Option<String>,
+    pub token_file: Option<String>,
+    // Cloudflare only. The record is looked up by the hostname when not given
+    pub zone_id: Option<String>,
+    pub record_id: Option<String>,
+    // For "url", with {hostname} and {ip} filled in
+    pub url: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Notification {
+    // For picking channels in alert rules
+    pub name: Option<String>,
+    // "webhook", "slack", "discord", "ntfy", "email", "pushover", or "gotify"
+    #[serde(rename = "type")]
+    pub kind: String,
+    // For email the server, as smtps://host[:port] or smtp://host[:port] for STARTTLS. For Gotify the
+    // server, and not needed for Pushover
+    pub url: String,
+    // The events to send: "alert", "wan_ip", "new_device", "report", and "firmware". All of them when empty
+    pub events: Vec<String>,
+    // The message, with {title}, {message}, and the event's fields filled in
+    pub template: Option<String>,
+    // Email only
+    pub from: Option<String>,
+    pub to: Vec<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    // The application token for Pushover and Gotify, and the Pushover user key
+    pub token: Option<String>,
+    pub user: Option<String>,
+    pub priority: Option<i64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    pub name: String,
+    pub metric: String,
+    // One of >, >=, <, <=, ==, !=
+    pub condition: String,
+    pub threshold: u64,
+    // How long the condition has to hold before the alert fires, e.g. "5m"
+    #[serde(default)]
+    pub duration: Option<String>,
+    // Compare the increase per minute rather than the value, for counters like net_rx_errors
+    #[serde(default)]
+    pub per_minute: bool,
+    // The names of the notification channels to use, all that want alerts when empty
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let extension = path.extension().and_then(|e| e.to_str()).map(str::to_lowercase);
+        let config = match extension.as_deref() {
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&contents).map_err(|e| e.to_string()),
+            _ => toml::from_str(&contents).map_err(|e| e.to_string()),
+        };
+        config.map_err(|e| format!("Could not parse {}: {}", path.display(), e))
+    }
+
+    // The config in Docker mode, from FIOS_* environment variables and fios_* files in the secrets
+    // directory, named after the settings. Nested ones are separated by a double underscore, e.g.
+    // FIOS_INTERVALS__DEVICES=10m. Values are read as TOML when they are valid TOML and the setting
+    // takes that, so numbers, booleans, and lists work, and as strings otherwise, e.g. for a password
+    // of 12345. Secrets are always strings, and win over the environment.
+    pub fn from_env<I>(variables: I, secrets: &Path) -> Result<Config, String>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut table = Table::new();
+        for (name, value) in variables {
+            if !name.starts_with(ENV_PREFIX) || FLAG_VARIABLES.contains(&name.as_str()) {
+                continue;
+            }
+            let key = name[ENV_PREFIX.len()..].to_lowercase();
+            let value = match toml::from_str::<Table>(&format!("value = {}", value)).ok().and_then(|mut parsed| parsed.remove("value")) {
+                Some(parsed) if parsed.is_str() || setting_fits(&key, parsed.clone()) => parsed,
+                _ => Value::String(value),
+            };
+            insert_setting(&mut table, &key, value);
+        }
+        // Without a secrets directory there are no secrets
+        for entry in std::fs::read_dir(secrets).into_iter().flatten().flatten() {
+            let name = entry.file_name().to_string_lossy().to_lowercase();
+            if let Some(key) = name.strip_prefix(SECRET_PREFIX) {
+                let path = entry.path();
+                let secret = std::fs::read_to_string(&path)
+                    .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+                insert_setting(&mut table, key, Value::String(secret.trim_end_matches(&['\r', '\n'][..]).to_string()));
+            }
+        }
+        Value::Table(table)
+            .try_into()
+            .map_err(|e| format!("Invalid setting in the environment or secrets: {}", e))
+    }
+
+    pub fn select_profile(&mut self, name: &str) -> Result<(), String> {
+        let profile = match self.profiles.get(name) {
+            Some(profile) => profile.clone(),
+            None => {
+                let known: Vec<&String> = self.profiles.keys().collect();
+                return Err(format!("Unknown profile {}, the config file has: {:?}", name, known));
+            }
+        };
+
+        if profile.gateway.is_some() {
+            self.gateway = profile.gateway;
+        }
+        if profile.model.is_some() {
+            self.model = profile.model;
+        }
+        if profile.resolve.is_some() {
+            self.resolve = profile.resolve;
+        }
+        if let Some(insecure) = profile.insecure {
+            self.insecure = insecure;
+        }
+        if profile.ca_cert.is_some() {
+            self.ca_cert = profile.ca_cert;
+        }
+        if profile.pin_sha256.is_some() {
+            self.pin_sha256 = profile.pin_sha256;
+        }
+        if let Some(tofu) = profile.tofu {
+            self.tofu = tofu;
+        }
+        if profile.username.is_some() {
+            self.username = profile.username;
+        }
+        // A password source in the profile replaces all of the top level ones
+        if profile.password.is_some() || profile.password_file.is_some() || profile.password_cmd.is_some() {
+            self.password = profile.password;
+            self.password_file = profile.password_file;
+            self.password_cmd = profile.password_cmd;
+        }
+        self.tags.extend(profile.tags);
+        self.tags.entry("profile".to_string()).or_insert_with(|| name.to_string());
+        Ok(())
+    }
+
+    pub fn gateway(&self) -> &str {
+        self.gateway.as_deref().unwrap_or(crate::DEFAULT_GATEWAY)
+    }
+
+    // The configured tags, plus the host tag identifying the router
+    pub fn all_tags(&self) -> BTreeMap<String, String> {
+        let mut tags = self.tags.clone();
+        if !tags.contains_key("host") {
+            tags.insert("host".to_string(), self.gateway().to_string());
+        }
+        tags
+    }
+
+    pub fn output_format(&self) -> Result<Option<Format>, String> {
+        match &self.format {
+            Some(name) => Format::from_name(name)
+                .map(Some)
+                .ok_or_else(|| format!("Unknown format: {}", name)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn unit(&self) -> Result<Unit, String> {
+        match self.units.as_deref() {
+            None | Some("bits") => Ok(Unit::Bits),
+            Some("bytes") => Ok(Unit::Bytes),
+            Some(units) => Err(format!("Unknown units: {}", units)),
+        }
+    }
+
+    pub fn metric_filter(&self) -> Filter {
+        Filter {
+            include: self.metrics.clone(),
+            exclude: self.exclude_metrics.clone(),
+        }
+    }
+
+    // The enabled collectors and how often to run them, falling back to the default intervals.
+    // Collectors whose measurements are all filtered out are left out.
+    pub fn collector_intervals(&self) -> Result<Vec<(&'static dyn Collector, Duration)>, String> {
+        for name in self.intervals.keys() {
+            if collectors::by_name(name).is_none() {
+                return Err(format!("Unknown collector: {}", name));
+            }
+        }
+
+        let mut intervals = Vec::new();
+        for collector in self.enabled_collectors()? {
+            let interval = match self.intervals.get(collector.name()) {
+                Some(value) => scheduler::parse_duration(value)
+                    .ok_or_else(|| format!("Invalid interval for {}: {}", collector.name(), value))?,
+                None => collector.default_interval(),
+            };
+            if interval.as_secs() > 0 && self.wants(collector) {
+                intervals.push((collector, interval));
+            }
+        }
+        Ok(intervals)
+    }
+
+    // The collectors listed in the config, in registry order, or all of them without a list
+    pub fn enabled_collectors(&self) -> Result<Vec<&'static dyn Collector>, String> {
+        for name in &self.collectors {
+            if collectors::by_name(name).is_none() {
+                return Err(format!("Unknown collector {}, available ones are: {:?}", name, collectors::names()));
+            }
+        }
+        Ok(collectors::REGISTRY
+            .iter()
+            .cloned()
+            .filter(|c| self.collectors.is_empty() || self.collectors.iter().any(|name| name == c.name()))
+            .collect())
+    }
+
+    // Whether the collector has anything to do on the configured model, and any of its measurements
+    // pass the metric filter
+    pub fn wants(&self, collector: &dyn Collector) -> bool {
+        if !collector.configured(self) {
+            return false;
+        }
+        // A detected model is only known once logged in, see collectors::run
+        if self.model.as_deref().and_then(Model::from_name).is_some_and(|model| !collector.supports(model)) {
+            return false;
+        }
+        let filter = self.metric_filter();
+        collector.measurements().iter().any(|m| filter.matches(m.name))
+    }
+}
+
+// Whether the setting can take the value
+fn setting_fits(key: &str, value: Value) -> bool {
+    let mut table = Table::new();
+    insert_setting(&mut table, key, value);
+    Value::Table(table).try_into::<Config>().is_ok()
+}
+
+// Sets a setting like "intervals__devices" in the table, making the tables on the way
+fn insert_setting(table: &mut Table, key: &str, value: Value) {
+    match key.split_once("__") {
+        Some((name, rest)) => {
+            let entry = table.entry(name.to_string()).or_insert_with(|| Value::Table(Table::new()));
+            if !entry.is_table() {
+                *entry = Value::Table(Table::new());
+            }
+            if let Value::Table(nested) = entry {
+                insert_setting(nested, rest, value);
+            }
+        }
+        None => {
+            table.insert(key.to_string(), value);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+// Recognizing devices that make up their MAC addresses
+//
+// Phones and laptops use a made up (locally administered) MAC per network, and some make up a new one
+// every so often, so the one phone turns into dozens of devices over time, each with a bit of the
+// presence history and a new device notification. With correlate_devices, a device with a made up MAC
+// and the host name of one seen before is taken to be that one, and known by the MAC it was first seen
+// with. The router doesn't give DHCP fingerprints, so the host name is all there is to go on, and
+// devices without one are left alone. Each made up MAC seen is kept in `device-aliases` in the state
+// directory, along with the first one and the host name, so the presence config can use any of them.
+
+use crate::config::Config;
+use crate::hostnames::{is_random_mac, normalize_mac};
+use crate::router::Device;
+use crate::state;
+use crate::{FetchError, FiosClient};
+use tracing::{debug, info};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+pub struct Correlator {
+    path: PathBuf,
+}
+
+impl Correlator {
+    // None when devices aren't correlated
+    pub fn from_config(config: &Config) -> Option<Correlator> {
+        if !config.correlate_devices {
+            return None;
+        }
+        Some(Correlator { path: state::file(config, "device-aliases") })
+    }
+
+    // Gives the devices with a made up MAC the first MAC seen with their host name
+    pub fn apply(&self, devices: &mut [Device]) -> Result<(), String> {
+        let mut aliases = self.load()?;
+        // Host name -> first MAC
+        let mut firsts: HashMap<String, String> =
+            aliases.values().map(|(first, name)| (name.to_lowercase(), first.clone())).collect();
+        let mut changed = false;
+        for device in devices.iter_mut() {
+            let (mac, name) = match (&device.mac, &device.name) {
+                (Some(mac), Some(name)) if !name.is_empty() && is_random_mac(mac) => (normalize_mac(mac), name.clone()),
+                _ => continue,
+            };
+            let first = firsts.entry(name.to_lowercase()).or_insert_with(|| mac.clone()).clone();
+            if let Entry::Vacant(entry) = aliases.entry(mac.clone()) {
+                info!("Recording {} for {}, the first MAC seen with that name is {}", mac, name, first);
+                entry.insert((first.clone(), name));
+                changed = true;
+            }
+            if first != mac {
+                debug!("Taking {} to be {}", mac, first);
+                device.mac = Some(first);
+            }
+        }
+        if changed {
+            let values = aliases.into_iter().map(|(mac, (first, name))| (mac, format!("{} {}", first, name))).collect();
+            state::save_values(&self.path, &values)?;
+        }
+        Ok(())
+    }
+
+    // Each made up MAC seen -> the first MAC seen with its host name
+    pub fn first_macs(&self) -> Result<HashMap<String, String>, String> {
+        Ok(self.load()?.into_iter().map(|(mac, (first, _))| (mac, first)).collect())
+    }
+
+    // "MAC first-MAC host name" lines, as MAC -> (first MAC, host name)
+    fn load(&self) -> Result<BTreeMap<String, (String, String)>, String> {
+        Ok(state::load_values(&self.path)?
+            .into_iter()
+            .filter_map(|(mac, value)| {
+                let (first, name) = value.split_once(char::is_whitespace)?;
+                Some((mac, (first.to_string(), name.trim().to_string())))
+            })
+            .collect())
+    }
+}
+
+// The router's devices, correlated when that's on
+pub fn devices(session: &FiosClient, config: &Config) -> Result<Vec<Device>, FetchError> {
+    let mut devices = session.devices()?;
+    if let Some(correlator) = Correlator::from_config(config) {
+        correlator.apply(&mut devices).map_err(simple_error::SimpleError::new)?;
+    }
+    Ok(devices)
+}
@@ -0,0 +1,109 @@
+// Dynamic DNS updates, when the WAN IP changes (see wan.rs)
+//
+// Supported are Cloudflare (an A record, through their API), DuckDNS, and any provider with an
+// update URL, where {hostname} and {ip} are filled in.
+
+use crate::config::Ddns;
+use crate::FetchError;
+use serde_json::{json, Value};
+use tracing::info;
+
+const CLOUDFLARE_API: &str = "https://api.cloudflare.com/client/v4";
+const DUCKDNS_UPDATE: &str = "https://www.duckdns.org/update";
+
+type Client = reqwest::blocking::Client;
+
+pub fn update(client: &Client, ddns: &Ddns, ip: &str) -> Result<(), FetchError> {
+    match ddns.provider.as_str() {
+        "cloudflare" => cloudflare(client, ddns, ip)?,
+        "duckdns" => duckdns(client, ddns, ip)?,
+        "url" => url(client, ddns, ip)?,
+        provider => bail!("Unknown DDNS provider {}, supported ones are cloudflare, duckdns, and url", provider),
+    }
+    info!("Updated {} to {} with {}", ddns.hostname, ip, ddns.provider);
+    Ok(())
+}
+
+fn token(ddns: &Ddns) -> Result<String, FetchError> {
+    if let Some(token) = &ddns.token {
+        return Ok(token.clone());
+    }
+    match &ddns.token_file {
+        Some(path) => match std::fs::read_to_string(path) {
+            Ok(token) => Ok(token.trim().to_string()),
+            Err(e) => bail!("Could not read DDNS token file {}: {}", path, e),
+        },
+        None => bail!("No token given for {}", ddns.provider),
+    }
+}
+
+fn cloudflare(client: &Client, ddns: &Ddns, ip: &str) -> Result<(), FetchError> {
+    let token = token(ddns)?;
+    let zone = match &ddns.zone_id {
+        Some(zone) => zone,
+        None => bail!("Cloudflare needs the zone_id of {}", ddns.hostname),
+    };
+    let call = |request: reqwest::blocking::RequestBuilder, api: &str| -> Result<Value, FetchError> {
+        let response = request.bearer_auth(&token).send()?;
+        let status = response.status();
+        let body = response.text()?;
+        let data: Value = serde_json::from_str(&body).map_err(|e| FetchError::parse(api, &body, e))?;
+        if !status.is_success() {
+            return Err(FetchError::status(api, status, &data["errors"].to_string()));
+        }
+        if data["success"] != Value::Bool(true) {
+            return Err(FetchError::response(api, "request failed", &data["errors"].to_string()));
+        }
+        Ok(data)
+    };
+
+    let records = format!("{}/zones/{}/dns_records", CLOUDFLARE_API, zone);
+    let record = match &ddns.record_id {
+        Some(record) => record.clone(),
+        None => {
+            let data = call(client.get(&records).query(&[("type", "A"), ("name", ddns.hostname.as_str())]), &records)?;
+            match data.pointer("/result/0/id").and_then(Value::as_str) {
+                Some(record) => record.to_string(),
+                None => bail!("No A record for {} in Cloudflare zone {}", ddns.hostname, zone),
+            }
+        }
+    };
+    let uri = format!("{}/{}", records, record);
+    let body = json!({"type": "A", "name": ddns.hostname, "content": ip}).to_string();
+    call(client.patch(&uri).header(reqwest::header::CONTENT_TYPE, "application/json").body(body), &uri)?;
+    Ok(())
+}
+
+// DuckDNS wants the subdomain, and answers with OK or KO
+fn duckdns(client: &Client, ddns: &Ddns, ip: &str) -> Result<(), FetchError> {
+    let token = token(ddns)?;
+    let domain = ddns.hostname.trim_end_matches(".duckdns.org");
+    let response = client.get(DUCKDNS_UPDATE)
+        .query(&[("domains", domain), ("token", token.as_str()), ("ip", ip)])
+        .send()?;
+    let status = response.status();
+    let body = response.text()?;
+    if !status.is_success() {
+        return Err(FetchError::status(DUCKDNS_UPDATE, status, &body));
+    }
+    if body.trim() != "OK" {
+        return Err(FetchError::response(DUCKDNS_UPDATE, "update refused", &body));
+    }
+    Ok(())
+}
+
+fn url(client: &Client, ddns: &Ddns, ip: &str) -> Result<(), FetchError> {
+    let template = match &ddns.url {
+        Some(url) => url,
+        None => bail!("The url DDNS provider needs an update url"),
+    };
+    let uri = template.replace("{hostname}", &ddns.hostname).replace("{ip}", ip);
+    let response = client.get(&uri).send()?;
+    let status = response.status();
+    let body = response.text()?;
+    if !status.is_success() {
+        // Reported as the template, the filled in URI likely has a password in it
+        return Err(FetchError::status(template, status, &body));
+    }
+    Ok(())
+}
@@ -0,0 +1,139 @@
+// Finding the router on the local network
+//
+// Candidates come from SSDP (UPnP internet gateway devices answering an M-SEARCH), the default
+// route, and the myfiosgateway.com name the routers hand out over DHCP. Each of them is then probed
+// to see whether it is a router we know, and which model.
+
+use crate::router::{self, Model};
+use crate::{Config, Gateway, DEFAULT_GATEWAY};
+use tracing::debug;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+const SSDP_ADDR: &str = "239.255.255.250:1900";
+const SSDP_SEARCH: &str = "M-SEARCH * HTTP/1.1\r\n\
+                           HOST: 239.255.255.250:1900\r\n\
+                           MAN: \"ssdp:discover\"\r\n\
+                           MX: 2\r\n\
+                           ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\r\n";
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub address: IpAddr,
+    // How it was found, e.g. "ssdp"
+    pub sources: Vec<&'static str>,
+    // The SERVER header of the SSDP response
+    pub server: Option<String>,
+    // Set when the probe recognized the router
+    pub model: Option<Model>,
+}
+
+pub fn discover(timeout: Duration) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = Vec::new();
+    let mut add = |address: IpAddr, source: &'static str, server: Option<String>| {
+        match candidates.iter_mut().find(|c| c.address == address) {
+            Some(candidate) => {
+                candidate.sources.push(source);
+                candidate.server = candidate.server.take().or(server);
+            }
+            None => candidates.push(Candidate { address, sources: vec![source], server, model: None }),
+        }
+    };
+
+    for (address, server) in ssdp(timeout) {
+        add(address, "ssdp", server);
+    }
+    if let Some(address) = default_route() {
+        add(IpAddr::V4(address), "default route", None);
+    }
+    if let Ok(addresses) = (DEFAULT_GATEWAY, 443).to_socket_addrs() {
+        for address in addresses {
+            add(address.ip(), DEFAULT_GATEWAY, None);
+        }
+    }
+
+    for candidate in candidates.iter_mut() {
+        candidate.model = probe(candidate.address);
+    }
+    // The recognized routers first
+    candidates.sort_by_key(|c| c.model.is_none());
+    candidates
+}
+
+fn ssdp(timeout: Duration) -> Vec<(IpAddr, Option<String>)> {
+    let mut found = Vec::new();
+    let socket = match UdpSocket::bind("0.0.0.0:0") {
+        Ok(socket) => socket,
+        Err(e) => {
+            debug!("Could not open a socket for SSDP: {}", e);
+            return found;
+        }
+    };
+    if let Err(e) = socket.send_to(SSDP_SEARCH.as_bytes(), SSDP_ADDR) {
+        debug!("Could not send SSDP search: {}", e);
+        return found;
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 2048];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left == Duration::from_secs(0) || socket.set_read_timeout(Some(left)).is_err() {
+            break;
+        }
+        match socket.recv_from(&mut buf) {
+            Ok((len, from)) => {
+                let response = String::from_utf8_lossy(&buf[..len]);
+                debug!("SSDP response from {}:\n{}", from, response);
+                let server = response
+                    .lines()
+                    .filter_map(|line| line.split_once(':'))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("server"))
+                    .map(|(_, value)| value.trim().to_string());
+                found.push((from.ip(), server));
+            }
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => break,
+            Err(e) => {
+                debug!("SSDP receive failed: {}", e);
+                break;
+            }
+        }
+    }
+    found
+}
+
+// The gateway of the default IPv4 route. Only known on Linux, from /proc/net/route where the
+// addresses are hex in host byte order
+fn default_route() -> Option<Ipv4Addr> {
+    let routes = std::fs::read_to_string("/proc/net/route").ok()?;
+    routes.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [_, "00000000", gateway, ..] => {
+                let gateway = u32::from_str_radix(gateway, 16).ok()?;
+                Some(Ipv4Addr::from(gateway.to_le_bytes())).filter(|a| !a.is_unspecified())
+            }
+            _ => None,
+        }
+    })
+}
+
+fn probe(address: IpAddr) -> Option<Model> {
+    let config = Config {
+        gateway: Some(address.to_string()),
+        insecure: true,
+        ..Config::default()
+    };
+    let gateway = Gateway::from_config(&config).ok()?;
+    let client = gateway.client_builder().timeout(PROBE_TIMEOUT).build().ok()?;
+    match router::detect(&client, &gateway) {
+        Ok(model) => Some(model),
+        Err(e) => {
+            debug!("{} doesn't look like a router we know: {}", address, e);
+            None
+        }
+    }
+}
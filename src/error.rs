@@ -0,0 +1,150 @@
+// Errors from talking to the router and the sinks
+//
+// Where we know them, the variants carry the API path, HTTP status, and the start of the response
+// body, since "unexpected response" alone says nothing about which firmware quirk we ran into.
+
+use thiserror::Error;
+
+// How much of a response body to keep in an error
+const SNIPPET_LENGTH: usize = 200;
+
+#[derive(Debug, Error)]
+pub enum FetchError {
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Invalid URL: {0}")]
+    Url(#[from] url::ParseError),
+    #[error("Invalid header value: {0}")]
+    Header(#[from] reqwest::header::InvalidHeaderValue),
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Unexpected status {status} from {api}{}", with_body(.body))]
+    Status {
+        api: String,
+        status: reqwest::StatusCode,
+        body: String,
+    },
+    #[error("Could not parse the response from {api}: {source}{}", with_body(.body))]
+    Parse {
+        api: String,
+        body: String,
+        source: serde_json::Error,
+    },
+    #[error("Unexpected response from {api}: {message}{}", with_body(.body))]
+    Response {
+        api: String,
+        message: String,
+        body: String,
+    },
+    #[error("{0}")]
+    Simple(#[from] simple_error::SimpleError),
+    #[error("{0}")]
+    Auth(#[from] AuthError),
+    // Writing to one of the sinks failed
+    #[error("Could not write to sink: {0}")]
+    Sink(#[source] Box<FetchError>),
+}
+
+// The ways logging in to the router can fail
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("Wrong router password")]
+    WrongPassword,
+    #[error("The router is refusing logins for {0}s after too many failed attempts, try again after that")]
+    LockedOut(u64),
+    #[error("The router already has the maximum number of users logged in. Log out of the admin page, \
+             or wait for the other sessions to time out")]
+    TooManySessions,
+    #[error("The router did not send the {0} cookie when logging in")]
+    MissingCookie(String),
+    #[error("Could not login: {status}{}", with_body(.body))]
+    Failed {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+}
+
+// Exit codes, so scripts and systemd OnFailure= handlers can tell the failures apart
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_AUTH: i32 = 3;
+pub const EXIT_UNREACHABLE: i32 = 4;
+pub const EXIT_PARSE: i32 = 5;
+pub const EXIT_SINK: i32 = 6;
+pub const EXIT_LOCKED_OUT: i32 = 7;
+pub const EXIT_TOO_MANY_SESSIONS: i32 = 8;
+
+impl FetchError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            FetchError::Auth(AuthError::LockedOut(_)) => EXIT_LOCKED_OUT,
+            FetchError::Auth(AuthError::TooManySessions) => EXIT_TOO_MANY_SESSIONS,
+            FetchError::Auth(_) => EXIT_AUTH,
+            FetchError::Http(e) if e.is_connect() || e.is_timeout() => EXIT_UNREACHABLE,
+            FetchError::Json(_) | FetchError::Parse { .. } | FetchError::Response { .. } => EXIT_PARSE,
+            FetchError::Sink(_) => EXIT_SINK,
+            _ => EXIT_ERROR,
+        }
+    }
+
+    // Whether there was no answer at all, rather than a wrong one
+    pub fn is_unreachable(&self) -> bool {
+        self.exit_code() == EXIT_UNREACHABLE
+    }
+
+    // Whether the router doesn't take the session any more, e.g. after it expired, so only logging in
+    // again helps
+    pub fn is_session_lost(&self) -> bool {
+        match self {
+            FetchError::Auth(_) => true,
+            FetchError::Status { status, .. } => {
+                *status == reqwest::StatusCode::UNAUTHORIZED || *status == reqwest::StatusCode::FORBIDDEN
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn status(api: &str, status: reqwest::StatusCode, body: &str) -> FetchError {
+        FetchError::Status {
+            api: api.to_string(),
+            status,
+            body: snippet(body),
+        }
+    }
+
+    pub(crate) fn parse(api: &str, body: &str, source: serde_json::Error) -> FetchError {
+        FetchError::Parse {
+            api: api.to_string(),
+            body: snippet(body),
+            source,
+        }
+    }
+
+    pub(crate) fn response(api: &str, message: &str, body: &str) -> FetchError {
+        FetchError::Response {
+            api: api.to_string(),
+            message: message.to_string(),
+            body: snippet(body),
+        }
+    }
+}
+
+impl From<&str> for FetchError {
+    fn from(err: &str) -> FetchError {
+        FetchError::Simple(simple_error::SimpleError::new(err))
+    }
+}
+
+pub(crate) fn snippet(body: &str) -> String {
+    match body.char_indices().nth(SNIPPET_LENGTH) {
+        Some((end, _)) => format!("{}...", &body[..end]),
+        None => body.to_string(),
+    }
+}
+
+fn with_body(body: &str) -> String {
+    if body.is_empty() {
+        String::new()
+    } else {
+        format!(", response: {}", body)
+    }
+}
@@ -0,0 +1,68 @@
+// Noticing firmware updates, which Verizon pushes without asking
+//
+// Like the WAN address (see wan.rs), the last firmware version seen for each gateway is kept in a
+// file, so updates are noticed across one-off runs as well as in daemon mode. An update is reported as
+// a firmware_changed data point tagged with the old and new version, and sent as a notification, so a
+// change in how the router behaves can be lined up with it.
+
+use crate::config::Config;
+use crate::metrics::Metric;
+use crate::notify::{self, Event, Notifier};
+use crate::state;
+use crate::{FetchError, FiosClient};
+use tracing::{debug, info};
+use std::path::PathBuf;
+
+pub struct Watcher {
+    gateway: String,
+    path: PathBuf,
+    notifier: Notifier,
+}
+
+impl Watcher {
+    // None when the firmware isn't watched
+    pub fn from_config(config: &Config) -> Result<Option<Watcher>, String> {
+        if !config.watch_firmware {
+            return Ok(None);
+        }
+        Ok(Some(Watcher {
+            gateway: config.gateway().to_string(),
+            path: state::file(config, "firmware"),
+            notifier: Notifier::from_config(config)?,
+        }))
+    }
+
+    // Compares the firmware version with the last one seen. The first time around it's just recorded,
+    // and when the notification fails the new version isn't, so it's tried again the next time.
+    pub fn check(&self, client: &reqwest::blocking::Client, session: &FiosClient) -> Result<Vec<Metric>, FetchError> {
+        let version = session.firmware_version()?;
+        let mut known = state::load_values(&self.path).map_err(simple_error::SimpleError::new)?;
+        let old = match known.get(&self.gateway) {
+            Some(old) if *old == version => return Ok(Vec::new()),
+            old => old.cloned(),
+        };
+
+        match &old {
+            Some(old) => {
+                info!("Router firmware changed from {} to {}", old, version);
+                let event = Event::new(
+                    notify::FIRMWARE,
+                    "Router firmware updated",
+                    &format!("{} is now running {}, was {}", self.gateway, version, old),
+                )
+                .field("gateway", self.gateway.as_str())
+                .field("old_version", old.as_str())
+                .field("new_version", version.as_str());
+                self.notifier.send(client, &event)?;
+            }
+            None => debug!("Router firmware is {}", version),
+        }
+        known.insert(self.gateway.clone(), version.clone());
+        state::save_values(&self.path, &known).map_err(simple_error::SimpleError::new)?;
+
+        Ok(old
+            .map(|old| Metric::new("firmware_changed", 1).tag("old_version", &old).tag("new_version", &version))
+            .into_iter()
+            .collect())
+    }
+}
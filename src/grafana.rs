@@ -0,0 +1,149 @@
+// A Grafana dashboard for the data we write, for `fios-stats grafana-dashboard`
+//
+// It's made from the collectors' measurements, so new collectors show up without editing a JSON file.
+// The datasource is left as an input, which Grafana asks for when importing. Every panel can be
+// narrowed down to some routers with the host variable.
+
+use crate::collectors;
+use crate::metrics::Unit;
+use serde_json::{json, Value};
+
+pub const DATASOURCES: [&str; 1] = ["influx"];
+
+// The measurements from outside the collectors that get a panel, see metrics::DAEMON_MEASUREMENTS
+const DAEMON_MEASUREMENTS: [&str; 8] = [
+    "router_up",
+    "router_outage",
+    "collector_success",
+    "collector_errors",
+    "schema_unknown_fields",
+    "alert_firing",
+    "wan_ip_changed",
+    "usage_month_bytes",
+];
+
+const WIDTH: u64 = 12;
+const HEIGHT: u64 = 8;
+
+pub fn dashboard(datasource: &str, unit: Unit) -> Result<Value, String> {
+    if !DATASOURCES.contains(&datasource) {
+        return Err(format!("Unknown datasource {}, use one of {:?}", datasource, DATASOURCES));
+    }
+
+    let mut panels = Vec::new();
+    let mut y = 0;
+    let rows = collectors::REGISTRY
+        .iter()
+        .map(|c| (c.name(), c.measurements().iter().map(|m| m.name).collect::<Vec<_>>()))
+        .chain(Some(("daemon", DAEMON_MEASUREMENTS.to_vec())));
+    for (row, measurements) in rows {
+        panels.push(json!({
+            "type": "row",
+            "title": row,
+            "collapsed": false,
+            "gridPos": {"h": 1, "w": 2 * WIDTH, "x": 0, "y": y},
+            "panels": [],
+        }));
+        y += 1;
+        for (i, measurement) in measurements.iter().enumerate() {
+            let x = (i as u64 % 2) * WIDTH;
+            panels.push(panel(measurement, unit, x, y));
+            if x > 0 || i == measurements.len() - 1 {
+                y += HEIGHT;
+            }
+        }
+    }
+    for (id, panel) in panels.iter_mut().enumerate() {
+        panel["id"] = json!(id + 1);
+    }
+
+    Ok(json!({
+        "__inputs": [{
+            "name": "DS_INFLUXDB",
+            "label": "InfluxDB",
+            "description": "Where fios-stats writes to",
+            "type": "datasource",
+            "pluginId": "influxdb",
+            "pluginName": "InfluxDB",
+        }],
+        "title": "Fios router",
+        "uid": "fios-stats",
+        "tags": ["fios-stats"],
+        "schemaVersion": 39,
+        "editable": true,
+        "refresh": "1m",
+        "time": {"from": "now-24h", "to": "now"},
+        "templating": {"list": [{
+            "name": "host",
+            "label": "Router",
+            "type": "query",
+            "datasource": datasource_ref(),
+            "query": "SHOW TAG VALUES WITH KEY = \"host\"",
+            "refresh": 1,
+            "includeAll": true,
+            "multi": true,
+            "current": {"text": "All", "value": "$__all"},
+        }]},
+        "panels": panels,
+    }))
+}
+
+fn datasource_ref() -> Value {
+    json!({"type": "influxdb", "uid": "${DS_INFLUXDB}"})
+}
+
+fn panel(measurement: &str, unit: Unit, x: u64, y: u64) -> Value {
+    // The traffic is stored per minute, but is easier to read per second
+    let (select, grafana_unit) = match measurement {
+        "net_rx" | "net_tx" => ("mean(\"value\") / 60", if unit == Unit::Bytes { "Bps" } else { "bps" }),
+        "device_last_seen" => ("last(\"value\") * 1000", "dateTimeFromNow"),
+        "router_outage" => ("max(\"value\")", "s"),
+        "collector_errors" => ("last(\"value\")", "short"),
+        "usage_month_bytes" => ("last(\"value\")", "decbytes"),
+        // Totals since the router started
+        "ssid_rx_bytes" | "ssid_tx_bytes" => ("non_negative_derivative(max(\"value\"), 1s)", "Bps"),
+        "wifi_retries" | "wifi_failed_frames" => ("non_negative_derivative(max(\"value\"), 1m)", "short"),
+        "wifi_airtime_percent" => ("mean(\"value\")", "percent"),
+        "interface_state_change" => ("count(\"value\")", "short"),
+        "dns_latency_ms" | "probe_latency_ms" => ("mean(\"value\")", "ms"),
+        "probe_latency_max_ms" => ("max(\"value\")", "ms"),
+        "probe_loss_percent" => ("mean(\"value\")", "percent"),
+        _ => ("mean(\"value\")", "short"),
+    };
+    // The tag that tells the series of a measurement apart, besides the router
+    let tag = match measurement {
+        "wifi_neighbors" => Some("channel"),
+        "ssid_rx_bytes" | "ssid_tx_bytes" => Some("ssid"),
+        "wifi_retries" | "wifi_failed_frames" | "wifi_airtime_percent" => Some("band"),
+        "interface_up" | "interface_state_change" => Some("interface"),
+        "device_present" | "device_last_seen" => Some("device"),
+        "alert_firing" => Some("alert"),
+        "collector_success" | "collector_errors" => Some("collector"),
+        "schema_unknown_fields" => Some("api"),
+        _ => None,
+    };
+    let (group_by, alias) = match tag {
+        Some(tag) => (format!(", \"host\", \"{}\"", tag), format!("$tag_host $tag_{}", tag)),
+        None => (", \"host\"".to_string(), "$tag_host".to_string()),
+    };
+    let query = format!(
+        "SELECT {} FROM \"{}\" WHERE \"host\" =~ /^$host$/ AND $timeFilter GROUP BY time($__interval){} fill(null)",
+        select, measurement, group_by
+    );
+    json!({
+        "type": "timeseries",
+        "title": measurement,
+        "datasource": datasource_ref(),
+        "gridPos": {"h": HEIGHT, "w": WIDTH, "x": x, "y": y},
+        "fieldConfig": {"defaults": {"unit": grafana_unit}, "overrides": []},
+        "options": {"legend": {"displayMode": "list", "placement": "bottom"}},
+        "targets": [{
+            "refId": "A",
+            "datasource": datasource_ref(),
+            "rawQuery": true,
+            "resultFormat": "time_series",
+            "query": query,
+            "alias": alias,
+        }],
+    })
+}
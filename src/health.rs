@@ -0,0 +1,93 @@
+// A health check endpoint for daemon mode, for Docker's HEALTHCHECK and Kubernetes probes
+//
+// Any GET on the address answers 200 while the daemon keeps completing cycles, and 503 once it's
+// gone more than a few intervals without one, e.g. because it's stuck waiting on the router. The
+// router being down doesn't make the daemon unhealthy, restarting wouldn't help, but the body says so.
+// It's plain HTTP on a thread of its own, so it works without the web feature.
+
+use crate::FetchError;
+use serde_json::json;
+use tracing::{debug, info};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How many intervals can go by without a cycle
+const MISSED_CYCLES: u32 = 3;
+
+#[derive(Clone)]
+pub struct Health {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    // Instants rather than times, so a clock jump doesn't make it unhealthy
+    started: Instant,
+    last_cycle: Option<Instant>,
+    router_up: bool,
+    max_age: Duration,
+}
+
+impl Health {
+    // Starts answering on an address like 0.0.0.0:8080. The interval is the shortest collector one.
+    pub fn start(address: &str, interval: Duration) -> Result<Health, FetchError> {
+        let listener = match TcpListener::bind(address) {
+            Ok(listener) => listener,
+            Err(e) => bail!("Could not listen on {}: {}", address, e),
+        };
+        info!("Serving the health check on http://{}/health", address);
+        let health = Health {
+            state: Arc::new(Mutex::new(State {
+                started: Instant::now(),
+                last_cycle: None,
+                router_up: false,
+                max_age: interval * MISSED_CYCLES,
+            })),
+        };
+        let server = health.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = server.respond(stream) {
+                    debug!("Health check request failed: {}", e);
+                }
+            }
+        });
+        Ok(health)
+    }
+
+    pub fn record_cycle(&self, router_up: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.last_cycle = Some(Instant::now());
+        state.router_up = router_up;
+    }
+
+    fn respond(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        // Only the request line matters, the path and headers are ignored
+        let mut request = String::new();
+        BufReader::new(&stream).read_line(&mut request)?;
+
+        let (healthy, body) = {
+            let state = self.state.lock().unwrap();
+            // Before the first cycle it's counted from the start
+            let age = state.last_cycle.unwrap_or(state.started).elapsed();
+            let healthy = age <= state.max_age;
+            let body = json!({
+                "status": if healthy { "ok" } else { "stalled" },
+                "last_cycle_seconds_ago": state.last_cycle.map(|t| t.elapsed().as_secs()),
+                "router_up": state.router_up,
+            });
+            (healthy, body.to_string())
+        };
+        let status = if healthy { "200 OK" } else { "503 Service Unavailable" };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+    }
+}
@@ -0,0 +1,119 @@
+// Names for the devices, for the per-device data, the notifications, and the dashboards
+//
+// The per-device data points, like device_present, are tagged with the device's MAC address, which
+// says little in a graph. With hostname_tags they also get a hostname tag, from the router's table of
+// its devices (the name a device gave over DHCP, or the one set in the admin page).
+//
+// Those names are often no help, e.g. "android-9f3a", so devices can be given nicknames, which win
+// over the router's names and are used without hostname_tags too. device_file is a file to keep them
+// in, with a "MAC,name[,category]" line per device and # for comments, read again whenever it's used,
+// so it can be edited while the daemon runs. The category becomes a category tag. [device_names] in
+// the config names devices as well, over the file.
+
+use crate::config::Config;
+use crate::metrics::Metric;
+use crate::router::Device;
+use crate::{FetchError, FiosClient};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Nickname {
+    pub name: String,
+    // e.g. "phone" or "iot"
+    pub category: Option<String>,
+}
+
+// Routers and people write MACs differently, e.g. AA-BB-CC-DD-EE-FF
+pub fn normalize_mac(mac: &str) -> String {
+    mac.trim().to_lowercase().replace('-', ":")
+}
+
+// Whether a MAC is made up by the device rather than assigned by the maker, i.e. locally administered
+pub fn is_random_mac(mac: &str) -> bool {
+    let hex: String = normalize_mac(mac).chars().filter(char::is_ascii_hexdigit).collect();
+    hex.get(0..2).and_then(|first| u8::from_str_radix(first, 16).ok()).is_some_and(|first| first & 0x02 != 0)
+}
+
+// The nicknames from the device_file and [device_names], by normalized MAC
+pub fn nicknames(config: &Config) -> Result<HashMap<String, Nickname>, String> {
+    let mut nicknames = match &config.device_file {
+        Some(path) => load_file(Path::new(path))?,
+        None => HashMap::new(),
+    };
+    // Keeping the category from the file
+    for (mac, name) in &config.device_names {
+        let mac = normalize_mac(mac);
+        let category = nicknames.get(&mac).and_then(|n| n.category.clone());
+        nicknames.insert(mac, Nickname { name: name.clone(), category });
+    }
+    Ok(nicknames)
+}
+
+pub fn load_file(path: &Path) -> Result<HashMap<String, Nickname>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let mut nicknames = HashMap::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        match fields[..] {
+            [mac, name] | [mac, name, ""] if !mac.is_empty() && !name.is_empty() => {
+                nicknames.insert(normalize_mac(mac), Nickname { name: name.to_string(), category: None })
+            }
+            [mac, name, category] if !mac.is_empty() && !name.is_empty() => nicknames.insert(
+                normalize_mac(mac),
+                Nickname { name: name.to_string(), category: Some(category.to_string()) },
+            ),
+            _ => return Err(format!("{} line {}: expected MAC,name[,category], got {}", path.display(), number + 1, line)),
+        };
+    }
+    Ok(nicknames)
+}
+
+// Gives the devices from the router their nicknames
+pub fn rename(devices: &mut [Device], nicknames: &HashMap<String, Nickname>) {
+    for device in devices {
+        if let Some(nickname) = device.mac.as_deref().and_then(|mac| nicknames.get(&normalize_mac(mac))) {
+            device.name = Some(nickname.name.clone());
+        }
+    }
+}
+
+// Adds hostname and category tags to the data points with a mac tag. The router is only asked when
+// there are any, and hostname_tags is on.
+pub fn tag(data: &mut [Metric], session: &FiosClient, config: &Config) -> Result<(), FetchError> {
+    if !data.iter().any(|m| mac_tag(m).is_some()) {
+        return Ok(());
+    }
+    let nicknames = nicknames(config).map_err(simple_error::SimpleError::new)?;
+    if !config.hostname_tags && nicknames.is_empty() {
+        return Ok(());
+    }
+    let mut names: HashMap<String, Nickname> = HashMap::new();
+    if config.hostname_tags {
+        for device in session.devices()? {
+            if let (Some(mac), Some(name)) = (device.mac, device.name.filter(|n| !n.is_empty())) {
+                names.insert(normalize_mac(&mac), Nickname { name, category: None });
+            }
+        }
+    }
+    names.extend(nicknames);
+
+    for metric in data.iter_mut() {
+        if let Some(nickname) = mac_tag(metric).and_then(|mac| names.get(&normalize_mac(mac))).cloned() {
+            metric.tags.push(("hostname".to_string(), nickname.name));
+            if let Some(category) = nickname.category {
+                metric.tags.push(("category".to_string(), category));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn mac_tag(metric: &Metric) -> Option<&str> {
+    metric.tags.iter().find(|(key, _)| key == "mac").map(|(_, value)| value.as_str())
+}
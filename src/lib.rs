@@ -0,0 +1,55 @@
+// Fetches network stats from the Fios Quantum G1000 router
+//
+// The library side: the router API client (see client.rs), the collectors turning its responses
+// into data points, and the sinks storing them. The fios-stats binary is a command line interface
+// on top of it, and other programs can use FiosClient directly:
+//
+//   let config = Config { gateway: Some("192.168.1.1".to_string()), tofu: true, ..Config::default() };
+//   let client = FiosClient::login(&config, "secret_password")?;
+//   println!("{:?}", client.network_stats()?);
+//   client.logout()?;
+
+#[macro_use]
+extern crate simple_error;
+
+// Without either, reqwest can't talk to the routers at all, and the errors don't say why
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+compile_error!("enable native-tls or rustls");
+
+pub mod alerts;
+pub mod cardinality;
+pub mod client;
+pub mod collectors;
+pub mod config;
+pub mod correlate;
+pub mod ddns;
+pub mod discover;
+pub mod grafana;
+pub mod health;
+pub mod hostnames;
+pub mod error;
+pub mod firmware;
+pub mod metrics;
+pub mod notify;
+pub mod oui;
+pub mod output;
+pub mod prometheus;
+pub mod queue;
+pub mod ratelimit;
+pub mod redact;
+pub mod rollup;
+#[cfg(feature = "influx")]
+pub mod report;
+pub mod router;
+pub mod scheduler;
+pub mod sinks;
+pub mod state;
+pub mod tofu;
+pub mod usage;
+pub mod wan;
+pub mod web;
+
+pub use client::{FiosClient, Gateway, DEFAULT_GATEWAY};
+pub use config::Config;
+pub use error::FetchError;
+pub use router::{Device, Diagnostics, Interface, NetworkStats, RadioStats, RouterClient, SsidTraffic, WifiNetwork};
@@ -1,243 +1,277 @@
-// Fetches network stats from the Fios Quantum G1000 router
+// Command line interface for the fios_stats library
 //
-// Their admin interface fetches data using a standard JSON REST API. If there is a way to
-// authenticate that is different than how a browser does, I don't know of it. So that's what I do
-// here. And then I just call /api/network/1, parse the results, and send the data to influx db.
-//
-// The program expects the router to be found on myfiosgateway.com
-//
-// The authentication works this way:
-// 1) You call /login to get a passwordSalt.
-// 2) you take the Sha512(password + passwordSalt) to create a hash
-// 3) you call /login with {"password": hash}
-// 4) on successful login, two cookies are returned XSRF-TOKEN and Session
-// For all API calls set Session as a cookie and a header X-XSRF-TOKEN with the XSRF-TOKEN value
-
-
-// TODO
-// * add tests
-// * nicer error handling. At least for: 1) unexpected data in /network/1 return, and 2) auth errors
+// The program expects the router to be found on myfiosgateway.com, unless told otherwise with -g
 
 #[macro_use]
 extern crate simple_error;
 
-use clap::{App, Arg};
-use env_logger::{Env};
-use log::{debug, info};
-use reqwest;
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use sha2::{Sha512, Digest};
-use std::collections::HashMap;
-use tokio;
-
-#[derive(Serialize, Deserialize, Debug)]
-#[allow(non_snake_case)]
-struct LoginResponse {
-    doSetupWizard: bool,
-    requirePassword: bool,
-    passwordSalt: String,
-    isWireless: bool,
-    error: u8,
-    maxUsers: u8,
-    denyState: u8,
-    denyTimeout: u8,
-    meshNetworkEnabledStatus: bool,
-    meshUserEnabledConfig: bool,
+mod cli;
+mod commands;
+mod password;
+#[cfg(all(windows, feature = "service"))]
+mod service;
+mod setup;
+mod startup;
+#[cfg(feature = "tui")]
+mod watch;
+
+use fios_stats::error::EXIT_ERROR;
+use fios_stats::{redact, scheduler, Config, FetchError, Gateway};
+use tracing::{error, info, info_span};
+use tracing_subscriber::EnvFilter;
+
+const DOCKER_HEALTH_ADDRESS: &str = "0.0.0.0:8080";
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", redact::redact(&e.to_string()));
+        let code = match e.downcast_ref::<FetchError>() {
+            Some(e) => e.exit_code(),
+            None => EXIT_ERROR,
+        };
+        std::process::exit(code);
+    }
 }
 
-#[derive(Debug, Default)]
-struct AuthInfo {
-    token: String,
-    session: u32,
-}
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let args = cli::app().get_matches();
 
-const BASE_URI:&str = "https://myfiosgateway.com/api/";
-
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
-
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = App::new("Fios Gateway Stats Retriever")
-        .version(VERSION)
-        .author("Allan Beaufour <allan@beaufour.dk>")
-        .arg(Arg::with_name("password")
-             .short("p")
-             .long("password")
-             .value_name("PASSWORD")
-             .help("Password for router")
-             .required(true)
-             .takes_value(true))
-        .arg(Arg::with_name("influx_db")
-             .short("i")
-             .long("influxdb")
-             .value_name("URI")
-             .help("URI to InfluxDB including databasename")
-             .takes_value(true))
-        .get_matches();
-    let password = args.value_of("password").unwrap();
-
-    let env = Env::default()
-        .filter_or("MY_LOG_LEVEL", "info")
-        .write_style_or("MY_LOG_STYLE", "always");
-    env_logger::init_from_env(env);
-
-    let client = reqwest::Client::builder()
-        // Unknown CA, and I'm not sure all devices use the same...
-        .danger_accept_invalid_certs(true)
-        .build()?;
-
-    let login_info = get_login_info(&client)?;
-    debug!("Got login info: {:#?}", login_info);
-
-    let auth_info = do_login(&client, &password, &login_info.passwordSalt)?;
-    debug!("Got auth info: {:#?}", auth_info);
-
-    let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert(reqwest::header::HeaderName::from_static("x-xsrf-token"),
-                   reqwest::header::HeaderValue::from_str(&auth_info.token)?);
-    // There is a cookie store on reqwest, but to set the default x-xsrf-token header I need to
-    // create a new client...
-    headers.insert(reqwest::header::HeaderName::from_static("cookie"),
-                   reqwest::header::HeaderValue::from_str(&format!("Session={};", auth_info.session))?);
-
-    let authed_client = reqwest::Client::builder()
-        // Unknown CA, and I'm not sure all devices use the same...
-        .danger_accept_invalid_certs(true)
-        .default_headers(headers)
-        .build()?;
-
-    let raw_data = fetch_api(&authed_client, "network/1")?;
-    let data:Value = serde_json::from_str(&raw_data)?;
-    debug!("Got network response: {:#?}", data);
-
-    let rx = data["bandwidth"]["minutesRx"][0].as_u64().unwrap() * 8;
-    let tx = data["bandwidth"]["minutesTx"][0].as_u64().unwrap() * 8;
-    let errors = data["rxErrors"].as_u64().unwrap() * 8;
-    let dropped = data["rxDropped"].as_u64().unwrap() * 8;
-    info!("Data: rx = {}, tx = {}, rx errors = {}, rx dropped = {}", rx, tx, errors, dropped);
-
-    // TODO: there's also natEntriesUsed from /api/settings/system, which might be interesting to pull
-
-    if let Some(influx_db) = args.value_of("influx_db") {
-        let mut influx_map = HashMap::new();
-        influx_map.insert("net_tx", tx);
-        influx_map.insert("net_rx", rx);
-        influx_map.insert("net_rx_errors", errors);
-        influx_map.insert("net_rx_dropped", dropped);
-        let influx_data = influx_map.iter().fold(String::new(), |mut acc, (key, val)| {
-            acc.push_str(&format!("{},host=myfiosgateway.com value={}i\n", key, val));
-            acc
-        });
-        debug!("Influx data:\n{}", influx_data);
-        save_data(&client, influx_db, influx_data)?;
+    // Global arguments are also available on the subcommand
+    let (command, sub_args) = args.subcommand();
+    let args = sub_args.unwrap_or(&args);
 
-        info!("Data stored to InfluxDB");
+    match command {
+        "completions" => {
+            let shell = args.value_of("shell").unwrap().parse::<clap::Shell>()?;
+            cli::app().gen_completions_to("fios-stats", shell, &mut std::io::stdout());
+            return Ok(());
+        }
+        "man" => {
+            print!("{}", cli::man_page());
+            return Ok(());
+        }
+        _ => (),
     }
 
-    fetch_api(&authed_client, "logout")?;
-
-    Ok(())
-}
+    // RUST_LOG takes precedence, for when more fine grained control is needed
+    let level = match (args.is_present("quiet"), args.occurrences_of("verbose")) {
+        (true, _) => "error",
+        // Anything logged would end up on top of the dashboard
+        (false, 0) if command == "watch" => "off",
+        (false, 0) => "info",
+        (false, 1) => "fios_stats=debug,info",
+        (false, 2) => "debug",
+        (false, _) => "trace",
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+    #[cfg(all(windows, feature = "service"))]
+    if command == "service" && args.value_of("action") == Some("run") {
+        return service::run(filter);
+    }
+    // Logs go to stderr, so they don't get mixed up with the --format output. In Docker mode they go
+    // to stdout as JSON by default, for the log collectors.
+    let docker = args.is_present("docker");
+    let writer = if docker { redact::Writer::Stdout } else { redact::Writer::Stderr };
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(move || writer);
+    if args.value_of("log_format").map_or(docker, |format| format == "json") {
+        subscriber.json().init();
+    } else {
+        subscriber.init();
+    }
 
-fn get_login_info(client: &reqwest::Client) -> Result<LoginResponse, FetchError>
-{
-    let body = fetch_api(client, "login")?;
-    let info = serde_json::from_str(&body)?;
-    Ok(info)
-}
+    if command == "setup" {
+        setup::run()?;
+        return Ok(());
+    }
+    if command == "discover" {
+        commands::discover(args.value_of("config"), args.is_present("save"))?;
+        return Ok(());
+    }
+    if command == "service" {
+        return service(args);
+    }
+    if command == "startup" {
+        return startup::run(args);
+    }
 
-fn fetch_api(client: &reqwest::Client, api: &str) -> Result<String, FetchError> {
-    let uri = reqwest::Url::parse(&format!("{}{}", BASE_URI, api))?;
-    debug!("Fetching: {}", uri);
-    let mut response = client.get(uri).send()?;
-    let body = response.text()?;
-    Ok(body)
+    run_commands(command, args)
 }
 
-fn do_login(client: &reqwest::Client, password: &str, password_salt: &str) -> Result<AuthInfo, FetchError> {
-    let mut info = AuthInfo::default();
-
-    let mut hasher = Sha512::new();
-    hasher.input(password);
-    hasher.input(password_salt);
-    let hash = hasher.result();
-    let json = format!("{{\"password\":\"{:x}\"}}", hash);
-
-    let uri = reqwest::Url::parse(&format!("{}login", BASE_URI))?;
-    let response = client.post(uri)
-        .header(reqwest::header::CONTENT_TYPE, "application/json;charset=UTF-8")
-        .body(json)
-        .send()?;
-
-    if response.status().is_success() {
-        for cookie in response.cookies() {
-            match cookie.name() {
-                "XSRF-TOKEN" => info.token = cookie.value().to_string(),
-                "Session" => info.session = cookie.value().parse().unwrap(),
-                _ => () ,
-            }
+// Runs the command with the config file, for one router or all of them
+fn run_commands(command: &str, args: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = match args.value_of("config") {
+        Some(_) if args.is_present("docker") => bail!("In Docker mode the settings come from FIOS_* variables, not -c"),
+        Some(path) => Config::load(std::path::Path::new(path))?,
+        None if args.is_present("docker") => docker_config()?,
+        None => Config::default(),
+    };
+    let command = match command {
+        "" if config.daemon => "daemon",
+        "" => "collect",
+        command => command,
+    };
+
+    if !args.is_present("all") {
+        if let Some(profile) = args.value_of("profile") {
+            config.select_profile(profile)?;
         }
-    } else {
-        bail!("Could not login: {}", response.status());
+        cli::apply(&mut config, args);
+        return run_command(command, args, &config);
     }
 
-    Ok(info)
-}
+    if config.profiles.is_empty() {
+        bail!("--all needs profiles in the config file");
+    }
+    let mut configs = Vec::new();
+    for profile in config.profiles.keys() {
+        let mut profile_config = config.clone();
+        profile_config.select_profile(profile)?;
+        cli::apply(&mut profile_config, args);
+        configs.push((profile.as_str(), profile_config));
+    }
 
-fn save_data(client: &reqwest::Client, influx_uri:&str, data: String) -> Result<(), FetchError> {
-    debug!("Saving data to InfluxDB: {}", influx_uri);
-    let response = client.post(influx_uri)
-        .header(reqwest::header::CONTENT_TYPE, "application/json;charset=UTF-8")
-        .body(data)
-        .send()?;
+    // Collection runs in parallel, each router with its own client and session, so one that is
+    // slow or unreachable doesn't hold up the others
+    if command == "collect" || command == "daemon" {
+        let failed = std::thread::scope(|scope| {
+            let threads: Vec<_> = configs
+                .iter()
+                .map(|(profile, profile_config)| scope.spawn(move || run_profile(command, args, profile, profile_config)))
+                .collect();
+            threads.into_iter().map(|thread| thread.join().unwrap_or(false)).filter(|ok| !ok).count()
+        });
+        if failed > 0 {
+            bail!("{} of {} profiles failed", failed, configs.len());
+        }
+        return Ok(());
+    }
 
-    if response.status() != reqwest::StatusCode::NO_CONTENT {
-        bail!("Unexpected status from InfluxDB: {}", response.status());
+    // Everything else goes through the profiles in turn, carrying on past failures
+    let mut result = Ok(());
+    for (profile, profile_config) in &configs {
+        info!("Profile {}", profile);
+        if let Err(e) = run_command(command, args, profile_config) {
+            error!("Profile {} failed: {}", profile, e);
+            result = Err(e);
+        }
     }
-    Ok(())
+    result
 }
 
-#[derive(Debug)]
-enum FetchError {
-    Http(reqwest::Error),
-    Url(reqwest::UrlError),
-    Json(serde_json::Error),
-    Simple(simple_error::SimpleError),
+// Docker mode runs the daemon with the health check on, unless told otherwise
+fn docker_config() -> Result<Config, Box<dyn std::error::Error>> {
+    let mut config = Config::from_env(std::env::vars(), std::path::Path::new(fios_stats::config::SECRETS_DIR))?;
+    config.daemon = true;
+    if config.health.is_none() {
+        config.health = Some(DOCKER_HEALTH_ADDRESS.to_string());
+    }
+    Ok(config)
 }
 
-impl From<reqwest::Error> for FetchError {
-    fn from(err: reqwest::Error) -> FetchError {
-        FetchError::Http(err)
+// The errors can't be sent between threads, so they are logged here instead of returned
+fn run_profile(command: &str, args: &clap::ArgMatches, profile: &str, config: &Config) -> bool {
+    let _profile = info_span!("profile", name = profile).entered();
+    match run_command(command, args, config) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Profile {} failed: {}", profile, e);
+            false
+        }
     }
 }
 
-impl From<reqwest::UrlError> for FetchError {
-    fn from(err: reqwest::UrlError) -> FetchError {
-        FetchError::Url(err)
+fn run_command(command: &str, args: &clap::ArgMatches, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    redact::register_config(config);
+    let mut gateway = Gateway::from_config(config)?;
+    // One client for the router and the sinks alike, so the connections get reused
+    let client = gateway.client_builder().build()?;
+
+    if command == "init-influx" {
+        commands::init_influx(&client, config)?;
+        return Ok(());
+    }
+    if command == "report" {
+        commands::report(&client, config, args.value_of("period").unwrap(), args.is_present("send"))?;
+        return Ok(());
+    }
+    if command == "list-collectors" {
+        commands::list_collectors(config)?;
+        return Ok(());
+    }
+    if command == "schema" {
+        commands::schema(config)?;
+        return Ok(());
+    }
+    if command == "update-oui" {
+        commands::update_oui(&client, config)?;
+        return Ok(());
+    }
+    if command == "grafana-dashboard" {
+        commands::grafana_dashboard(config, args.value_of("datasource").unwrap())?;
+        return Ok(());
     }
-}
 
-impl From<serde_json::Error> for FetchError {
-    fn from(err: serde_json::Error) -> FetchError {
-        FetchError::Json(err)
+    // When logging in to save the password, we don't want the one already in the keyring
+    let password = password::get_password(config, command != "login")?;
+    redact::register(&password);
+    gateway.trust_on_first_use(&client)?;
+
+    match command {
+        "collect" => {
+            let collectors = cli::collectors(args, config)?;
+            commands::collect(&client, &gateway, &password, config, &collectors, args.is_present("sparkline"))?
+        }
+        "daemon" => scheduler::run(&client, &gateway, &password, config)?,
+        "watch" => watch(&client, &gateway, &password, config, args)?,
+        "dump" => commands::dump(&client, &gateway, &password, args.value_of("api").unwrap())?,
+        "diagnostics" => commands::diagnostics(&client, &gateway, &password)?,
+        "check" => commands::check(&client, &gateway, &password, config)?,
+        "selftest" => commands::selftest(&client, &gateway, &password, config)?,
+        "backup" => commands::backup(&client, &gateway, &password, std::path::Path::new(args.value_of("output").unwrap()))?,
+        "reboot" => commands::reboot(&client, &gateway, &password)?,
+        "login" => commands::save_login(&client, &gateway, &password, config, args.is_present("save"))?,
+        _ => unreachable!(),
     }
+
+    Ok(())
 }
 
-impl From<simple_error::SimpleError> for FetchError {
-    fn from(err: simple_error::SimpleError) -> FetchError {
-        FetchError::Simple(err)
+#[cfg(all(windows, feature = "service"))]
+fn service(args: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    match args.value_of("action").unwrap() {
+        "install" => service::install(args),
+        "uninstall" => service::uninstall(),
+        // Only the service manager runs it, see run()
+        _ => bail!("`service run` is for the Windows service manager, use `daemon` to run in the foreground"),
     }
 }
 
-impl std::fmt::Display for FetchError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "fetch error")
-    }
+#[cfg(not(all(windows, feature = "service")))]
+fn service(_: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    bail!("Built without the service feature, or not for Windows, can't run as a Windows service");
 }
 
-impl std::error::Error for FetchError {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        None
-    }
+#[cfg(feature = "tui")]
+fn watch(
+    client: &reqwest::blocking::Client,
+    gateway: &Gateway,
+    password: &str,
+    config: &Config,
+    args: &clap::ArgMatches,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let refresh = args.value_of("refresh").and_then(scheduler::parse_duration);
+    watch::run(client, gateway, password, config, refresh)
+}
+
+#[cfg(not(feature = "tui"))]
+fn watch(
+    _: &reqwest::blocking::Client,
+    _: &Gateway,
+    _: &str,
+    _: &Config,
+    _: &clap::ArgMatches,
+) -> Result<(), Box<dyn std::error::Error>> {
+    bail!("Built without the tui feature, can't show the dashboard");
 }
@@ -0,0 +1,266 @@
+// The data points produced by the collectors, and their encoding for InfluxDB
+
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unit {
+    Count,
+    Bytes,
+    Bits,
+}
+
+impl Unit {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Unit::Count => "count",
+            Unit::Bytes => "bytes",
+            Unit::Bits => "bits",
+        }
+    }
+}
+
+// What gets written under a measurement name, for `fios-stats schema`. The field is always an integer
+// called value, and every point also has the host tag and the configured tags.
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+    pub name: &'static str,
+    // What the value counts, e.g. "bytes" or "ms"
+    pub unit: &'static str,
+    pub kind: Kind,
+    pub tags: &'static [&'static str],
+    pub description: &'static str,
+    // Written with Metric::bytes, so in the configured unit after convert_units()
+    pub converted: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    // A reading that can go up and down
+    Gauge,
+    // A total that only goes up, until the router restarts
+    Counter,
+}
+
+impl Kind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Gauge => "gauge",
+            Kind::Counter => "counter",
+        }
+    }
+}
+
+impl Measurement {
+    pub const fn new(name: &'static str, unit: &'static str, tags: &'static [&'static str], description: &'static str) -> Measurement {
+        Measurement { name, unit, kind: Kind::Gauge, tags, description, converted: false }
+    }
+
+    // Bytes, or bits with the default unit, see Metric::bytes
+    pub const fn bytes(name: &'static str, tags: &'static [&'static str], description: &'static str) -> Measurement {
+        Measurement { converted: true, ..Measurement::new(name, "bytes", tags, description) }
+    }
+
+    pub const fn counter(name: &'static str, unit: &'static str, tags: &'static [&'static str], description: &'static str) -> Measurement {
+        Measurement { kind: Kind::Counter, ..Measurement::new(name, unit, tags, description) }
+    }
+
+    // The unit of the data written, with the configured unit for the traffic
+    pub fn unit_in(&self, unit: Unit) -> &'static str {
+        if self.converted {
+            unit.name()
+        } else {
+            self.unit
+        }
+    }
+}
+
+// The measurements written besides the collectors' data
+pub static DAEMON_MEASUREMENTS: [Measurement; 17] = [
+    Measurement::new("collector_success", "0/1", &["collector"], "Whether the collector's last run worked"),
+    Measurement::counter("collector_errors", "count", &["collector"], "How often the collector has failed since the start"),
+    Measurement::new("schema_unknown_fields", "count", &["api"], "Fields in a router response we don't know about"),
+    Measurement::new("router_up", "0/1", &[], "Whether the router answers, in daemon mode"),
+    Measurement::new("router_down_seconds", "seconds", &[], "How long the router has been down, in daemon mode"),
+    Measurement::new("router_outage", "seconds", &[], "How long an outage lasted, written once it's over"),
+    Measurement::new("write_queue_length", "count", &[], "Points waiting for InfluxDB to come back, in daemon mode"),
+    Measurement::new("alert_firing", "0/1", &["alert"], "Whether the alert rule is firing, in daemon mode"),
+    Measurement::new("wan_ip_changed", "count", &["old_ip", "new_ip"], "A change of the public address, with watch_wan_ip"),
+    Measurement::new("firmware_changed", "count", &["old_version", "new_version"], "A firmware update, with watch_firmware"),
+    Measurement::new("usage_month_rx_bytes", "bytes", &[], "Bytes received this month, with track_usage"),
+    Measurement::new("usage_month_tx_bytes", "bytes", &[], "Bytes sent this month, with track_usage"),
+    Measurement::new("usage_month_bytes", "bytes", &[], "Bytes sent and received this month, with track_usage"),
+    Measurement::new("usage_cap_bytes", "bytes", &[], "The monthly data cap, with data_cap"),
+    Measurement::new("usage_cap_percent", "percent", &[], "How much of the data cap is used"),
+    Measurement::new("usage_projected_bytes", "bytes", &[], "The usage projected for the end of the month"),
+    Measurement::new("usage_projected_overage_bytes", "bytes", &[], "How far past the cap the projection goes"),
+];
+
+#[derive(Debug, Clone)]
+pub struct Metric {
+    pub name: String,
+    pub tags: Vec<(String, String)>,
+    pub value: u64,
+    pub unit: Unit,
+    // Unix time in seconds. Without it InfluxDB uses the time it got the data
+    pub time: Option<u64>,
+}
+
+impl Metric {
+    pub fn new(name: &str, value: u64) -> Metric {
+        Metric {
+            name: name.to_string(),
+            tags: Vec::new(),
+            value,
+            unit: Unit::Count,
+            time: None,
+        }
+    }
+
+    // A byte counter, which can be converted to bits with convert_units()
+    pub fn bytes(name: &str, value: u64) -> Metric {
+        Metric {
+            unit: Unit::Bytes,
+            ..Metric::new(name, value)
+        }
+    }
+
+    pub fn tag(mut self, key: &str, value: &str) -> Metric {
+        self.tags.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn to_line_protocol(&self) -> String {
+        let mut line = escape(&self.name);
+        for (key, value) in &self.tags {
+            line.push_str(&format!(",{}={}", escape(key), escape(value)));
+        }
+        line.push_str(&format!(" value={}i", self.value));
+        if let Some(time) = self.time {
+            line.push_str(&format!(" {}000000000", time));
+        }
+        line.push('\n');
+        line
+    }
+}
+
+pub fn to_line_protocol(metrics: &[Metric]) -> String {
+    metrics.iter().map(|m| m.to_line_protocol()).collect()
+}
+
+// Converts the byte counters to the wanted unit (Bytes or Bits). Other metrics are left alone.
+pub fn convert_units(metrics: &mut [Metric], unit: Unit) {
+    for metric in metrics.iter_mut() {
+        match (metric.unit, unit) {
+            (Unit::Bytes, Unit::Bits) => {
+                metric.value *= 8;
+                metric.unit = Unit::Bits;
+            }
+            (Unit::Bits, Unit::Bytes) => {
+                metric.value /= 8;
+                metric.unit = Unit::Bytes;
+            }
+            _ => (),
+        }
+    }
+}
+
+// Adds the common tags (host and the user configured ones) to every data point
+pub fn add_tags(metrics: &mut [Metric], tags: &BTreeMap<String, String>) {
+    for metric in metrics.iter_mut() {
+        for (key, value) in tags {
+            metric.tags.push((key.clone(), value.clone()));
+        }
+    }
+}
+
+pub fn set_time(metrics: &mut [Metric], time: u64) {
+    for metric in metrics.iter_mut() {
+        metric.time = Some(time);
+    }
+}
+
+// Limits the measurements to those matching any of the include patterns (all, if there are none),
+// and not matching any of the exclude patterns
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl Filter {
+    pub fn matches(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| glob_match(p, name)))
+            && !self.exclude.iter().any(|p| glob_match(p, name))
+    }
+}
+
+// Matches a name against a glob pattern with `*` (any number of characters) and `?` (one character)
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where to backtrack to when a * turns out to have matched too little
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// Measurement names, tag keys, and tag values need commas, spaces, and equal signs escaped
+fn escape(s: &str) -> String {
+    s.replace(',', "\\,").replace(' ', "\\ ").replace('=', "\\=")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        // Stars match any run of characters, including none
+        assert!(glob_match("*", "net_rx"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("net_*", "net_rx"));
+        assert!(glob_match("net_*", "net_"));
+        assert!(glob_match("*_bytes", "ssid_rx_bytes"));
+        assert!(glob_match("*rx*", "ssid_rx_bytes"));
+        assert!(glob_match("a*b*c", "abbbc"));
+        assert!(!glob_match("a*b*c", "abcb"));
+        // Question marks match exactly one
+        assert!(glob_match("net_?x", "net_rx"));
+        assert!(!glob_match("net_?x", "net_x"));
+        assert!(!glob_match("net_?", "net_rx"));
+        // The whole name has to match
+        assert!(!glob_match("net", "net_rx"));
+        assert!(!glob_match("rx", "net_rx"));
+        assert!(!glob_match("net_rx", "ssid_net_rx"));
+        assert!(glob_match("net_rx", "net_rx"));
+        // An empty pattern only matches an empty name
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "net_rx"));
+    }
+
+    #[test]
+    fn measurement_units() {
+        let traffic = Measurement::bytes("net_rx", &[], "Received in the last minute");
+        assert_eq!(traffic.unit_in(Unit::Bits), "bits");
+        assert_eq!(traffic.unit_in(Unit::Bytes), "bytes");
+        // Only the traffic is converted, not the other byte counts
+        let usage = DAEMON_MEASUREMENTS.iter().find(|m| m.name == "usage_month_bytes").unwrap();
+        assert_eq!(usage.unit_in(Unit::Bits), "bytes");
+    }
+}
+
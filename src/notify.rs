@@ -0,0 +1,250 @@
+// Notifications about events: alerts firing and resolving, WAN IP changes, new devices, firmware
+// updates, and reports
+//
+// Every event goes to each configured channel that wants it. The channels are a generic webhook
+// (the event as JSON), Slack, Discord, ntfy.sh, Pushover, Gotify, and email when built with the email
+// feature. Except for the webhook they get a text message, "{title}: {message}" unless the channel
+// has its own template, where the event's fields can be used too, e.g. "{new_ip}".
+
+use crate::config::{Config, Notification};
+use crate::FetchError;
+use serde_json::{json, Map, Value};
+use tracing::debug;
+
+pub const ALERT: &str = "alert";
+pub const WAN_IP: &str = "wan_ip";
+pub const NEW_DEVICE: &str = "new_device";
+pub const REPORT: &str = "report";
+pub const FIRMWARE: &str = "firmware";
+
+const EVENTS: [&str; 5] = [ALERT, WAN_IP, NEW_DEVICE, REPORT, FIRMWARE];
+
+const DEFAULT_TEMPLATE: &str = "{title}: {message}";
+const PUSHOVER_API: &str = "https://api.pushover.net/1/messages.json";
+
+const KINDS: [&str; 7] = ["webhook", "slack", "discord", "ntfy", "email", "pushover", "gotify"];
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    // One of EVENTS
+    pub kind: &'static str,
+    pub title: String,
+    pub message: String,
+    pub fields: Map<String, Value>,
+}
+
+impl Event {
+    pub fn new(kind: &'static str, title: &str, message: &str) -> Event {
+        Event {
+            kind,
+            title: title.to_string(),
+            message: message.to_string(),
+            fields: Map::new(),
+        }
+    }
+
+    pub fn field(mut self, name: &str, value: impl Into<Value>) -> Event {
+        self.fields.insert(name.to_string(), value.into());
+        self
+    }
+
+    fn render(&self, template: &str) -> String {
+        let mut text = template.replace("{title}", &self.title).replace("{message}", &self.message);
+        for (name, value) in &self.fields {
+            let value = match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            text = text.replace(&format!("{{{}}}", name), &value);
+        }
+        text
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    channels: Vec<Notification>,
+}
+
+impl Notifier {
+    pub fn from_config(config: &Config) -> Result<Notifier, String> {
+        let mut channels = config.notifications.clone();
+        // The older single purpose webhook settings
+        if let Some(url) = &config.alert_webhook {
+            channels.push(Notification::webhook(url, ALERT));
+        }
+        if let Some(url) = &config.wan_ip_webhook {
+            channels.push(Notification::webhook(url, WAN_IP));
+        }
+        for channel in &channels {
+            if !KINDS.contains(&channel.kind.as_str()) {
+                return Err(format!("Unknown notification type {}, use one of {:?}", channel.kind, KINDS));
+            }
+            let needs_token = match channel.kind.as_str() {
+                "pushover" => channel.token.is_none() || channel.user.is_none(),
+                "gotify" => channel.token.is_none() || channel.url.is_empty(),
+                _ => false,
+            };
+            if needs_token {
+                return Err(format!("{} notifications need a token (and user key for Pushover, url for Gotify)", channel.kind));
+            }
+            if channel.kind == "email" {
+                if cfg!(not(feature = "email")) {
+                    return Err("Built without the email feature, can't send email notifications".to_string());
+                }
+                if channel.from.is_none() || channel.to.is_empty() {
+                    return Err("Email notifications need from and to addresses".to_string());
+                }
+            }
+            if let Some(event) = channel.events.iter().find(|e| !EVENTS.contains(&e.as_str())) {
+                return Err(format!("Unknown notification event {}, use one of {:?}", event, EVENTS));
+            }
+        }
+        for rule in &config.alerts {
+            if let Some(name) = rule.notify.iter().find(|n| !channels.iter().any(|c| c.name.as_ref() == Some(*n))) {
+                return Err(format!("Alert {} uses notification channel {}, which isn't configured", rule.name, name));
+            }
+        }
+        Ok(Notifier { channels })
+    }
+
+    // Whether any channel wants this kind of event
+    pub fn wants(&self, kind: &str) -> bool {
+        self.channels.iter().any(|c| c.wants(kind))
+    }
+
+    // Sends the event to every channel that wants it. All of them are tried, and the first failure
+    // is returned.
+    pub fn send(&self, client: &reqwest::blocking::Client, event: &Event) -> Result<(), FetchError> {
+        self.send_to(client, event, &[])
+    }
+
+    // Like send(), but only to the channels with these names, unless none are given
+    pub fn send_to(&self, client: &reqwest::blocking::Client, event: &Event, names: &[String]) -> Result<(), FetchError> {
+        let mut result = Ok(());
+        let picked = |c: &&Notification| names.is_empty() || c.name.as_ref().is_some_and(|n| names.contains(n));
+        for channel in self.channels.iter().filter(|c| c.wants(event.kind)).filter(picked) {
+            if let Err(e) = send(client, channel, event) {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Notification {
+    fn webhook(url: &str, event: &str) -> Notification {
+        Notification {
+            kind: "webhook".to_string(),
+            url: url.to_string(),
+            events: vec![event.to_string()],
+            template: None,
+            ..Notification::default()
+        }
+    }
+
+    fn wants(&self, kind: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|e| e == kind)
+    }
+}
+
+fn send(client: &reqwest::blocking::Client, channel: &Notification, event: &Event) -> Result<(), FetchError> {
+    let text = event.render(channel.template.as_deref().unwrap_or(DEFAULT_TEMPLATE));
+    if channel.kind == "email" {
+        #[cfg(feature = "email")]
+        return send_email(channel, event, text);
+        #[cfg(not(feature = "email"))]
+        bail!("Built without the email feature, can't send email notifications");
+    }
+    let request = match channel.kind.as_str() {
+        "pushover" => client.post(PUSHOVER_API),
+        "gotify" => client.post(format!("{}/message", channel.url.trim_end_matches('/'))),
+        _ => client.post(&channel.url),
+    };
+    let token = channel.token.as_deref().unwrap_or_default();
+    let request = match channel.kind.as_str() {
+        "slack" => request.header(reqwest::header::CONTENT_TYPE, "application/json").body(json!({"text": text}).to_string()),
+        "discord" => request.header(reqwest::header::CONTENT_TYPE, "application/json").body(json!({"content": text}).to_string()),
+        "ntfy" => request.header("Title", event.title.as_str()).body(text),
+        "pushover" => {
+            let priority = channel.priority.unwrap_or(0).to_string();
+            request.form(&[
+                ("token", token),
+                ("user", channel.user.as_deref().unwrap_or_default()),
+                ("title", event.title.as_str()),
+                ("message", text.as_str()),
+                ("priority", priority.as_str()),
+            ])
+        }
+        "gotify" => request
+            .header("X-Gotify-Key", token)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(json!({"title": event.title, "message": text, "priority": channel.priority.unwrap_or(5)}).to_string()),
+        _ => {
+            let mut body = event.fields.clone();
+            body.insert("event".to_string(), Value::from(event.kind));
+            body.insert("title".to_string(), Value::from(event.title.as_str()));
+            body.insert("message".to_string(), Value::from(event.message.as_str()));
+            request.header(reqwest::header::CONTENT_TYPE, "application/json").body(Value::Object(body).to_string())
+        }
+    };
+    let response = request.send()?;
+    let status = response.status();
+    let body = response.text()?;
+    if !status.is_success() {
+        // Not the URL, for Slack and Discord it's the secret
+        return Err(FetchError::status(&format!("{} notification", channel.kind), status, &body));
+    }
+    debug!("Sent {} notification to {}", event.kind, channel.kind);
+    Ok(())
+}
+
+#[cfg(feature = "email")]
+fn send_email(channel: &Notification, event: &Event, text: String) -> Result<(), FetchError> {
+    use lettre::transport::smtp::authentication::Credentials;
+    use lettre::{Message, SmtpTransport, Transport};
+
+    let server = reqwest::Url::parse(&channel.url)?;
+    let host = match server.host_str() {
+        Some(host) => host,
+        None => bail!("No host in the email server {}", channel.url),
+    };
+    let transport = match server.scheme() {
+        "smtps" => SmtpTransport::relay(host),
+        "smtp" => SmtpTransport::starttls_relay(host),
+        scheme => bail!("The email server has to be smtps:// or smtp:// (with STARTTLS), not {}://", scheme),
+    };
+    let mut transport = match transport {
+        Ok(transport) => transport,
+        Err(e) => bail!("Could not set up email to {}: {}", host, e),
+    };
+    if let Some(port) = server.port() {
+        transport = transport.port(port);
+    }
+    if let (Some(username), Some(password)) = (&channel.username, &channel.password) {
+        transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+
+    let mut message = Message::builder().subject(event.title.as_str());
+    match channel.from.as_deref().unwrap_or_default().parse() {
+        Ok(from) => message = message.from(from),
+        Err(e) => bail!("Invalid email from address: {}", e),
+    }
+    for to in &channel.to {
+        match to.parse() {
+            Ok(to) => message = message.to(to),
+            Err(e) => bail!("Invalid email address {}: {}", to, e),
+        }
+    }
+    let message = match message.body(text) {
+        Ok(message) => message,
+        Err(e) => bail!("Could not create email: {}", e),
+    };
+    if let Err(e) = transport.build().send(&message) {
+        bail!("Could not send email through {}: {}", host, e);
+    }
+    debug!("Sent {} notification by email", event.kind);
+    Ok(())
+}
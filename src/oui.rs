@@ -0,0 +1,104 @@
+// MAC address vendors, for the vendor tag of the per-device data
+//
+// The first three bytes of a MAC address (the OUI) say who made the device, which is a start on
+// telling what an unknown device is. The IEEE's list of them is several megabytes, so rather than
+// being bundled it's downloaded with `fios-stats update-oui` into the state directory, or read from
+// oui_file. Phones and laptops often make up a MAC per network, and those have no vendor.
+
+use crate::config::Config;
+use crate::hostnames::{self, is_random_mac, normalize_mac};
+use crate::metrics::Metric;
+use crate::state;
+use crate::FetchError;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+pub const URL: &str = "https://standards-oui.ieee.org/oui/oui.txt";
+
+// OUI -> vendor
+type Vendors = Arc<HashMap<String, String>>;
+
+// The list read last, and the file it came from. It's only read once, not every cycle.
+static TABLE: Mutex<Option<(PathBuf, Vendors)>> = Mutex::new(None);
+
+pub fn path(config: &Config) -> PathBuf {
+    config.oui_file.as_ref().map(PathBuf::from).unwrap_or_else(|| state::file(config, "oui.txt"))
+}
+
+// Downloads the list, returning how many vendors are in it
+pub fn download(client: &reqwest::blocking::Client, config: &Config) -> Result<usize, FetchError> {
+    let response = client.get(URL).send()?;
+    let status = response.status();
+    let text = response.text()?;
+    if !status.is_success() {
+        return Err(FetchError::status(URL, status, &text));
+    }
+    let count = parse(&text).len();
+    if count == 0 {
+        bail!("No vendors in {}", URL);
+    }
+    let path = path(config);
+    fs::write(&path, text).map_err(|e| simple_error::SimpleError::new(format!("Could not write {}: {}", path.display(), e)))?;
+    *TABLE.lock().unwrap() = None;
+    Ok(count)
+}
+
+// oui.txt has lines like "00-1A-2B   (hex)		Example Corp", as OUI -> vendor
+pub fn parse(text: &str) -> HashMap<String, String> {
+    text.lines()
+        .filter_map(|line| {
+            let (prefix, vendor) = line.split_once("(hex)")?;
+            let prefix = prefix.trim().replace('-', "").to_lowercase();
+            if prefix.len() != 6 || vendor.trim().is_empty() {
+                return None;
+            }
+            Some((prefix, vendor.trim().to_string()))
+        })
+        .collect()
+}
+
+// None for a MAC that isn't in the list or is made up, i.e. locally administered
+pub fn vendor<'a>(table: &'a HashMap<String, String>, mac: &str) -> Option<&'a str> {
+    if is_random_mac(mac) {
+        return None;
+    }
+    let hex: String = normalize_mac(mac).chars().filter(char::is_ascii_hexdigit).collect();
+    table.get(hex.get(0..6)?).map(String::as_str)
+}
+
+// Adds a vendor tag to the data points with a mac tag
+pub fn tag(data: &mut [Metric], config: &Config) -> Result<(), FetchError> {
+    if !config.vendor_tags || !data.iter().any(|m| hostnames::mac_tag(m).is_some()) {
+        return Ok(());
+    }
+    let table = table(config)?;
+    for metric in data.iter_mut() {
+        if let Some(vendor) = hostnames::mac_tag(metric).and_then(|mac| vendor(&table, mac)).map(String::from) {
+            metric.tags.push(("vendor".to_string(), vendor));
+        }
+    }
+    Ok(())
+}
+
+fn table(config: &Config) -> Result<Vendors, FetchError> {
+    let path = path(config);
+    let mut cached = TABLE.lock().unwrap();
+    if let Some((cached_path, table)) = cached.as_ref() {
+        if *cached_path == path {
+            return Ok(table.clone());
+        }
+    }
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => {
+            bail!("No vendor list at {}, download it with `fios-stats update-oui`", path.display())
+        }
+        Err(e) => bail!("Could not read {}: {}", path.display(), e),
+    };
+    let table = Arc::new(parse(&text));
+    *cached = Some((path, table.clone()));
+    Ok(table)
+}
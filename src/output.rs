@@ -0,0 +1,179 @@
+// Printing collected data to stdout, for humans (table) or scripts (json, influx line protocol, and
+// collectd's exec plugin)
+
+use crate::collectors;
+use crate::config::Config;
+use crate::metrics::{self, Kind, Metric, Unit};
+use crate::scheduler;
+use serde_json::{json, Map, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Table,
+    Json,
+    Influx,
+    Collectd,
+}
+
+impl Format {
+    pub const NAMES: [&'static str; 4] = ["table", "json", "influx", "collectd"];
+
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "table" => Some(Format::Table),
+            "json" => Some(Format::Json),
+            "influx" => Some(Format::Influx),
+            "collectd" => Some(Format::Collectd),
+            _ => None,
+        }
+    }
+}
+
+// The config gives the collectors' intervals, for collectd
+pub fn print(format: Format, data: &[Metric], config: &Config) {
+    match format {
+        Format::Table => print!("{}", to_table(data)),
+        Format::Json => println!("{}", to_json(data)),
+        Format::Influx => print!("{}", metrics::to_line_protocol(data)),
+        Format::Collectd => {
+            // Set when collectd's exec plugin runs us
+            let exec_interval = std::env::var("COLLECTD_INTERVAL").ok().and_then(|i| i.parse::<f64>().ok());
+            print!("{}", to_collectd(data, config, exec_interval))
+        }
+    }
+}
+
+// A line of block characters, scaled to the largest value
+pub fn sparkline(values: &[u64]) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let max = values.iter().cloned().max().unwrap_or(0).max(1);
+    values
+        .iter()
+        .map(|v| BLOCKS[(u128::from(*v) * (BLOCKS.len() as u128 - 1) / u128::from(max)) as usize])
+        .collect()
+}
+
+fn to_table(data: &[Metric]) -> String {
+    let rows: Vec<(String, String, String)> = data
+        .iter()
+        .map(|m| {
+            let tags: Vec<String> = m.tags.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            let value = match m.unit {
+                Unit::Count => m.value.to_string(),
+                unit => format!("{} {}", m.value, unit.name()),
+            };
+            (m.name.clone(), tags.join(","), value)
+        })
+        .collect();
+    let name_width = rows.iter().map(|r| r.0.len()).chain(Some(11)).max().unwrap_or(0);
+    let tags_width = rows.iter().map(|r| r.1.len()).chain(Some(4)).max().unwrap_or(0);
+
+    let mut table = format!("{:<nw$}  {:<tw$}  {}\n", "MEASUREMENT", "TAGS", "VALUE", nw = name_width, tw = tags_width);
+    for (name, tags, value) in rows {
+        table.push_str(&format!("{:<nw$}  {:<tw$}  {}\n", name, tags, value, nw = name_width, tw = tags_width));
+    }
+    table
+}
+
+fn to_json(data: &[Metric]) -> Value {
+    Value::Array(
+        data.iter()
+            .map(|m| {
+                let tags: Map<String, Value> = m.tags.iter().map(|(k, v)| (k.clone(), json!(v))).collect();
+                json!({
+                    "name": m.name,
+                    "tags": tags,
+                    "value": m.value,
+                    "unit": m.unit.name(),
+                })
+            })
+            .collect(),
+    )
+}
+
+// PUTVAL lines for collectd's exec plugin, e.g.
+//   PUTVAL "myfiosgateway.com/fios-2.4GHz/derive-wifi_retries" interval=60 1700000000:42
+// The host is the router, and the other tags make the plugin instance. Counters are derives, so
+// collectd graphs their rate, and the rest gauges. Under the exec plugin everything is collected each
+// time it runs us, so the interval is the one it gives, exec_interval. Otherwise it's how often the
+// measurement is collected, as configured.
+fn to_collectd(data: &[Metric], config: &Config, exec_interval: Option<f64>) -> String {
+    let mut lines = String::new();
+    for m in data {
+        let host = m.tags.iter().find(|(k, _)| k == "host").map(|(_, v)| v.as_str()).unwrap_or("localhost");
+        let instance: Vec<&str> = m.tags.iter().filter(|(k, _)| k != "host").map(|(_, v)| v.as_str()).collect();
+        let plugin = if instance.is_empty() { "fios".to_string() } else { format!("fios-{}", instance.join("_")) };
+        let interval = exec_interval.unwrap_or_else(|| interval(&m.name, config) as f64);
+        let kind = match collectors::measurement(&m.name).map(|m| m.kind) {
+            Some(Kind::Counter) => "derive",
+            _ => "gauge",
+        };
+        let time = m.time.map(|t| t.to_string()).unwrap_or_else(|| "N".to_string());
+        lines.push_str(&format!(
+            "PUTVAL \"{}/{}/{}-{}\" interval={} {}:{}\n",
+            collectd_name(host),
+            collectd_name(&plugin),
+            kind,
+            collectd_name(&m.name),
+            interval,
+            time,
+            m.value
+        ));
+    }
+    lines
+}
+
+// The interval of the collector taking the measurement, from the config or --interval, or its
+// default
+fn interval(name: &str, config: &Config) -> u64 {
+    if name.ends_with("_hourly") || name.ends_with("_hourly_max") {
+        return 60 * 60;
+    }
+    collectors::REGISTRY
+        .iter()
+        .find(|c| c.measurements().iter().any(|m| m.name == name))
+        .map(|c| {
+            config
+                .intervals
+                .get(c.name())
+                .and_then(|value| scheduler::parse_duration(value))
+                .unwrap_or_else(|| c.default_interval())
+                .as_secs()
+        })
+        .unwrap_or(60)
+}
+
+// The parts of an identifier are separated by slashes, and it's quoted
+fn collectd_name(name: &str) -> String {
+    name.replace(['/', '"'], "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collectd() {
+        let mut config = Config::default();
+        config.intervals.insert("wifi_radios".to_string(), "5m".to_string());
+        let data = vec![
+            Metric::new("wifi_retries", 42).tag("host", "myfiosgateway.com").tag("band", "2.4GHz"),
+            Metric::new("devices_connected", 7),
+            Metric::new("devices_connected_hourly", 6),
+        ];
+        assert_eq!(
+            to_collectd(&data, &config, None),
+            "PUTVAL \"myfiosgateway.com/fios-2.4GHz/derive-wifi_retries\" interval=300 N:42\n\
+             PUTVAL \"localhost/fios/gauge-devices_connected\" interval=300 N:7\n\
+             PUTVAL \"localhost/fios/gauge-devices_connected_hourly\" interval=3600 N:6\n"
+        );
+        // Under the exec plugin, everything has its interval
+        assert_eq!(
+            to_collectd(&data, &config, Some(10.0)),
+            "PUTVAL \"myfiosgateway.com/fios-2.4GHz/derive-wifi_retries\" interval=10 N:42\n\
+             PUTVAL \"localhost/fios/gauge-devices_connected\" interval=10 N:7\n\
+             PUTVAL \"localhost/fios/gauge-devices_connected_hourly\" interval=10 N:6\n"
+        );
+        assert_eq!(collectd_name("a/b\"c"), "a_b_c");
+    }
+}
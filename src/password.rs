@@ -0,0 +1,81 @@
+// Finding the router password
+//
+// Giving it with -p makes it show up in `ps` output, so it can also be read from a file, from the
+// output of a command (e.g. `pass show fios`), from the OS keyring (saved with `login --save`), or
+// typed in at a prompt.
+
+use fios_stats::Config;
+use tracing::debug;
+use std::process::Command;
+
+// The passwords are stored per gateway, so each profile can have its own
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "fios-stats";
+
+pub fn get_password(config: &Config, use_keyring: bool) -> Result<String, String> {
+    if let Some(password) = &config.password {
+        return Ok(password.clone());
+    }
+
+    if let Some(path) = &config.password_file {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read password file {}: {}", path, e))?;
+        return Ok(strip_newline(&contents));
+    }
+
+    if let Some(command) = &config.password_cmd {
+        return run_command(command);
+    }
+
+    if use_keyring {
+        match keyring_password(config.gateway()) {
+            Ok(password) => return Ok(password),
+            Err(e) => debug!("No password in keyring: {}", e),
+        }
+    }
+
+    rpassword::read_password_from_tty(Some("Router password: "))
+        .map_err(|e| format!("No password given, and could not prompt for one: {}", e))
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_password(gateway: &str) -> Result<String, String> {
+    keyring::Keyring::new(KEYRING_SERVICE, gateway).get_password().map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_password(_: &str) -> Result<String, String> {
+    Err("built without the keyring feature".to_string())
+}
+
+#[cfg(feature = "keyring")]
+pub fn save_to_keyring(gateway: &str, password: &str) -> Result<(), String> {
+    keyring::Keyring::new(KEYRING_SERVICE, gateway)
+        .set_password(password)
+        .map_err(|e| format!("Could not save password to keyring: {}", e))
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn save_to_keyring(_: &str, _: &str) -> Result<(), String> {
+    Err("Built without the keyring feature, can't save the password".to_string())
+}
+
+fn run_command(command: &str) -> Result<String, String> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).output()
+    } else {
+        Command::new("sh").args(["-c", command]).output()
+    };
+    let output = output.map_err(|e| format!("Could not run password command: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("Password command failed: {}", output.status));
+    }
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| "Password command did not output valid UTF-8".to_string())?;
+    Ok(strip_newline(&stdout))
+}
+
+// Only the line ending is stripped, as spaces could be part of the password
+fn strip_newline(s: &str) -> String {
+    s.trim_end_matches(['\n', '\r']).to_string()
+}
@@ -0,0 +1,153 @@
+// Prometheus names for the measurements
+//
+// The InfluxDB names don't follow Prometheus's conventions, so the data sent there is renamed: a
+// fios_ prefix, base units (seconds rather than ms or minutes, ratios rather than percents), and
+// _total on counters. The units, kinds, and descriptions come from the collectors' measurements (see
+// metrics::Measurement), which also give the HELP and TYPE metadata.
+
+use crate::collectors;
+use crate::metrics::{Kind, Measurement, Metric, Unit};
+
+const PREFIX: &str = "fios_";
+
+// Where the generic renaming doesn't read well, or the unit needs more than a scale
+const NAMES: [(&str, &str); 4] = [
+    // Bytes of the last minute, as a rate
+    ("net_rx", "network_receive_bytes_per_second"),
+    ("net_tx", "network_transmit_bytes_per_second"),
+    ("net_rx_errors", "network_receive_errors"),
+    ("net_rx_dropped", "network_receive_drops"),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Family {
+    pub name: String,
+    pub kind: Kind,
+    pub help: String,
+    // What to multiply the values by, for the base unit
+    scale: f64,
+}
+
+impl Family {
+    // The metric's value in the family's unit
+    pub fn value(&self, metric: &Metric) -> f64 {
+        let value = match metric.unit {
+            Unit::Bits => metric.value as f64 / 8.0,
+            _ => metric.value as f64,
+        };
+        value * self.scale
+    }
+
+    // The HELP and TYPE lines of the text format
+    pub fn metadata(&self) -> String {
+        let help = self.help.replace('\\', "\\\\").replace('\n', "\\n");
+        format!("# HELP {} {}\n# TYPE {} {}\n", self.name, help, self.name, self.kind.name())
+    }
+}
+
+// The family of a measurement. The hourly rollups are gauges named after the measurement's family,
+// and measurements that aren't described anywhere keep their name.
+pub fn family(name: &str) -> Family {
+    for (suffix, what) in &[("_hourly_max", "Hourly maximum"), ("_hourly", "Hourly mean")] {
+        if let Some(base) = name.strip_suffix(suffix).and_then(collectors::measurement) {
+            let (base_name, scale) = base_name(base);
+            return Family {
+                name: format!("{}{}{}", PREFIX, base_name, suffix),
+                kind: Kind::Gauge,
+                help: format!("{} of: {}", what, base.description),
+                scale,
+            };
+        }
+    }
+    match collectors::measurement(name) {
+        Some(measurement) => {
+            let (base_name, scale) = base_name(measurement);
+            let total = if measurement.kind == Kind::Counter { "_total" } else { "" };
+            Family {
+                name: format!("{}{}{}", PREFIX, base_name, total),
+                kind: measurement.kind,
+                help: measurement.description.to_string(),
+                scale,
+            }
+        }
+        None => Family {
+            name: format!("{}{}", PREFIX, sanitize(name)),
+            kind: Kind::Gauge,
+            help: String::new(),
+            scale: 1.0,
+        },
+    }
+}
+
+// Prometheus names are letters, digits and underscores, not starting with a digit
+pub fn sanitize(name: &str) -> String {
+    let name: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+// The name without the prefix and _total, and the scale to the base unit
+fn base_name(measurement: &Measurement) -> (String, f64) {
+    if let Some((_, name)) = NAMES.iter().find(|(from, _)| *from == measurement.name) {
+        let scale = if name.ends_with("_per_second") { 1.0 / 60.0 } else { 1.0 };
+        return (name.to_string(), scale);
+    }
+    let name = measurement.name;
+    match measurement.unit {
+        "ms" => (format!("{}_seconds", name.strip_suffix("_ms").unwrap_or(name)), 0.001),
+        "percent" => (format!("{}_ratio", name.strip_suffix("_percent").unwrap_or(name)), 0.01),
+        "seconds" if !name.ends_with("_seconds") => (format!("{}_seconds", name), 1.0),
+        "bytes" if !name.ends_with("_bytes") => (format!("{}_bytes", name), 1.0),
+        "unix time" => (format!("{}_timestamp_seconds", name), 1.0),
+        _ => (name.to_string(), 1.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics;
+
+    #[test]
+    fn families() {
+        let rx = family("net_rx");
+        assert_eq!(rx.name, "fios_network_receive_bytes_per_second");
+        assert_eq!(rx.kind, Kind::Gauge);
+        assert_eq!(rx.value(&Metric::bytes("net_rx", 6000)), 100.0);
+        let mut bits = Metric::bytes("net_rx", 6000);
+        metrics::convert_units(std::slice::from_mut(&mut bits), Unit::Bits);
+        assert_eq!(rx.value(&bits), 100.0);
+
+        let errors = family("net_rx_errors");
+        assert_eq!(errors.name, "fios_network_receive_errors_total");
+        assert_eq!(errors.kind, Kind::Counter);
+        assert_eq!(family("ssid_rx_bytes").name, "fios_ssid_rx_bytes_total");
+        assert_eq!(family("wifi_retries").name, "fios_wifi_retries_total");
+
+        let latency = family("dns_latency_ms");
+        assert_eq!(latency.name, "fios_dns_latency_seconds");
+        assert_eq!(latency.value(&Metric::new("dns_latency_ms", 250)), 0.25);
+        let loss = family("probe_loss_percent");
+        assert_eq!(loss.name, "fios_probe_loss_ratio");
+        assert_eq!(loss.value(&Metric::new("probe_loss_percent", 50)), 0.5);
+        assert_eq!(family("router_outage").name, "fios_router_outage_seconds");
+        assert_eq!(family("device_last_seen").name, "fios_device_last_seen_timestamp_seconds");
+        assert_eq!(family("devices_connected").name, "fios_devices_connected");
+
+        let hourly = family("dns_latency_ms_hourly_max");
+        assert_eq!(hourly.name, "fios_dns_latency_seconds_hourly_max");
+        assert_eq!(hourly.kind, Kind::Gauge);
+        assert_eq!(family("not-described").name, "fios_not_described");
+    }
+
+    #[test]
+    fn metadata() {
+        assert_eq!(
+            family("devices_connected").metadata(),
+            "# HELP fios_devices_connected Devices connected right now\n# TYPE fios_devices_connected gauge\n"
+        );
+    }
+}
@@ -0,0 +1,83 @@
+// A bounded queue between collecting and writing to the sinks in daemon mode
+//
+// Writing happens on its own thread, so a slow or unreachable sink doesn't hold up collection. While
+// the sink is down the batches wait in the queue, and once it's full either the oldest batch is
+// dropped or collection waits for room, as configured.
+
+use crate::metrics::Metric;
+use tracing::warn;
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+
+pub const DEFAULT_CAPACITY: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    DropOldest,
+    Block,
+}
+
+impl Overflow {
+    pub const NAMES: [&'static str; 2] = ["drop-oldest", "block"];
+
+    pub fn from_name(name: &str) -> Option<Overflow> {
+        match name {
+            "drop-oldest" => Some(Overflow::DropOldest),
+            "block" => Some(Overflow::Block),
+            _ => None,
+        }
+    }
+}
+
+pub struct Queue {
+    batches: Mutex<VecDeque<Vec<Metric>>>,
+    changed: Condvar,
+    capacity: usize,
+    overflow: Overflow,
+}
+
+impl Queue {
+    pub fn new(capacity: usize, overflow: Overflow) -> Queue {
+        Queue {
+            batches: Mutex::new(VecDeque::new()),
+            changed: Condvar::new(),
+            capacity: capacity.max(1),
+            overflow,
+        }
+    }
+
+    pub fn push(&self, batch: Vec<Metric>) {
+        let mut batches = self.batches.lock().unwrap();
+        while batches.len() >= self.capacity {
+            match self.overflow {
+                Overflow::DropOldest => {
+                    let dropped = batches.pop_front().map_or(0, |b| b.len());
+                    warn!("Write queue is full, dropped the oldest {} data points", dropped);
+                }
+                Overflow::Block => batches = self.changed.wait(batches).unwrap(),
+            }
+        }
+        batches.push_back(batch);
+        self.changed.notify_all();
+    }
+
+    // Waits for the next batch
+    pub fn pop(&self) -> Vec<Metric> {
+        let mut batches = self.batches.lock().unwrap();
+        loop {
+            if let Some(batch) = batches.pop_front() {
+                self.changed.notify_all();
+                return batch;
+            }
+            batches = self.changed.wait(batches).unwrap();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.batches.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,43 @@
+// Spacing out the requests to the router
+//
+// The router's web server is slow, and busy polling makes its admin page sluggish for whoever is
+// using it at the same time. With rate_limit set, all requests to a router, from every collector and
+// session, are spread out to at most that many per second. Requests wait their turn rather than fail.
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    // When the next request may go out
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: f64) -> Result<RateLimiter, String> {
+        if !(per_second > 0.0 && per_second.is_finite()) {
+            return Err(format!("Invalid rate limit {}, it must be more than 0 requests per second", per_second));
+        }
+        Ok(RateLimiter {
+            interval: Duration::from_secs_f64(1.0 / per_second),
+            next: Mutex::new(Instant::now()),
+        })
+    }
+
+    // Waits until a request may go out. The slot is taken before sleeping, so concurrent callers
+    // queue up behind each other.
+    pub fn wait(&self) {
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            let slot = (*next).max(Instant::now());
+            *next = slot + self.interval;
+            slot
+        };
+        let now = Instant::now();
+        if slot > now {
+            thread::sleep(slot - now);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+// A summary of the last day or week, read back from InfluxDB, for `fios-stats report`
+//
+// The traffic totals add up the hourly averages of the per minute readings, so hours without data
+// don't count. The error counters and the known devices are totals, so what matters is how much they
+// grew. Everything is for the router the config points at, by its host tag.
+
+use crate::metrics::Unit;
+use crate::sinks::influx;
+use crate::usage::civil_from_days;
+use crate::FetchError;
+use serde_json::Value;
+use std::fmt;
+
+const PEAK_HOURS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Period {
+    Day,
+    Week,
+}
+
+impl Period {
+    pub fn from_name(name: &str) -> Option<Period> {
+        match name {
+            "day" => Some(Period::Day),
+            "week" => Some(Period::Week),
+            _ => None,
+        }
+    }
+
+    fn influx_duration(&self) -> &'static str {
+        match self {
+            Period::Day => "1d",
+            Period::Week => "7d",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Period::Day => "the last 24 hours",
+            Period::Week => "the last 7 days",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub host: String,
+    pub period: Option<Period>,
+    // Bytes
+    pub rx: f64,
+    pub tx: f64,
+    // The start of the hour in Unix time, and the average download in bits per second
+    pub peak_hours: Vec<(u64, f64)>,
+    pub rx_errors: Option<f64>,
+    pub rx_dropped: Option<f64>,
+    pub connected_min: Option<f64>,
+    pub connected_max: Option<f64>,
+    pub new_devices: Option<f64>,
+    pub outages: u64,
+    pub outage_seconds: f64,
+}
+
+impl Report {
+    // The unit is what the data was collected in, with --units
+    pub fn build(
+        client: &reqwest::blocking::Client,
+        influx_uri: &str,
+        host: &str,
+        unit: Unit,
+        period: Period,
+    ) -> Result<Report, FetchError> {
+        let filter = format!(
+            "\"host\" = '{}' AND time > now() - {}",
+            host.replace('\\', "\\\\").replace('\'', "\\'"),
+            period.influx_duration()
+        );
+        let query = |select: &str, measurement: &str, group_by: &str| {
+            influx::query(client, influx_uri, &format!("SELECT {} FROM \"{}\" WHERE {}{}", select, measurement, filter, group_by))
+        };
+        let number = |value: Option<&Value>| value.and_then(Value::as_f64);
+        // The values of the single row an aggregate gives
+        let single = |select: &str, measurement: &str| -> Result<Vec<Option<f64>>, FetchError> {
+            let rows = query(select, measurement, "")?;
+            Ok(match rows.first() {
+                Some(row) => row.iter().skip(1).map(|v| number(Some(v))).collect(),
+                None => Vec::new(),
+            })
+        };
+        // Bytes per minute
+        let bytes = |value: f64| if unit == Unit::Bits { value / 8.0 } else { value };
+
+        let mut report = Report {
+            host: host.to_string(),
+            period: Some(period),
+            ..Report::default()
+        };
+
+        let hourly = " GROUP BY time(1h) fill(none)";
+        let rx_hours = query("mean(\"value\")", "net_rx", hourly)?;
+        let tx_hours = query("mean(\"value\")", "net_tx", hourly)?;
+        let total = |rows: &[Vec<Value>]| -> f64 { rows.iter().filter_map(|row| number(row.get(1))).map(|v| bytes(v) * 60.0).sum() };
+        report.rx = total(&rx_hours);
+        report.tx = total(&tx_hours);
+
+        let mut hours: Vec<(u64, f64)> = rx_hours
+            .iter()
+            .filter_map(|row| Some((row.first()?.as_u64()?, number(row.get(1))?)))
+            .map(|(hour, average)| (hour, bytes(average) * 8.0 / 60.0))
+            .collect();
+        hours.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        hours.truncate(PEAK_HOURS);
+        report.peak_hours = hours;
+
+        report.rx_errors = single("spread(\"value\")", "net_rx_errors")?.into_iter().next().flatten();
+        report.rx_dropped = single("spread(\"value\")", "net_rx_dropped")?.into_iter().next().flatten();
+        if let [min, max] = single("min(\"value\"), max(\"value\")", "devices_connected")?[..] {
+            report.connected_min = min;
+            report.connected_max = max;
+        }
+        if let [Some(first), Some(last)] = single("first(\"value\"), last(\"value\")", "devices_known")?[..] {
+            report.new_devices = Some(last - first);
+        }
+        if let [count, sum] = single("count(\"value\"), sum(\"value\")", "router_outage")?[..] {
+            report.outages = count.unwrap_or(0.0) as u64;
+            report.outage_seconds = sum.unwrap_or(0.0);
+        }
+        Ok(report)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let period = self.period.map(|p| p.description()).unwrap_or("the period");
+        writeln!(f, "Router {}, {}", self.host, period)?;
+        writeln!(f)?;
+        writeln!(f, "Downloaded:      {}", gigabytes(self.rx))?;
+        writeln!(f, "Uploaded:        {}", gigabytes(self.tx))?;
+        if !self.peak_hours.is_empty() {
+            let hours: Vec<String> = self
+                .peak_hours
+                .iter()
+                .map(|(hour, bits)| format!("{} ({:.1} Mbit/s)", hour_name(*hour), bits / 1e6))
+                .collect();
+            writeln!(f, "Busiest hours:   {}", hours.join(", "))?;
+        }
+        let count = |value: Option<f64>| value.map(|v| format!("{:.0}", v)).unwrap_or_else(|| "no data".to_string());
+        writeln!(f, "Receive errors:  {}", count(self.rx_errors))?;
+        writeln!(f, "Dropped packets: {}", count(self.rx_dropped))?;
+        if let (Some(min), Some(max)) = (self.connected_min, self.connected_max) {
+            writeln!(f, "Connected:       {:.0} to {:.0} devices", min, max)?;
+        }
+        if let Some(new_devices) = self.new_devices {
+            writeln!(f, "New devices:     {:.0}", new_devices)?;
+        }
+        writeln!(f, "Router outages:  {}, {:.0}s in total", self.outages, self.outage_seconds)
+    }
+}
+
+fn gigabytes(bytes: f64) -> String {
+    format!("{:.2} GB", bytes / 1e9)
+}
+
+// "2024-03-05 20:00 UTC"
+fn hour_name(time: u64) -> String {
+    let (year, month, day) = civil_from_days((time / 86_400) as i64);
+    format!("{:04}-{:02}-{:02} {:02}:00 UTC", year, month, day, time % 86_400 / 3600)
+}
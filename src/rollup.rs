@@ -0,0 +1,68 @@
+// Hourly rollups of the data in daemon mode
+//
+// Graphing months of per-minute data is slow, and InfluxDB only downsamples with continuous queries
+// or tasks set up by hand. With hourly_rollups, the daemon also writes the mean and maximum of each
+// series over every hour, as <measurement>_hourly and <measurement>_hourly_max, at the start of the
+// hour. They are kept in memory, so the hour the daemon is restarted in only covers part of it.
+
+use crate::metrics::{Metric, Unit};
+use std::collections::BTreeMap;
+
+const HOUR: u64 = 60 * 60;
+
+#[derive(Default)]
+pub struct Rollup {
+    // Start of the hour being added up, as a Unix time
+    hour: Option<u64>,
+    series: BTreeMap<(String, Vec<(String, String)>), Stats>,
+}
+
+struct Stats {
+    unit: Unit,
+    sum: u128,
+    count: u64,
+    max: u64,
+}
+
+impl Rollup {
+    // Adds a cycle's data, which has to have its time set. Once the data is from a later hour, the
+    // rollups of the previous one are returned.
+    pub fn add(&mut self, data: &[Metric]) -> Vec<Metric> {
+        let hour = match data.iter().find_map(|m| m.time) {
+            Some(time) => time / HOUR * HOUR,
+            None => return Vec::new(),
+        };
+        let rollups = match self.hour {
+            Some(previous) if previous != hour => self.finish(previous),
+            _ => Vec::new(),
+        };
+        self.hour = Some(hour);
+        for metric in data {
+            let stats = self
+                .series
+                .entry((metric.name.clone(), metric.tags.clone()))
+                .or_insert(Stats { unit: metric.unit, sum: 0, count: 0, max: 0 });
+            stats.sum += u128::from(metric.value);
+            stats.count += 1;
+            stats.max = stats.max.max(metric.value);
+        }
+        rollups
+    }
+
+    fn finish(&mut self, hour: u64) -> Vec<Metric> {
+        let mut rollups = Vec::new();
+        for ((name, tags), stats) in std::mem::take(&mut self.series) {
+            let mean = (stats.sum / u128::from(stats.count)) as u64;
+            for (suffix, value) in &[("hourly", mean), ("hourly_max", stats.max)] {
+                rollups.push(Metric {
+                    name: format!("{}_{}", name, suffix),
+                    tags: tags.clone(),
+                    value: *value,
+                    unit: stats.unit,
+                    time: Some(hour),
+                });
+            }
+        }
+        rollups
+    }
+}
@@ -0,0 +1,54 @@
+// How the password is hashed with the router's salt before logging in
+//
+// The firmwares we know of, on every model, send SHA-512(password + salt) as lowercase hex, but other
+// revisions are said to use SHA-256 or an HMAC. password_hash in the config picks one of those for a
+// firmware that does something else.
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256, Sha512};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PasswordHash {
+    // Of the password followed by the salt
+    Sha512,
+    Sha256,
+    // Of the password, keyed with the salt
+    HmacSha256,
+}
+
+impl PasswordHash {
+    pub const NAMES: [&'static str; 3] = ["sha512", "sha256", "hmac-sha256"];
+
+    pub fn from_name(name: &str) -> Option<PasswordHash> {
+        match name {
+            "sha512" => Some(PasswordHash::Sha512),
+            "sha256" => Some(PasswordHash::Sha256),
+            "hmac-sha256" => Some(PasswordHash::HmacSha256),
+            _ => None,
+        }
+    }
+
+    // Lowercase hex
+    pub fn hash(&self, password: &str, salt: &str) -> String {
+        match self {
+            PasswordHash::Sha512 => {
+                let mut hasher = Sha512::new();
+                hasher.input(password);
+                hasher.input(salt);
+                format!("{:x}", hasher.result())
+            }
+            PasswordHash::Sha256 => {
+                let mut hasher = Sha256::new();
+                hasher.input(password);
+                hasher.input(salt);
+                format!("{:x}", hasher.result())
+            }
+            PasswordHash::HmacSha256 => {
+                // Any key length works for an HMAC
+                let mut mac = Hmac::<Sha256>::new_varkey(salt.as_bytes()).expect("HMAC takes keys of any size");
+                mac.input(password.as_bytes());
+                format!("{:x}", mac.result().code())
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+// The Verizon Router, CR1000A (WiFi 6E)
+//
+// It logs in like the G3100, but sessions are handled differently:
+// * Every request has to carry the session's token in a "token" header, which is the loginToken
+//   from /loginStatus.cgi once logged in. The cookie alone gets a 401.
+// * Sessions time out after a few idle minutes, which is shorter than the slower collector
+//   intervals. So on a 401 we log in again and retry the request once, rather than failing the
+//   collector and waiting for the next run.
+
+use super::tr181::{self, Counters, Object};
+use super::{Device, Diagnostics, Interface, NetworkStats, RadioStats, RouterClient, SsidTraffic, WifiNetwork};
+use crate::client::Gateway;
+use crate::error::AuthError;
+use crate::redact;
+use crate::FetchError;
+use tracing::{debug, info};
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+
+const WAN_STATS: &str = "Device.IP.Interface.1.Stats";
+const WAN_ADDRESS: &str = "Device.IP.Interface.1.IPv4Address";
+
+pub struct Cr1000a {
+    client: reqwest::blocking::Client,
+    gateway: Gateway,
+    // Kept for logging in again when the session times out
+    password: String,
+    token: RefCell<String>,
+    last_counters: Cell<Option<Counters>>,
+}
+
+impl Cr1000a {
+    // The client must have a cookie store, to keep the session cookie
+    pub fn login(client: &reqwest::blocking::Client, gateway: &Gateway, password: &str) -> Result<Cr1000a, FetchError> {
+        let router = Cr1000a {
+            client: client.clone(),
+            gateway: gateway.clone(),
+            password: password.to_string(),
+            token: RefCell::new(String::new()),
+            last_counters: Cell::new(None),
+        };
+        router.start_session()?;
+        Ok(router)
+    }
+
+    fn start_session(&self) -> Result<(), FetchError> {
+        let status = self.login_status()?;
+        if let Some(token) = status["loginToken"].as_str() {
+            redact::register(token);
+        }
+        debug!("Got login status: {:#?}", status);
+        let salt = status["passwordSalt"].as_str().unwrap_or_default();
+        let token = match status["loginToken"].as_str() {
+            Some(token) => token,
+            None => return Err(FetchError::response("/loginStatus.cgi", "no loginToken", &status.to_string())),
+        };
+
+        let hash = self.gateway.password_hash().hash(&self.password, salt);
+        redact::register(&hash);
+
+        let uri = self.gateway.root_uri("login.cgi")?;
+        self.gateway.throttle();
+        let response = self.client.post(uri)
+            .form(&[("luci_username", self.gateway.username.as_deref().unwrap_or("admin")), ("luci_password", hash.as_str()), ("token", token)])
+            .send()?;
+        self.gateway.check_certificate(&response)?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(match status {
+                reqwest::StatusCode::FORBIDDEN => AuthError::LockedOut(0),
+                reqwest::StatusCode::SERVICE_UNAVAILABLE => AuthError::TooManySessions,
+                _ => AuthError::WrongPassword,
+            }
+            .into());
+        }
+
+        // Logged in, the token is now the one for this session
+        let status = self.login_status()?;
+        match status["loginToken"].as_str() {
+            Some(token) => {
+                redact::register(token);
+                *self.token.borrow_mut() = token.to_string();
+            }
+            None => return Err(FetchError::response("/loginStatus.cgi", "no loginToken after login", &status.to_string())),
+        }
+        Ok(())
+    }
+
+    fn login_status(&self) -> Result<Value, FetchError> {
+        let (status, body) = self.get("/loginStatus.cgi")?;
+        if !status.is_success() {
+            return Err(FetchError::status("/loginStatus.cgi", status, &body));
+        }
+        serde_json::from_str(&body).map_err(|e| FetchError::parse("/loginStatus.cgi", &body, e))
+    }
+
+    fn get(&self, path: &str) -> Result<(reqwest::StatusCode, String), FetchError> {
+        let uri = self.gateway.root_uri(path)?;
+        debug!("Fetching: {}", uri);
+        self.gateway.throttle();
+        let response = self.client.get(uri)
+            .header("token", self.token.borrow().as_str())
+            .send()?;
+        self.gateway.check_certificate(&response)?;
+        let status = response.status();
+        Ok((status, response.text()?))
+    }
+
+    fn get_object(&self, object: &str) -> Result<Vec<Object>, FetchError> {
+        Ok(self.get_named_objects(object)?.into_iter().map(|(_, object)| object).collect())
+    }
+
+    fn get_named_objects(&self, object: &str) -> Result<Vec<(String, Object)>, FetchError> {
+        let path = tr181::object_path(object);
+        let data = self.fetch_json(&path)?;
+        debug!("Got {} response: {:#?}", object, data);
+        tr181::parse_named_objects(&path, &data)
+    }
+}
+
+impl RouterClient for Cr1000a {
+    fn fetch(&self, api: &str) -> Result<String, FetchError> {
+        let (mut status, mut body) = self.get(api)?;
+        if status == reqwest::StatusCode::UNAUTHORIZED {
+            info!("Router session expired, logging in again");
+            self.start_session()?;
+            let (retry_status, retry_body) = self.get(api)?;
+            status = retry_status;
+            body = retry_body;
+        }
+        if !status.is_success() {
+            return Err(FetchError::status(api, status, &body));
+        }
+        Ok(body)
+    }
+
+    fn network_stats(&self) -> Result<NetworkStats, FetchError> {
+        tr181::network_stats(&self.last_counters, || Counters::from_stats(WAN_STATS, &self.get_object(WAN_STATS)?))
+    }
+
+    fn devices(&self) -> Result<Vec<Device>, FetchError> {
+        Ok(self.get_object(tr181::HOSTS)?.into_iter().map(tr181::device).collect())
+    }
+
+    fn wifi_networks(&self) -> Result<Vec<WifiNetwork>, FetchError> {
+        Ok(self.get_object(tr181::NEIGHBORS)?.into_iter().map(tr181::wifi_network).collect())
+    }
+
+    fn ssid_traffic(&self) -> Result<Vec<SsidTraffic>, FetchError> {
+        tr181::ssid_traffic(|object| self.get_named_objects(object))
+    }
+
+    fn radio_stats(&self) -> Result<Vec<RadioStats>, FetchError> {
+        tr181::radio_stats(|object| self.get_named_objects(object))
+    }
+
+    // Not every firmware has the MoCA table
+    fn interfaces(&self) -> Result<Vec<Interface>, FetchError> {
+        let mut interfaces = tr181::interfaces("ethernet", tr181::ETHERNET, self.get_named_objects(tr181::ETHERNET)?);
+        match self.get_named_objects(tr181::MOCA) {
+            Ok(moca) => interfaces.extend(tr181::interfaces("moca", tr181::MOCA, moca)),
+            Err(e) => debug!("Could not get the MoCA interfaces: {}", e),
+        }
+        Ok(interfaces)
+    }
+
+    fn diagnostics(&self) -> Result<Diagnostics, FetchError> {
+        Ok(tr181::diagnostics(&self.get_object(tr181::PING)?, self.get_named_objects(tr181::TRACEROUTE_HOPS)?))
+    }
+
+    fn minute_history(&self) -> Result<(Vec<u64>, Vec<u64>), FetchError> {
+        bail!("The CR1000A only has the traffic totals, not a history");
+    }
+
+    fn wan_ip(&self) -> Result<String, FetchError> {
+        tr181::ip_address(WAN_ADDRESS, &self.get_object(WAN_ADDRESS)?)
+    }
+
+    fn firmware_version(&self) -> Result<String, FetchError> {
+        tr181::firmware_version(&self.get_object(tr181::DEVICE_INFO)?)
+    }
+
+    fn backup(&self) -> Result<Vec<u8>, FetchError> {
+        bail!("Configuration backups are not supported on the CR1000A yet");
+    }
+
+    fn reboot(&self) -> Result<(), FetchError> {
+        bail!("Rebooting is not supported on the CR1000A yet");
+    }
+
+    fn logout(&self) -> Result<(), FetchError> {
+        // Not through fetch(), an expired session doesn't need logging in just to log out
+        self.get("/logout.cgi")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+// Reading the fields of a response
+//
+// Firmware updates rename and drop fields now and then. A field we can't do without is an error, while
+// one that's only nice to have is logged with where it was looked for and left out, so the rest of the
+// response still gets used. The warning is only given once per field, not on every poll.
+
+use crate::FetchError;
+use tracing::{debug, warn};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+static MISSING: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+pub struct Fields<'a> {
+    api: &'a str,
+    data: &'a Value,
+}
+
+impl<'a> Fields<'a> {
+    pub fn new(api: &'a str, data: &'a Value) -> Fields<'a> {
+        Fields { api, data }
+    }
+
+    // A number at a JSON pointer like /bandwidth/minutesRx/0
+    pub fn u64(&self, pointer: &str) -> Result<u64, FetchError> {
+        self.data
+            .pointer(pointer)
+            .and_then(Value::as_u64)
+            .ok_or_else(|| FetchError::response(self.api, &format!("no number at {}", pointer), &self.data.to_string()))
+    }
+
+    pub fn optional_u64(&self, pointer: &str) -> Option<u64> {
+        let value = self.data.pointer(pointer).and_then(Value::as_u64);
+        if value.is_none() {
+            missing(self.api, pointer);
+        }
+        value
+    }
+}
+
+// Logs a field that isn't in a response, or isn't what we expected, as the API and the path in it
+pub fn missing(api: &str, path: &str) {
+    let field = format!("{} {}", api, path);
+    let mut seen = MISSING.lock().unwrap();
+    if seen.get_or_insert_with(HashSet::new).insert(field.clone()) {
+        warn!("No {} in the router's response, leaving it out", field);
+    } else {
+        debug!("No {} in the router's response", field);
+    }
+}
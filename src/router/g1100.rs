@@ -0,0 +1,398 @@
+// The Fios Quantum Gateway, G1100 (and the G1000 that preceded it)
+//
+// Their admin interface fetches data using a standard JSON REST API. If there is a way to
+// authenticate that is different than how a browser does, I don't know of it. So that's what I do
+// here.
+//
+// The authentication works this way:
+// 1) You call /login to get a passwordSalt.
+// 2) you take the Sha512(password + passwordSalt) to create a hash (see auth.rs)
+// 3) you call /login with {"password": hash}
+// 4) on successful login, two cookies are returned XSRF-TOKEN and Session
+// For all API calls set Session as a cookie and a header X-XSRF-TOKEN with the XSRF-TOKEN value.
+// The cookie store of the client takes care of the cookie.
+
+use super::fields::Fields;
+use super::schema;
+use super::{Device, NetworkStats, RouterClient, WifiNetwork};
+use crate::client::{certificate_fingerprint, Gateway};
+use crate::error::{self, AuthError};
+use crate::redact;
+use crate::FetchError;
+use tracing::{debug, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+// The fields vary between firmware versions, so only the salt is required, and that only when a
+// password is. Most of them are only ever logged, but are listed so they don't show up as unknown
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case, dead_code)]
+struct LoginResponse {
+    passwordSalt: Option<String>,
+    doSetupWizard: Option<bool>,
+    requirePassword: Option<bool>,
+    isWireless: Option<bool>,
+    error: Option<u64>,
+    maxUsers: Option<u64>,
+    denyState: Option<u64>,
+    denyTimeout: Option<u64>,
+    meshNetworkEnabledStatus: Option<bool>,
+    meshUserEnabledConfig: Option<bool>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, Value>,
+}
+
+// The error codes in the response to a failed login
+const LOGIN_ERROR_PASSWORD: u64 = 1;
+const LOGIN_ERROR_MAX_SESSIONS: u64 = 2;
+
+const XSRF_HEADER: &str = "x-xsrf-token";
+
+// The session ID is opaque, some firmwares use numbers and others not
+#[derive(Debug)]
+struct AuthInfo {
+    token: String,
+    session: String,
+}
+
+// A response the router sent an ETag or Last-Modified with, parsed. When asked with those, the router
+// answers 304 Not Modified if nothing changed, and the data is used again without parsing it.
+struct Cached {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    data: Value,
+}
+
+pub struct G1100 {
+    client: reqwest::blocking::Client,
+    gateway: Gateway,
+    // Some endpoints send a new one, which is used from then on
+    xsrf_token: RefCell<String>,
+    // API path -> the last response, for the endpoints with conditional requests
+    cache: RefCell<HashMap<String, Cached>>,
+}
+
+impl G1100 {
+    // Logs in to the router. The client must have a cookie store, to keep the session cookie
+    pub fn login(client: &reqwest::blocking::Client, gateway: &Gateway, password: &str) -> Result<G1100, FetchError> {
+        let login_info = get_login_info(client, gateway)?;
+        debug!("Got login info: {:#?}", login_info);
+
+        if login_info.denyState.unwrap_or(0) != 0 {
+            return Err(AuthError::LockedOut(login_info.denyTimeout.unwrap_or(0)).into());
+        }
+
+        let auth_info = do_login(client, gateway, password, &login_info)?;
+        redact::register(&auth_info.token);
+        redact::register(&auth_info.session);
+        debug!("Logged in with session {}", auth_info.session);
+
+        Ok(G1100 {
+            client: client.clone(),
+            gateway: gateway.clone(),
+            xsrf_token: RefCell::new(auth_info.token),
+            cache: RefCell::new(HashMap::new()),
+        })
+    }
+
+    // Like fetch(), but for binary responses
+    pub fn download(&self, api: &str) -> Result<Vec<u8>, FetchError> {
+        let uri = self.gateway.base_uri.join(api)?;
+        debug!("Downloading: {}", uri);
+        self.gateway.throttle();
+        let response = self.client.get(uri).header(XSRF_HEADER, self.xsrf_token.borrow().as_str()).send()?;
+        self.gateway.check_certificate(&response)?;
+        self.update_token(&response);
+        let status = response.status();
+        if !status.is_success() {
+            return Err(FetchError::status(api, status, &response.text().unwrap_or_default()));
+        }
+        Ok(response.bytes()?.to_vec())
+    }
+
+    // Keeps up with the router changing the XSRF token mid-session, so requests don't start failing
+    // with 403s in a long running daemon
+    fn update_token(&self, response: &reqwest::blocking::Response) {
+        if let Some(cookie) = response.cookies().find(|c| c.name() == "XSRF-TOKEN") {
+            if *self.xsrf_token.borrow() != cookie.value() {
+                redact::register(cookie.value());
+                debug!("The router sent a new XSRF token");
+                *self.xsrf_token.borrow_mut() = cookie.value().to_string();
+            }
+        }
+    }
+
+    pub fn post(&self, api: &str, json: &str) -> Result<String, FetchError> {
+        let uri = self.gateway.base_uri.join(api)?;
+        debug!("Posting to: {}", uri);
+        self.gateway.throttle();
+        let response = self.client.post(uri)
+            .header(XSRF_HEADER, self.xsrf_token.borrow().as_str())
+            .header(reqwest::header::CONTENT_TYPE, "application/json;charset=UTF-8")
+            .body(json.to_string())
+            .send()?;
+        self.gateway.check_certificate(&response)?;
+        self.update_token(&response);
+        let status = response.status();
+        let body = response.text()?;
+        if !status.is_success() {
+            return Err(FetchError::status(api, status, &body));
+        }
+        Ok(body)
+    }
+}
+
+impl RouterClient for G1100 {
+    fn fetch(&self, api: &str) -> Result<String, FetchError> {
+        let uri = self.gateway.base_uri.join(api)?;
+        debug!("Fetching: {}", uri);
+        self.gateway.throttle();
+        let response = self.client.get(uri).header(XSRF_HEADER, self.xsrf_token.borrow().as_str()).send()?;
+        self.gateway.check_certificate(&response)?;
+        self.update_token(&response);
+        let status = response.status();
+        let body = response.text()?;
+        if !status.is_success() {
+            return Err(FetchError::status(api, status, &body));
+        }
+        Ok(body)
+    }
+
+    fn fetch_json(&self, api: &str) -> Result<Value, FetchError> {
+        use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+
+        let uri = self.gateway.base_uri.join(api)?;
+        debug!("Fetching: {}", uri);
+        let mut request = self.client.get(uri).header(XSRF_HEADER, self.xsrf_token.borrow().as_str());
+        if let Some(cached) = self.cache.borrow().get(api) {
+            if let Some(etag) = &cached.etag {
+                request = request.header(IF_NONE_MATCH, etag.as_str());
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+            }
+        }
+        self.gateway.throttle();
+        let response = request.send()?;
+        self.gateway.check_certificate(&response)?;
+        self.update_token(&response);
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_MODIFIED {
+            if let Some(cached) = self.cache.borrow().get(api) {
+                debug!("{} hasn't changed", api);
+                return Ok(cached.data.clone());
+            }
+        }
+        let header = |name: reqwest::header::HeaderName| response.headers().get(name).and_then(|v| v.to_str().ok()).map(String::from);
+        let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+        let body = response.text()?;
+        if !status.is_success() {
+            return Err(FetchError::status(api, status, &body));
+        }
+        let data: Value = serde_json::from_str(&body).map_err(|e| FetchError::parse(api, &body, e))?;
+        if etag.is_some() || last_modified.is_some() {
+            let cached = Cached { etag, last_modified, data: data.clone() };
+            self.cache.borrow_mut().insert(api.to_string(), cached);
+        }
+        Ok(data)
+    }
+
+    fn network_stats(&self) -> Result<NetworkStats, FetchError> {
+        let data = self.fetch_json("network/1")?;
+        debug!("Got network response: {:#?}", data);
+        schema::check("network/1", &data);
+        parse_network_stats(&data)
+    }
+
+    fn devices(&self) -> Result<Vec<Device>, FetchError> {
+        let data = self.fetch_json("devices")?;
+        debug!("Got devices response: {:#?}", data);
+        schema::check("devices", &data);
+        parse_devices(&data)
+    }
+
+    fn wifi_networks(&self) -> Result<Vec<WifiNetwork>, FetchError> {
+        let data = self.fetch_json("wireless/sitesurvey")?;
+        debug!("Got site survey response: {:#?}", data);
+        schema::check("wireless/sitesurvey", &data);
+        parse_wifi_networks(&data)
+    }
+
+    fn minute_history(&self) -> Result<(Vec<u64>, Vec<u64>), FetchError> {
+        parse_minute_history(&self.fetch_json("network/1")?)
+    }
+
+    fn wan_ip(&self) -> Result<String, FetchError> {
+        let data = self.fetch_json("network/1")?;
+        match data["ipAddress"].as_str() {
+            Some(ip) => Ok(ip.to_string()),
+            None => Err(FetchError::response("network/1", "no ipAddress", &data.to_string())),
+        }
+    }
+
+    // Older firmwares call it the software version
+    fn firmware_version(&self) -> Result<String, FetchError> {
+        let data = self.fetch_json("settings/system")?;
+        match data["firmwareVersion"].as_str().or_else(|| data["softwareVersion"].as_str()) {
+            Some(version) => Ok(version.to_string()),
+            None => Err(FetchError::response("settings/system", "no firmwareVersion", &data.to_string())),
+        }
+    }
+
+    fn backup(&self) -> Result<Vec<u8>, FetchError> {
+        self.download("settings/backup")
+    }
+
+    fn reboot(&self) -> Result<(), FetchError> {
+        self.post("settings/reboot", "{}")?;
+        Ok(())
+    }
+
+    fn logout(&self) -> Result<(), FetchError> {
+        self.fetch("logout")?;
+        Ok(())
+    }
+}
+
+// The parsing of the responses is kept apart from the fetching, so it can be tested on its own
+
+pub fn parse_network_stats(data: &Value) -> Result<NetworkStats, FetchError> {
+    let fields = Fields::new("network/1", data);
+    Ok(NetworkStats {
+        rx_bytes: fields.u64("/bandwidth/minutesRx/0")?,
+        tx_bytes: fields.u64("/bandwidth/minutesTx/0")?,
+        rx_errors: fields.optional_u64("/rxErrors"),
+        rx_dropped: fields.optional_u64("/rxDropped"),
+    })
+}
+
+// The router lists the most recent minute first
+pub fn parse_minute_history(data: &Value) -> Result<(Vec<u64>, Vec<u64>), FetchError> {
+    let minutes = |name: &str| match data["bandwidth"][name].as_array() {
+        Some(values) => Ok(values.iter().rev().map(|v| v.as_u64().unwrap_or(0)).collect()),
+        None => Err(FetchError::response("network/1", &format!("no bandwidth {}", name), &data.to_string())),
+    };
+    Ok((minutes("minutesRx")?, minutes("minutesTx")?))
+}
+
+pub fn parse_devices(data: &Value) -> Result<Vec<Device>, FetchError> {
+    let devices = match data.as_array() {
+        Some(devices) => devices,
+        None => return Err(FetchError::response("devices", "expected a list of devices", &data.to_string())),
+    };
+    Ok(devices
+        .iter()
+        .map(|d| Device {
+            name: d["name"].as_str().map(String::from),
+            mac: d["mac"].as_str().map(String::from),
+            ip: d["ip"].as_str().map(String::from),
+            connected: d["status"].as_bool().unwrap_or(false),
+            signal: d["rssi"].as_i64(),
+        })
+        .collect())
+}
+
+pub fn parse_wifi_networks(data: &Value) -> Result<Vec<WifiNetwork>, FetchError> {
+    let networks = match data.as_array() {
+        Some(networks) => networks,
+        None => return Err(FetchError::response("wireless/sitesurvey", "expected a list of networks", &data.to_string())),
+    };
+    Ok(networks
+        .iter()
+        .map(|n| WifiNetwork {
+            ssid: n["ssid"].as_str().map(String::from),
+            channel: n["channel"].as_u64(),
+        })
+        .collect())
+}
+
+fn get_login_info(client: &reqwest::blocking::Client, gateway: &Gateway) -> Result<LoginResponse, FetchError>
+{
+    let uri = gateway.base_uri.join("login")?;
+    gateway.throttle();
+    let response = client.get(uri).send()?;
+    gateway.check_certificate(&response)?;
+    if gateway.insecure && gateway.pin_sha256.is_none() {
+        if let Some(fingerprint) = certificate_fingerprint(&response) {
+            info!("Router certificate SHA-256 fingerprint is {}, use --pin-sha256 to trust only it", fingerprint);
+        }
+    }
+    let body = response.text()?;
+    let info: LoginResponse = serde_json::from_str(&body).map_err(|e| FetchError::parse("login", &body, e))?;
+    if info.passwordSalt.is_none() && info.requirePassword != Some(false) {
+        return Err(FetchError::response("login", "no passwordSalt", &body));
+    }
+    if !info.unknown.is_empty() {
+        let fields: Vec<&String> = info.unknown.keys().collect();
+        warn!("The login response has fields we don't know about: {:?}. \
+               If logging in fails, please report it along with the router's firmware version", fields);
+    }
+    Ok(info)
+}
+
+fn do_login(client: &reqwest::blocking::Client, gateway: &Gateway, password: &str, login_info: &LoginResponse) -> Result<AuthInfo, FetchError> {
+    // A router that was never set up has no password, and then there's nothing to hash
+    let hash = match (&login_info.passwordSalt, login_info.requirePassword) {
+        (_, Some(false)) => {
+            warn!("The router doesn't require a password, so anyone on its network can change its settings! \
+                   Set one in its admin page");
+            String::new()
+        }
+        (Some(salt), _) => {
+            let hash = gateway.password_hash().hash(password, salt);
+            redact::register(&hash);
+            hash
+        }
+        (None, _) => String::new(),
+    };
+    let mut body = json!({"password": hash});
+    if let Some(username) = &gateway.username {
+        body["username"] = json!(username);
+    }
+    let json = body.to_string();
+
+    let uri = gateway.base_uri.join("login")?;
+    gateway.throttle();
+    let response = client.post(uri)
+        .header(reqwest::header::CONTENT_TYPE, "application/json;charset=UTF-8")
+        .body(json)
+        .send()?;
+    gateway.check_certificate(&response)?;
+
+    if response.status().is_success() {
+        let cookie = |name: &str| match response.cookies().find(|c| c.name() == name) {
+            Some(cookie) => Ok(cookie.value().to_string()),
+            None => Err(AuthError::MissingCookie(name.to_string())),
+        };
+        Ok(AuthInfo {
+            token: cookie("XSRF-TOKEN")?,
+            session: cookie("Session")?,
+        })
+    } else {
+        let status = response.status();
+        let body = response.text().unwrap_or_default();
+        if let Some(max_users) = login_info.maxUsers {
+            debug!("The router allows {} users at a time", max_users);
+        }
+        Err(login_error(status, &body).into())
+    }
+}
+
+// Works out why the login failed, from the error code in the response if there is one
+fn login_error(status: reqwest::StatusCode, body: &str) -> AuthError {
+    let json: Value = serde_json::from_str(body).unwrap_or(Value::Null);
+    if json["denyState"].as_u64().unwrap_or(0) != 0 {
+        return AuthError::LockedOut(json["denyTimeout"].as_u64().unwrap_or(0));
+    }
+    match json["error"].as_u64() {
+        Some(LOGIN_ERROR_PASSWORD) => AuthError::WrongPassword,
+        Some(LOGIN_ERROR_MAX_SESSIONS) => AuthError::TooManySessions,
+        _ if status == reqwest::StatusCode::UNAUTHORIZED => AuthError::WrongPassword,
+        _ => AuthError::Failed {
+            status,
+            body: error::snippet(body),
+        },
+    }
+}
@@ -0,0 +1,155 @@
+// The Fios Home Router, G3100 (and the E3200 extender, which runs the same firmware)
+//
+// Unlike the G1100 there is no REST API. The admin page logs in through CGI scripts and then reads
+// the TR-181 data model objects with cgi_get:
+// 1) You call /loginStatus.cgi to get a passwordSalt and a loginToken.
+// 2) you take the Sha512(password + passwordSalt) to create a hash
+// 3) you post a form to /login.cgi with the hash and the token
+// 4) on successful login, a sysauth cookie is returned, which the cookie store sends from then on
+
+use super::tr181::{self, Counters, Object};
+use super::{Device, Diagnostics, Interface, NetworkStats, RadioStats, RouterClient, SsidTraffic, WifiNetwork};
+use crate::client::Gateway;
+use crate::error::AuthError;
+use crate::redact;
+use crate::FetchError;
+use tracing::debug;
+use std::cell::Cell;
+
+const WAN_STATS: &str = "Device.IP.Interface.2.Stats";
+const WAN_ADDRESS: &str = "Device.IP.Interface.2.IPv4Address";
+
+pub struct G3100 {
+    client: reqwest::blocking::Client,
+    gateway: Gateway,
+    last_counters: Cell<Option<Counters>>,
+}
+
+impl G3100 {
+    // The client must have a cookie store, to keep the session cookie
+    pub fn login(client: &reqwest::blocking::Client, gateway: &Gateway, password: &str) -> Result<G3100, FetchError> {
+        let router = G3100 {
+            client: client.clone(),
+            gateway: gateway.clone(),
+            last_counters: Cell::new(None),
+        };
+
+        let status = router.fetch_json("/loginStatus.cgi")?;
+        if let Some(token) = status["loginToken"].as_str() {
+            redact::register(token);
+        }
+        debug!("Got login status: {:#?}", status);
+        let salt = status["passwordSalt"].as_str().unwrap_or_default();
+        let token = match status["loginToken"].as_str() {
+            Some(token) => token,
+            None => return Err(FetchError::response("/loginStatus.cgi", "no loginToken", &status.to_string())),
+        };
+
+        let hash = gateway.password_hash().hash(password, salt);
+        redact::register(&hash);
+
+        let uri = gateway.root_uri("login.cgi")?;
+        gateway.throttle();
+        let response = router.client.post(uri)
+            .form(&[("luci_username", gateway.username.as_deref().unwrap_or("admin")), ("luci_password", hash.as_str()), ("luci_view", "Desktop"), ("token", token)])
+            .send()?;
+        gateway.check_certificate(&response)?;
+        let logged_in = response.cookies().any(|c| c.name() == "sysauth");
+        let status = response.status();
+        if !logged_in {
+            return Err(match status {
+                reqwest::StatusCode::FORBIDDEN => AuthError::LockedOut(0),
+                reqwest::StatusCode::SERVICE_UNAVAILABLE => AuthError::TooManySessions,
+                _ => AuthError::WrongPassword,
+            }
+            .into());
+        }
+        Ok(router)
+    }
+
+    fn get_object(&self, object: &str) -> Result<Vec<Object>, FetchError> {
+        Ok(self.get_named_objects(object)?.into_iter().map(|(_, object)| object).collect())
+    }
+
+    fn get_named_objects(&self, object: &str) -> Result<Vec<(String, Object)>, FetchError> {
+        let path = tr181::object_path(object);
+        let data = self.fetch_json(&path)?;
+        debug!("Got {} response: {:#?}", object, data);
+        tr181::parse_named_objects(&path, &data)
+    }
+}
+
+impl RouterClient for G3100 {
+    fn fetch(&self, api: &str) -> Result<String, FetchError> {
+        let uri = self.gateway.root_uri(api)?;
+        debug!("Fetching: {}", uri);
+        self.gateway.throttle();
+        let response = self.client.get(uri).send()?;
+        self.gateway.check_certificate(&response)?;
+        let status = response.status();
+        let body = response.text()?;
+        if !status.is_success() {
+            return Err(FetchError::status(api, status, &body));
+        }
+        Ok(body)
+    }
+
+    fn network_stats(&self) -> Result<NetworkStats, FetchError> {
+        tr181::network_stats(&self.last_counters, || Counters::from_stats(WAN_STATS, &self.get_object(WAN_STATS)?))
+    }
+
+    fn devices(&self) -> Result<Vec<Device>, FetchError> {
+        Ok(self.get_object(tr181::HOSTS)?.into_iter().map(tr181::device).collect())
+    }
+
+    fn wifi_networks(&self) -> Result<Vec<WifiNetwork>, FetchError> {
+        Ok(self.get_object(tr181::NEIGHBORS)?.into_iter().map(tr181::wifi_network).collect())
+    }
+
+    fn ssid_traffic(&self) -> Result<Vec<SsidTraffic>, FetchError> {
+        tr181::ssid_traffic(|object| self.get_named_objects(object))
+    }
+
+    fn radio_stats(&self) -> Result<Vec<RadioStats>, FetchError> {
+        tr181::radio_stats(|object| self.get_named_objects(object))
+    }
+
+    // Not every firmware has the MoCA table
+    fn interfaces(&self) -> Result<Vec<Interface>, FetchError> {
+        let mut interfaces = tr181::interfaces("ethernet", tr181::ETHERNET, self.get_named_objects(tr181::ETHERNET)?);
+        match self.get_named_objects(tr181::MOCA) {
+            Ok(moca) => interfaces.extend(tr181::interfaces("moca", tr181::MOCA, moca)),
+            Err(e) => debug!("Could not get the MoCA interfaces: {}", e),
+        }
+        Ok(interfaces)
+    }
+
+    fn diagnostics(&self) -> Result<Diagnostics, FetchError> {
+        Ok(tr181::diagnostics(&self.get_object(tr181::PING)?, self.get_named_objects(tr181::TRACEROUTE_HOPS)?))
+    }
+
+    fn minute_history(&self) -> Result<(Vec<u64>, Vec<u64>), FetchError> {
+        bail!("The G3100 only has the traffic totals, not a history");
+    }
+
+    fn wan_ip(&self) -> Result<String, FetchError> {
+        tr181::ip_address(WAN_ADDRESS, &self.get_object(WAN_ADDRESS)?)
+    }
+
+    fn firmware_version(&self) -> Result<String, FetchError> {
+        tr181::firmware_version(&self.get_object(tr181::DEVICE_INFO)?)
+    }
+
+    fn backup(&self) -> Result<Vec<u8>, FetchError> {
+        bail!("Configuration backups are not supported on the G3100 yet");
+    }
+
+    fn reboot(&self) -> Result<(), FetchError> {
+        bail!("Rebooting is not supported on the G3100 yet");
+    }
+
+    fn logout(&self) -> Result<(), FetchError> {
+        self.fetch("/logout.cgi")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+// The API of the different router models
+//
+// Each model gets a backend implementing RouterClient, which turns its responses into the common
+// types below. The collectors and commands only use those, so supporting a new model doesn't touch
+// them.
+
+pub mod auth;
+pub mod cr1000a;
+pub mod fields;
+pub mod g1100;
+pub mod g3100;
+pub mod schema;
+pub mod tr181;
+
+use crate::client::Gateway;
+use crate::redact;
+use crate::FetchError;
+use tracing::debug;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Model {
+    // Also the G1000
+    G1100,
+    // Also the E3200
+    G3100,
+    Cr1000a,
+}
+
+impl Model {
+    pub const NAMES: [&'static str; 5] = ["g1100", "g1000", "g3100", "e3200", "cr1000a"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Model::G1100 => "G1100",
+            Model::G3100 => "G3100",
+            Model::Cr1000a => "CR1000A",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Model> {
+        match name.to_lowercase().as_str() {
+            "g1100" | "g1000" => Some(Model::G1100),
+            "g3100" | "e3200" => Some(Model::G3100),
+            "cr1000a" => Some(Model::Cr1000a),
+            _ => None,
+        }
+    }
+}
+
+// Works out the model from the endpoints the router answers on before logging in. The G1100 has its
+// REST API (without a salt when there's no password), while the newer ones have /loginStatus.cgi, which names the model.
+pub fn detect(client: &reqwest::blocking::Client, gateway: &Gateway) -> Result<Model, FetchError> {
+    let get = |uri: reqwest::Url| -> Result<Option<Value>, FetchError> {
+        gateway.throttle();
+        let response = client.get(uri).send()?;
+        gateway.check_certificate(&response)?;
+        let status = response.status();
+        let body = response.text()?;
+        if !status.is_success() {
+            return Ok(None);
+        }
+        Ok(serde_json::from_str(&body).ok())
+    };
+
+    if let Some(login) = get(gateway.base_uri.join("login")?)? {
+        if login.get("passwordSalt").is_some() || login.get("requirePassword").is_some() {
+            return Ok(Model::G1100);
+        }
+    }
+    if let Some(status) = get(gateway.root_uri("loginStatus.cgi")?)? {
+        if let Some(token) = status["loginToken"].as_str() {
+            redact::register(token);
+        }
+        debug!("Got login status: {:#?}", status);
+        let name = status["modelName"].as_str().unwrap_or_default().to_uppercase();
+        if let Some(firmware) = status["firmwareVersion"].as_str() {
+            debug!("Router firmware is {}", firmware);
+        }
+        return Ok(if name.contains("CR1000") { Model::Cr1000a } else { Model::G3100 });
+    }
+    bail!("Could not work out the router model, give it with --model");
+}
+
+pub trait RouterClient {
+    // The raw response of an API endpoint
+    fn fetch(&self, api: &str) -> Result<String, FetchError>;
+
+    fn fetch_json(&self, api: &str) -> Result<Value, FetchError> {
+        let body = self.fetch(api)?;
+        serde_json::from_str(&body).map_err(|e| FetchError::parse(api, &body, e))
+    }
+
+    fn network_stats(&self) -> Result<NetworkStats, FetchError>;
+
+    fn devices(&self) -> Result<Vec<Device>, FetchError>;
+
+    // The other WiFi networks the router can see
+    fn wifi_networks(&self) -> Result<Vec<WifiNetwork>, FetchError>;
+
+    // The bytes received and sent on each SSID since the router started
+    fn ssid_traffic(&self) -> Result<Vec<SsidTraffic>, FetchError> {
+        bail!("This router doesn't break the traffic down by SSID");
+    }
+
+    // The retries, failed frames, and airtime of each WiFi radio
+    fn radio_stats(&self) -> Result<Vec<RadioStats>, FetchError> {
+        bail!("This router doesn't have WiFi radio statistics");
+    }
+
+    // The Ethernet and MoCA interfaces, and whether they're up
+    fn interfaces(&self) -> Result<Vec<Interface>, FetchError> {
+        bail!("This router's interface status isn't supported");
+    }
+
+    // The results of the last ping and traceroute the router ran itself, from its diagnostics page.
+    // Starting them isn't supported, that takes writing to the router.
+    fn diagnostics(&self) -> Result<Diagnostics, FetchError> {
+        bail!("This router's diagnostics aren't supported");
+    }
+
+    // The rx/tx bytes of each of the last minutes, oldest first
+    fn minute_history(&self) -> Result<(Vec<u64>, Vec<u64>), FetchError>;
+
+    // The public IPv4 address of the WAN interface
+    fn wan_ip(&self) -> Result<String, FetchError>;
+
+    // The version of the router's firmware
+    fn firmware_version(&self) -> Result<String, FetchError>;
+
+    // A small request, to keep the session from expiring
+    fn keep_alive(&self) -> Result<(), FetchError> {
+        self.wan_ip().map(|_| ())
+    }
+
+    // TODO: counting the failed and successful admin logins, local and remote, would show password
+    // guessing against the router, but none of the firmwares we know of has the admin access log in
+    // its API. The G1100's system log page and the TR-181 Device.DeviceInfo.VendorLogFile objects
+    // might have it, a dump of either from a firmware that logs the logins would tell.
+
+    // The router configuration, as the file the admin page would download
+    fn backup(&self) -> Result<Vec<u8>, FetchError>;
+
+    fn reboot(&self) -> Result<(), FetchError>;
+
+    fn logout(&self) -> Result<(), FetchError>;
+}
+
+// Traffic counters of the WAN interface. The rx/tx bytes are for the last minute. The error counters
+// are None when the firmware doesn't have them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NetworkStats {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_errors: Option<u64>,
+    pub rx_dropped: Option<u64>,
+}
+
+// A device the router knows about, whether or not it's connected right now
+#[derive(Debug, Clone, PartialEq)]
+pub struct Device {
+    pub name: Option<String>,
+    pub mac: Option<String>,
+    pub ip: Option<String>,
+    pub connected: bool,
+    // WiFi signal strength in dBm, where the router reports it
+    pub signal: Option<i64>,
+}
+
+// A neighboring WiFi network, from a site survey
+#[derive(Debug, Clone, PartialEq)]
+pub struct WifiNetwork {
+    pub ssid: Option<String>,
+    pub channel: Option<u64>,
+}
+
+// The traffic of one WiFi network of the router, e.g. the main one or the guest one. The counters are
+// totals since the router started.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SsidTraffic {
+    pub ssid: String,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+// The congestion numbers of one WiFi radio, e.g. the 5GHz one. The counters are totals since the
+// router started, and each is None when the firmware doesn't have it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RadioStats {
+    pub band: String,
+    pub retries: Option<u64>,
+    pub failed_frames: Option<u64>,
+    // How much of the time the channel is busy
+    pub airtime_percent: Option<u64>,
+}
+
+// A network interface of the router
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interface {
+    pub name: String,
+    // "wan", "ethernet", or "moca"
+    pub kind: String,
+    pub up: bool,
+    // Seconds since it last went up or down, where the router says
+    pub last_change: Option<u64>,
+}
+
+// The last ping and traceroute run on the router, with times in milliseconds. None when there wasn't one.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Diagnostics {
+    pub ping: Option<Ping>,
+    pub traceroute: Vec<Hop>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ping {
+    pub host: String,
+    // e.g. "Complete", or an error
+    pub state: String,
+    pub successes: u64,
+    pub failures: u64,
+    pub average_ms: Option<u64>,
+    pub min_ms: Option<u64>,
+    pub max_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hop {
+    pub host: String,
+    pub address: String,
+    pub rtt_ms: Vec<u64>,
+}
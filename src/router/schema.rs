@@ -0,0 +1,66 @@
+// Spotting fields in the G1100's responses that we don't know about
+//
+// New firmware versions add fields now and then, which might be worth collecting. The top level keys
+// of each response (of each element, for lists) are compared with the ones we've seen in the
+// firmware versions we have fixtures for, and new ones are logged once, so they show up in the logs
+// users paste into issues. schema_unknown_fields counts them per API, to spot them on a dashboard.
+// The TR-181 routers use a standard data model, so they aren't checked.
+
+use crate::metrics::Metric;
+use tracing::info;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+
+const KNOWN: [(&str, &[&str]); 3] = [
+    (
+        "network/1",
+        &[
+            "bandwidth", "connectionType", "id", "ipAddress", "name", "rxDropped", "rxErrors", "rxPackets",
+            "status", "txDropped", "txErrors", "txPackets", "uptime",
+        ],
+    ),
+    ("devices", &["connectionType", "icon", "id", "ip", "leaseType", "mac", "name", "rssi", "status"]),
+    ("wireless/sitesurvey", &["bssid", "channel", "security", "signal", "ssid"]),
+];
+
+// API -> the unknown keys seen so far
+static UNKNOWN: Mutex<BTreeMap<String, BTreeSet<String>>> = Mutex::new(BTreeMap::new());
+
+// Notes the keys of a response that aren't in the known ones for its API
+pub fn check(api: &str, data: &Value) {
+    let known = match KNOWN.iter().find(|(name, _)| *name == api) {
+        Some((_, known)) => known,
+        None => return,
+    };
+    let objects: Vec<&Value> = match data {
+        Value::Array(elements) => elements.iter().collect(),
+        data => vec![data],
+    };
+    let mut unknown = UNKNOWN.lock().unwrap();
+    let seen = unknown.entry(api.to_string()).or_default();
+    for key in objects.iter().filter_map(|o| o.as_object()).flat_map(|o| o.keys()) {
+        if !known.contains(&key.as_str()) && seen.insert(key.clone()) {
+            info!(
+                "The router's {} response has a field we don't know about: {}. If it looks useful, please \
+                 report it with the output of `fios-stats dump {}`",
+                api, key, api
+            );
+        }
+    }
+}
+
+// The number of unknown keys seen for each API checked so far
+pub fn metrics() -> Vec<Metric> {
+    UNKNOWN
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(api, keys)| Metric::new("schema_unknown_fields", keys.len() as u64).tag("api", api))
+        .collect()
+}
+
+// The unknown keys seen for an API, e.g. for tests
+pub fn unknown_fields(api: &str) -> Vec<String> {
+    UNKNOWN.lock().unwrap().get(api).map(|keys| keys.iter().cloned().collect()).unwrap_or_default()
+}
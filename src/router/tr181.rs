@@ -0,0 +1,292 @@
+// Shared by the routers whose admin page reads the TR-181 data model objects with cgi_get (the
+// G3100 and the CR1000A)
+
+use super::{fields, Device, Diagnostics, Hop, Interface, NetworkStats, Ping, RadioStats, SsidTraffic, WifiNetwork};
+use crate::FetchError;
+use serde_json::Value;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const HOSTS: &str = "Device.Hosts.Host";
+pub const NEIGHBORS: &str = "Device.WiFi.NeighboringWiFiDiagnostic.Result";
+pub const SSIDS: &str = "Device.WiFi.SSID";
+pub const RADIOS: &str = "Device.WiFi.Radio";
+pub const DEVICE_INFO: &str = "Device.DeviceInfo";
+pub const ETHERNET: &str = "Device.Ethernet.Interface";
+pub const MOCA: &str = "Device.MoCA.Interface";
+pub const PING: &str = "Device.IP.Diagnostics.IPPing";
+pub const TRACEROUTE_HOPS: &str = "Device.IP.Diagnostics.TraceRoute.RouteHops";
+
+// How long to wait for a second reading, when there is no earlier one to compare with
+const FIRST_SAMPLE: Duration = Duration::from_secs(5);
+
+// One instance of an object, as parameter name -> value
+pub type Object = HashMap<String, String>;
+
+pub fn object_path(object: &str) -> String {
+    format!("/cgi/cgi_get?Object={}", object)
+}
+
+// cgi_get answers with {"Objects": [{"ObjName": "...", "Param": [{"ParamName": "...", "ParamValue": "..."}]}]}
+pub fn parse_objects(path: &str, data: &Value) -> Result<Vec<Object>, FetchError> {
+    Ok(parse_named_objects(path, data)?.into_iter().map(|(_, object)| object).collect())
+}
+
+// Along with the name of each object, e.g. "Device.WiFi.SSID.1."
+pub fn parse_named_objects(path: &str, data: &Value) -> Result<Vec<(String, Object)>, FetchError> {
+    let objects = match data["Objects"].as_array() {
+        Some(objects) => objects,
+        None => return Err(FetchError::response(path, "no Objects list", &data.to_string())),
+    };
+    Ok(objects
+        .iter()
+        .map(|o| {
+            let params = o["Param"].as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|p| Some((p["ParamName"].as_str()?.to_string(), p["ParamValue"].as_str()?.to_string())))
+                .collect();
+            (o["ObjName"].as_str().unwrap_or_default().to_string(), params)
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Counters {
+    at: Instant,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    rx_errors: Option<u64>,
+    rx_dropped: Option<u64>,
+}
+
+impl Counters {
+    // From the Stats object of an interface
+    pub fn from_stats(object: &str, stats: &[Object]) -> Result<Counters, FetchError> {
+        let stats = stats.first().cloned().unwrap_or_default();
+        let optional = |name: &str| {
+            let value = stats.get(name).and_then(|v| v.parse::<u64>().ok());
+            if value.is_none() {
+                fields::missing(object, name);
+            }
+            value
+        };
+        let number = |name: &str| match optional(name) {
+            Some(n) => Ok(n),
+            None => Err(FetchError::response(object, &format!("no number for {}", name), &format!("{:?}", stats))),
+        };
+        Ok(Counters {
+            at: Instant::now(),
+            rx_bytes: number("BytesReceived")?,
+            tx_bytes: number("BytesSent")?,
+            rx_errors: optional("ErrorsReceived"),
+            rx_dropped: optional("DiscardPacketsReceived"),
+        })
+    }
+}
+
+// The counters are totals since the router booted, so the per minute numbers are worked out from the
+// difference to the last reading. The first time around that means taking two readings.
+pub fn network_stats(
+    last: &Cell<Option<Counters>>,
+    read: impl Fn() -> Result<Counters, FetchError>,
+) -> Result<NetworkStats, FetchError> {
+    let previous = match last.get() {
+        Some(previous) => previous,
+        None => {
+            let first = read()?;
+            thread::sleep(FIRST_SAMPLE);
+            first
+        }
+    };
+    let current = read()?;
+    last.set(Some(current));
+
+    let elapsed = current.at - previous.at;
+    Ok(NetworkStats {
+        rx_bytes: per_minute(previous.rx_bytes, current.rx_bytes, elapsed),
+        tx_bytes: per_minute(previous.tx_bytes, current.tx_bytes, elapsed),
+        rx_errors: current.rx_errors,
+        rx_dropped: current.rx_dropped,
+    })
+}
+
+// Scales the growth of a counter between two readings to a minute. A counter that went backwards
+// was reset by a reboot, so then the new value is all there is.
+fn per_minute(previous: u64, current: u64, elapsed: Duration) -> u64 {
+    let delta = current.checked_sub(previous).unwrap_or(current);
+    let millis = elapsed.as_millis().max(1);
+    (u128::from(delta) * 60_000 / millis) as u64
+}
+
+// From the IPv4Address object of an interface
+pub fn ip_address(object: &str, addresses: &[Object]) -> Result<String, FetchError> {
+    match addresses.iter().find_map(|a| a.get("IPAddress").filter(|ip| !ip.is_empty())) {
+        Some(ip) => Ok(ip.clone()),
+        None => Err(FetchError::response(object, "no IPAddress", &format!("{:?}", addresses))),
+    }
+}
+
+// From the DeviceInfo object
+pub fn firmware_version(infos: &[Object]) -> Result<String, FetchError> {
+    match infos.iter().find_map(|i| i.get("SoftwareVersion").filter(|v| !v.is_empty())) {
+        Some(version) => Ok(version.clone()),
+        None => Err(FetchError::response(DEVICE_INFO, "no SoftwareVersion", &format!("{:?}", infos))),
+    }
+}
+
+// The enabled interfaces of a table like ETHERNET, which are of the kind given unless they're the
+// upstream (WAN) one
+pub fn interfaces(kind: &str, table: &str, objects: Vec<(String, Object)>) -> Vec<Interface> {
+    objects
+        .into_iter()
+        .filter(|(name, object)| is_instance(table, name) && enabled(object))
+        .map(|(name, mut object)| {
+            let upstream = matches!(object.get("Upstream").map(String::as_str), Some("1") | Some("true"));
+            let label = object.remove("Alias").filter(|a| !a.is_empty()).or_else(|| object.remove("Name").filter(|n| !n.is_empty()));
+            Interface {
+                name: label.unwrap_or_else(|| name.trim_end_matches('.').to_string()),
+                kind: if upstream { "wan" } else { kind }.to_string(),
+                up: object.get("Status").map(String::as_str) == Some("Up"),
+                last_change: object.get("LastChange").and_then(|v| v.parse().ok()),
+            }
+        })
+        .collect()
+}
+
+// From the IPPing object and the TraceRoute hops. A diagnostic that was never run is "None".
+pub fn diagnostics(pings: &[Object], hops: Vec<(String, Object)>) -> Diagnostics {
+    let number = |object: &Object, name: &str| object.get(name).and_then(|v| v.parse::<u64>().ok());
+    let run = |p: &&Object| !matches!(p.get("DiagnosticsState").map(String::as_str), None | Some("None"));
+    let ping = pings.first().filter(run).map(|p| {
+        // The times are 0 when no reply came back
+        let time = |name: &str| number(p, name).filter(|_| number(p, "SuccessCount").unwrap_or(0) > 0);
+        Ping {
+            host: p.get("Host").cloned().unwrap_or_default(),
+            state: p.get("DiagnosticsState").cloned().unwrap_or_default(),
+            successes: number(p, "SuccessCount").unwrap_or(0),
+            failures: number(p, "FailureCount").unwrap_or(0),
+            average_ms: time("AverageResponseTime"),
+            min_ms: time("MinimumResponseTime"),
+            max_ms: time("MaximumResponseTime"),
+        }
+    });
+    let traceroute = hops
+        .into_iter()
+        .filter(|(name, _)| is_instance(TRACEROUTE_HOPS, name))
+        .map(|(_, mut hop)| Hop {
+            host: hop.remove("Host").unwrap_or_default(),
+            address: hop.remove("HostAddress").unwrap_or_default(),
+            rtt_ms: hop.get("RTTimes").map(|t| t.split(',').filter_map(|t| t.trim().parse().ok()).collect()).unwrap_or_default(),
+        })
+        .collect();
+    Diagnostics { ping, traceroute }
+}
+
+pub fn device(mut host: Object) -> Device {
+    Device {
+        name: host.remove("HostName").filter(|n| !n.is_empty()),
+        mac: host.remove("PhysAddress"),
+        ip: host.remove("IPAddress"),
+        connected: matches!(host.get("Active").map(String::as_str), Some("1") | Some("true")),
+        // Only in the WiFi AssociatedDevice objects, not the hosts
+        signal: None,
+    }
+}
+
+pub fn wifi_network(mut network: Object) -> WifiNetwork {
+    WifiNetwork {
+        ssid: network.remove("SSID"),
+        channel: network.get("Channel").and_then(|c| c.parse().ok()),
+    }
+}
+
+// The traffic counters of each enabled SSID. An SSID on both bands is two instances, which are added up.
+pub fn ssid_traffic(
+    get: impl Fn(&str) -> Result<Vec<(String, Object)>, FetchError>,
+) -> Result<Vec<SsidTraffic>, FetchError> {
+    let objects = get(SSIDS)?;
+    let mut traffic: BTreeMap<String, SsidTraffic> = BTreeMap::new();
+    for (name, ssid) in objects.iter().filter(|(name, _)| is_instance(SSIDS, name)) {
+        let ssid_name = match ssid.get("SSID").filter(|s| !s.is_empty()) {
+            Some(ssid_name) if enabled(ssid) => ssid_name,
+            _ => continue,
+        };
+        let (stats_name, stats) = instance_stats(&objects, name, &get)?;
+        let number = |param: &str| {
+            stats.get(param).and_then(|v| v.parse::<u64>().ok()).ok_or_else(|| {
+                FetchError::response(&stats_name, &format!("no number for {}", param), &format!("{:?}", stats))
+            })
+        };
+        let entry = traffic.entry(ssid_name.clone()).or_insert_with(|| SsidTraffic {
+            ssid: ssid_name.clone(),
+            rx_bytes: 0,
+            tx_bytes: 0,
+        });
+        entry.rx_bytes += number("BytesReceived")?;
+        entry.tx_bytes += number("BytesSent")?;
+    }
+    Ok(traffic.into_values().collect())
+}
+
+// The retry, failed frame, and airtime numbers of each enabled radio. These aren't in every firmware,
+// and the ones that are missing are left out.
+pub fn radio_stats(
+    get: impl Fn(&str) -> Result<Vec<(String, Object)>, FetchError>,
+) -> Result<Vec<RadioStats>, FetchError> {
+    let objects = get(RADIOS)?;
+    let mut radios = Vec::new();
+    for (name, radio) in objects.iter().filter(|(name, _)| is_instance(RADIOS, name)) {
+        if !enabled(radio) {
+            continue;
+        }
+        let band = match radio.get("OperatingFrequencyBand").filter(|b| !b.is_empty()) {
+            Some(band) => band.clone(),
+            None => name.trim_end_matches('.').rsplit('.').next().unwrap_or_default().to_string(),
+        };
+        let (stats_name, stats) = instance_stats(&objects, name, &get)?;
+        // Some firmwares have the TR-181 names, others the ones of the SSID stats
+        let optional = |params: &[&str]| {
+            let value = params.iter().find_map(|p| stats.get(*p).and_then(|v| v.parse::<u64>().ok()));
+            if value.is_none() {
+                fields::missing(&stats_name, params[0]);
+            }
+            value
+        };
+        radios.push(RadioStats {
+            band,
+            retries: optional(&["RetransCount", "RetryCount"]),
+            failed_frames: optional(&["FailedRetransCount", "ErrorsSent"]),
+            airtime_percent: optional(&["ActivityFactor", "ChannelUtilization"]),
+        });
+    }
+    Ok(radios)
+}
+
+fn enabled(object: &Object) -> bool {
+    !matches!(object.get("Enable").map(String::as_str), Some("0") | Some("false"))
+}
+
+// The Stats object of an instance like "Device.WiFi.SSID.1.", and its name. Some firmwares send it
+// along with the instances, others need it read on its own.
+fn instance_stats(
+    objects: &[(String, Object)],
+    instance: &str,
+    get: impl Fn(&str) -> Result<Vec<(String, Object)>, FetchError>,
+) -> Result<(String, Object), FetchError> {
+    let stats_name = format!("{}Stats", instance);
+    let stats = match objects.iter().find(|(n, _)| n.trim_end_matches('.') == stats_name) {
+        Some((_, stats)) => stats.clone(),
+        None => get(&stats_name)?.into_iter().next().map(|(_, stats)| stats).unwrap_or_default(),
+    };
+    Ok((stats_name, stats))
+}
+
+// An instance like "Device.WiFi.SSID.1." of the table, rather than one of its sub-objects
+fn is_instance(table: &str, name: &str) -> bool {
+    name.strip_prefix(table)
+        .and_then(|rest| rest.strip_prefix('.'))
+        .is_some_and(|rest| rest.trim_end_matches('.').parse::<u32>().is_ok())
+}
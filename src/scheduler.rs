@@ -0,0 +1,427 @@
+// Daemon mode: runs every collector on its own interval against one shared router session
+//
+// The router only allows a handful of concurrent sessions, so rather than logging in per
+// collector we keep a single session around, and only log in again when the router stops taking it.
+
+use crate::alerts::Alerts;
+use crate::collectors::{self, devices, Collector, ErrorCounts};
+use crate::config::Config;
+use crate::correlate;
+use crate::firmware;
+use crate::health::Health;
+use crate::hostnames;
+use crate::metrics::{self, Metric};
+use crate::notify::{self, Event, Notifier};
+use crate::output;
+use crate::queue::{self, Overflow, Queue};
+use crate::rollup::Rollup;
+use crate::sinks;
+use crate::usage;
+use crate::wan;
+use crate::web::Dashboard;
+use crate::{Device, FetchError, FiosClient, Gateway};
+use tracing::{debug, error, info, info_span, warn};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// How long to wait before writing to a sink that is down again, at first and at most
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+// How long the session can go unused before it's used just to keep it alive
+const SESSION_KEEPALIVE: Duration = Duration::from_secs(5 * 60);
+
+// Boards without a battery backed clock, like the Raspberry Pi, boot with the time in the past until
+// NTP sets it, and the data collected until then would be stored at the wrong time. Any time before
+// 2024 is taken as a clock that isn't set yet.
+const CLOCK_SET_AFTER: u64 = 1_704_067_200;
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+struct Entry {
+    collector: &'static dyn Collector,
+    interval: Duration,
+    next_run: Instant,
+}
+
+// Whether the router is answering. Every cycle gives a router_up data point, and while the router is
+// unreachable router_down_seconds has how long it's been. Once it's back the whole outage is written
+// as router_outage, in seconds.
+#[derive(Default)]
+struct Uptime {
+    down_since: Option<Instant>,
+}
+
+impl Uptime {
+    fn update(&mut self, reachable: bool) -> Vec<Metric> {
+        let now = Instant::now();
+        let mut data = vec![Metric::new("router_up", u64::from(reachable))];
+        match (reachable, self.down_since) {
+            (false, None) => {
+                warn!("Router is unreachable");
+                self.down_since = Some(now);
+                data.push(Metric::new("router_down_seconds", 0));
+            }
+            (false, Some(since)) => data.push(Metric::new("router_down_seconds", (now - since).as_secs())),
+            (true, Some(since)) => {
+                let seconds = (now - since).as_secs();
+                info!("Router is reachable again after {}s", seconds);
+                self.down_since = None;
+                data.push(Metric::new("router_outage", seconds));
+            }
+            (true, None) => (),
+        }
+        data
+    }
+}
+
+pub fn run(
+    client: &reqwest::blocking::Client,
+    gateway: &Gateway,
+    password: &str,
+    config: &Config,
+) -> Result<(), FetchError> {
+    let intervals = config.collector_intervals().map_err(simple_error::SimpleError::new)?;
+    let format = config.output_format().map_err(simple_error::SimpleError::new)?;
+    let unit = config.unit().map_err(simple_error::SimpleError::new)?;
+    let start = Instant::now();
+    let mut entries: Vec<Entry> = intervals
+        .into_iter()
+        .map(|(collector, interval)| Entry {
+            collector,
+            interval,
+            next_run: start,
+        })
+        .collect();
+    if entries.is_empty() {
+        bail!("No collectors enabled, available ones are: {:?}", collectors::names());
+    }
+    for entry in &entries {
+        info!("Collecting {} every {}s", entry.collector.name(), entry.interval.as_secs());
+    }
+
+    let tags = config.all_tags();
+    let filter = config.metric_filter();
+    let wan_watcher = wan::Watcher::from_config(config).map_err(simple_error::SimpleError::new)?;
+    let firmware_watcher = firmware::Watcher::from_config(config).map_err(simple_error::SimpleError::new)?;
+    let usage_tracker = usage::Tracker::from_config(config);
+    let mut alerts = Alerts::from_config(config).map_err(simple_error::SimpleError::new)?;
+    let notifier = Notifier::from_config(config).map_err(simple_error::SimpleError::new)?;
+    let dashboard = match &config.dashboard {
+        Some(address) => Some(Dashboard::start(address)?),
+        None => None,
+    };
+    let health = match &config.health {
+        Some(address) => Some(Health::start(address, entries.iter().map(|e| e.interval).min().unwrap())?),
+        None => None,
+    };
+    // The MAC addresses seen so far, when notifying about new devices
+    let mut known_devices: Option<HashSet<String>> = None;
+    let mut uptime = Uptime::default();
+    let mut rollup = if config.hourly_rollups { Some(Rollup::default()) } else { None };
+    let mut collector_errors = ErrorCounts::default();
+    let overflow = match &config.queue_overflow {
+        Some(name) => match Overflow::from_name(name) {
+            Some(overflow) => overflow,
+            None => bail!("Unknown queue_overflow {}, use one of {:?}", name, Overflow::NAMES),
+        },
+        None => Overflow::DropOldest,
+    };
+    let queue = Arc::new(Queue::new(config.queue_size.unwrap_or(queue::DEFAULT_CAPACITY), overflow));
+    {
+        let (client, config, queue) = (client.clone(), config.clone(), queue.clone());
+        thread::spawn(move || write_queued(&client, &config, &queue));
+    }
+
+    let keepalive = match &config.session_keepalive {
+        Some(value) => match parse_duration(value) {
+            Some(duration) if duration.as_secs() == 0 => None,
+            Some(duration) => Some(duration),
+            None => bail!("Invalid session_keepalive: {}", value),
+        },
+        None => Some(SESSION_KEEPALIVE),
+    };
+
+    if config.wait_for_clock {
+        wait_for_clock();
+    }
+
+    let mut session: Option<FiosClient> = None;
+    let mut cycle: u64 = 0;
+    // The time of the last data, which the next has to come after
+    let mut last_collected = 0;
+    let mut clock_behind = false;
+    loop {
+        cycle += 1;
+        let next_run = entries.iter().map(|e| e.next_run).min().unwrap();
+        // With long intervals the router would let the session expire in between, so it's used
+        // every now and then while waiting
+        loop {
+            let now = Instant::now();
+            if next_run <= now {
+                break;
+            }
+            match keepalive {
+                Some(keepalive) if next_run - now > keepalive && session.is_some() => {
+                    thread::sleep(keepalive);
+                    match session.as_ref().map(|s| s.keep_alive()) {
+                        Some(Err(e)) if e.is_session_lost() => {
+                            debug!("Keeping the session alive failed, logging in again next time: {}", e);
+                            end_session(&mut session);
+                        }
+                        Some(Err(e)) => debug!("Keeping the session alive failed: {}", e),
+                        _ => (),
+                    }
+                }
+                _ => thread::sleep(next_run - now),
+            }
+        }
+
+        let _cycle = info_span!("cycle", cycle).entered();
+
+        let now = Instant::now();
+        let mut due = Vec::new();
+        for entry in entries.iter_mut().filter(|e| e.next_run <= now) {
+            due.push(entry.collector);
+            // Keep to the original cadence, unless we've fallen more than an interval behind
+            entry.next_run += entry.interval;
+            if entry.next_run <= now {
+                entry.next_run = now + entry.interval;
+            }
+        }
+
+        // Only an error without any answer counts as the router being down, not e.g. a wrong password
+        let mut reachable = true;
+        if session.is_none() {
+            match FiosClient::login_with(client, gateway, password) {
+                Ok(new_session) => session = Some(new_session),
+                Err(e) => {
+                    error!("Could not log in to router: {}", e);
+                    reachable = !e.is_unreachable();
+                }
+            }
+        }
+
+        let devices_due = due.iter().any(|c| c.name() == devices::NAME);
+        let mut data: Vec<Metric> = Vec::new();
+        let mut session_lost = false;
+        if let Some(current) = &session {
+            let (collected, errors) = collectors::run(&due, current, config, &mut collector_errors);
+            data.extend(collected);
+            session_lost = errors.iter().any(FetchError::is_session_lost);
+            reachable = !errors.iter().any(FetchError::is_unreachable);
+            if let (Some(watcher), true) = (&wan_watcher, reachable) {
+                match watcher.check(client, current) {
+                    Ok(metrics) => data.extend(metrics),
+                    Err(e) => warn!("Could not check the WAN IP: {}", e),
+                }
+            }
+            if let (Some(watcher), true) = (&firmware_watcher, reachable) {
+                match watcher.check(client, current) {
+                    Ok(metrics) => data.extend(metrics),
+                    Err(e) => warn!("Could not check the firmware version: {}", e),
+                }
+            }
+            // The device list itself, rather than the counts the collector gives
+            if devices_due && reachable && (notifier.wants(notify::NEW_DEVICE) || dashboard.is_some()) {
+                match correlate::devices(current, config) {
+                    Ok(mut devices) => {
+                        match hostnames::nicknames(config) {
+                            Ok(nicknames) => hostnames::rename(&mut devices, &nicknames),
+                            Err(e) => warn!("Could not read the device nicknames: {}", e),
+                        }
+                        if let Some(dashboard) = &dashboard {
+                            dashboard.set_devices(&devices);
+                        }
+                        if notifier.wants(notify::NEW_DEVICE) {
+                            if let Err(e) = notify_new_devices(client, &devices, &notifier, &mut known_devices) {
+                                warn!("Could not send new device notification: {}", e);
+                            }
+                        }
+                    }
+                    Err(e) => warn!("Could not get the devices: {}", e),
+                }
+            }
+        }
+        if session_lost {
+            // Most likely it expired, so get a new one next time around
+            end_session(&mut session);
+        }
+        data.extend(uptime.update(reachable));
+        if let Some(health) = &health {
+            health.record_cycle(reachable);
+        }
+        if let Some(tracker) = &usage_tracker {
+            match tracker.update(&data) {
+                Ok(metrics) => data.extend(metrics),
+                Err(e) => warn!("Could not update the data usage: {}", e),
+            }
+        }
+
+        if let Some(dashboard) = &dashboard {
+            dashboard.record(&data);
+        }
+        data.push(Metric::new("write_queue_length", queue.len() as u64));
+        data.retain(|m| filter.matches(&m.name));
+        metrics::convert_units(&mut data, unit);
+        let states = alerts.evaluate(client, &data);
+        data.extend(states);
+        metrics::add_tags(&mut data, &tags);
+        // The data can sit in the queue for a while, so it gets the time it was collected. If the
+        // clock was set back, e.g. by the host of a container syncing it, the data would overwrite what
+        // was stored for those times, so it's stored just after the last data instead until the clock
+        // catches up.
+        let mut collected = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+        if collected < last_collected && !clock_behind {
+            warn!("The system clock went back {}s, storing the data just after the last", last_collected - collected);
+        }
+        clock_behind = collected < last_collected;
+        collected = collected.max(last_collected + 1);
+        last_collected = collected;
+        metrics::set_time(&mut data, collected);
+        if let Some(rollup) = &mut rollup {
+            let rollups = rollup.add(&data);
+            data.extend(rollups);
+        }
+        if let Some(format) = format {
+            output::print(format, &data, config);
+        }
+        queue.push(data);
+    }
+}
+
+// Logs out of a session the router no longer takes, in case it still holds a login slot for it
+fn end_session(session: &mut Option<FiosClient>) {
+    if let Some(Err(e)) = session.take().map(FiosClient::logout) {
+        debug!("Could not log out: {}", e);
+    }
+}
+
+// Writes the queued data to the sinks. When a sink can't be reached or has trouble of its own (a 5xx
+// status), the same data is tried again until it works, while newer data waits in the queue. Any
+// other failure drops the data, trying again wouldn't help.
+fn write_queued(client: &reqwest::blocking::Client, config: &Config, queue: &Queue) {
+    loop {
+        let data = queue.pop();
+        let mut delay = RETRY_DELAY;
+        loop {
+            match sinks::write(client, config, &data) {
+                Ok(()) => break,
+                Err(e) if retryable(&e) => {
+                    warn!("Could not store data, trying again in {}s: {}", delay.as_secs(), e);
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                }
+                Err(e) => {
+                    error!("Could not store data: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn retryable(error: &FetchError) -> bool {
+    match error {
+        FetchError::Sink(error) => retryable(error),
+        FetchError::Status { status, .. } => status.is_server_error(),
+        error => error.is_unreachable(),
+    }
+}
+
+// Sends a notification for each device that hasn't been seen since the daemon started. The first
+// time around the devices are only recorded, or every device would be new.
+fn notify_new_devices(
+    client: &reqwest::blocking::Client,
+    devices: &[Device],
+    notifier: &Notifier,
+    known: &mut Option<HashSet<String>>,
+) -> Result<(), FetchError> {
+    let first = known.is_none();
+    let known = known.get_or_insert_with(HashSet::new);
+    for device in devices {
+        let mac = match &device.mac {
+            Some(mac) => mac.to_lowercase(),
+            None => continue,
+        };
+        if !known.insert(mac.clone()) || first {
+            continue;
+        }
+        let name = device.name.clone().unwrap_or_else(|| "unknown".to_string());
+        info!("New device on the network: {} ({})", name, mac);
+        let event = Event::new(notify::NEW_DEVICE, "New device", &format!("{} ({}) joined the network", name, mac))
+            .field("name", name.as_str())
+            .field("mac", mac.as_str())
+            .field("ip", device.ip.clone().unwrap_or_default());
+        notifier.send(client, &event)?;
+    }
+    Ok(())
+}
+
+// Parses "60", "60s", "5m", or "1h"
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let (number, multiplier) = match value.chars().last()? {
+        's' => (&value[..value.len() - 1], 1),
+        'm' => (&value[..value.len() - 1], 60),
+        'h' => (&value[..value.len() - 1], 60 * 60),
+        _ => (value, 1),
+    };
+    let n = number.parse::<u64>().ok()?;
+    Some(Duration::from_secs(n.checked_mul(multiplier)?))
+}
+
+// Parses a "collector=duration" interval argument
+pub fn parse_interval(value: &str) -> Result<(&'static dyn Collector, Duration), String> {
+    let mut parts = value.splitn(2, '=');
+    let name = parts.next().unwrap_or("");
+    let collector = collectors::by_name(name).ok_or_else(|| format!("Unknown collector: {}", name))?;
+    let interval = parts
+        .next()
+        .and_then(parse_duration)
+        .ok_or_else(|| format!("Invalid interval: {}", value))?;
+    Ok((collector, interval))
+}
+
+fn wait_for_clock() {
+    let now = || SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    if now() >= CLOCK_SET_AFTER {
+        return;
+    }
+    warn!("The system clock isn't set yet, waiting for it before collecting");
+    while now() < CLOCK_SET_AFTER {
+        thread::sleep(CLOCK_CHECK_INTERVAL);
+    }
+    info!("The system clock is set, starting");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("60"), Some(Duration::from_secs(60)));
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("5m"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("0"), Some(Duration::from_secs(0)));
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("5d"), None);
+        assert_eq!(parse_duration("-5m"), None);
+        assert_eq!(parse_duration("999999999999999999h"), None);
+    }
+
+    #[test]
+    fn intervals() {
+        let (collector, interval) = parse_interval("devices=10m").unwrap();
+        assert_eq!(collector.name(), "devices");
+        assert_eq!(interval, Duration::from_secs(600));
+        assert_eq!(parse_interval("site_survey=0").unwrap().1, Duration::from_secs(0));
+        assert!(parse_interval("nonexistent=10m").is_err());
+        assert!(parse_interval("devices").is_err());
+        assert!(parse_interval("devices=often").is_err());
+        assert!(parse_interval("devices=999999999999999999h").is_err());
+    }
+}
@@ -0,0 +1,231 @@
+// Running the daemon as a Windows service
+//
+// `service install` registers fios-stats with the service manager, to start with Windows and run
+// `service run` with the same config file. There's no terminal to log to, so the log goes to the
+// Application event log instead, with fios-stats as the source. The daemon only returns on errors,
+// so it runs on a thread of its own, and stopping the service ends the process.
+
+use fios_stats::error::EXIT_ERROR;
+use fios_stats::{redact, FetchError};
+use tracing::{error, info, Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::EnvFilter;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceInfo,
+    ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+use windows_sys::Win32::System::EventLog::{
+    RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE,
+    REPORT_EVENT_TYPE,
+};
+use winreg::enums::HKEY_LOCAL_MACHINE;
+use winreg::RegKey;
+use std::error::Error;
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::sync::mpsc;
+use std::time::Duration;
+use std::{env, fs, ptr, thread};
+
+const NAME: &str = "fios-stats";
+const DISPLAY_NAME: &str = "Fios Gateway Stats";
+const DESCRIPTION: &str = "Collects network stats from the Fios router";
+
+const EVENT_SOURCE_KEY: &str = r"SYSTEM\CurrentControlSet\Services\EventLog\Application\fios-stats";
+
+pub fn install(args: &clap::ArgMatches) -> Result<(), Box<dyn Error>> {
+    // The service starts in the system directory, and has no command line of its own to give flags on
+    let config = match args.value_of("config") {
+        Some(path) => fs::canonicalize(path)?,
+        None => bail!("The service needs a config file to run with, given with -c"),
+    };
+    let mut arguments: Vec<OsString> = vec!["service".into(), "run".into(), "--config".into(), config.into()];
+    if let Some(profile) = args.value_of("profile") {
+        arguments.extend(vec!["--profile".into(), profile.into()]);
+    }
+    if args.is_present("all") {
+        arguments.push("--all".into());
+    }
+
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+    let service = manager.create_service(
+        &ServiceInfo {
+            name: NAME.into(),
+            display_name: DISPLAY_NAME.into(),
+            service_type: ServiceType::OWN_PROCESS,
+            start_type: ServiceStartType::AutoStart,
+            error_control: ServiceErrorControl::Normal,
+            executable_path: env::current_exe()?,
+            launch_arguments: arguments,
+            dependencies: vec![],
+            account_name: None,
+            account_password: None,
+        },
+        ServiceAccess::CHANGE_CONFIG,
+    )?;
+    service.set_description(DESCRIPTION)?;
+
+    // Event Viewer looks up the text of each event in a message file. The one that comes with .NET
+    // has every event ID be just the text logged, so that's what shows.
+    let (source, _) = RegKey::predef(HKEY_LOCAL_MACHINE).create_subkey(EVENT_SOURCE_KEY)?;
+    let root = env::var("SystemRoot").unwrap_or_else(|_| r"C:\Windows".to_string());
+    source.set_value(
+        "EventMessageFile",
+        &format!(r"{}\Microsoft.NET\Framework64\v4.0.30319\EventLogMessages.dll", root),
+    )?;
+    source.set_value("TypesSupported", &7u32)?;
+
+    info!("Installed the {} service, start it with `sc start {}`", NAME, NAME);
+    Ok(())
+}
+
+pub fn uninstall() -> Result<(), Box<dyn Error>> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    manager.open_service(NAME, ServiceAccess::DELETE)?.delete()?;
+    if let Err(e) = RegKey::predef(HKEY_LOCAL_MACHINE).delete_subkey_all(EVENT_SOURCE_KEY) {
+        info!("Could not remove the event log source: {}", e);
+    }
+    info!("Removed the {} service", NAME);
+    Ok(())
+}
+
+// Called by the service manager, through `service run`
+pub fn run(filter: EnvFilter) -> Result<(), Box<dyn Error>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(false)
+        // The event log has the time of each event
+        .without_time()
+        .with_writer(EventLog::open()?)
+        .init();
+    service_dispatcher::start(NAME, ffi_service_main)?;
+    Ok(())
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("Service failed: {}", e);
+    }
+}
+
+fn run_service() -> Result<(), Box<dyn Error>> {
+    // The exit code, when the service is stopped or the daemon fails
+    let (done, stopped) = mpsc::channel();
+
+    let stop = done.clone();
+    let status = service_control_handler::register(NAME, move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            let _ = stop.send(ServiceExitCode::Win32(0));
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })?;
+    let set_status = |state, controls_accepted, exit_code| {
+        status.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted,
+            exit_code,
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        })
+    };
+    set_status(
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        ServiceExitCode::Win32(0),
+    )?;
+
+    thread::spawn(move || {
+        let matches = crate::cli::app().get_matches();
+        let args = matches.subcommand_matches("service").unwrap_or(&matches);
+        let exit_code = match crate::run_commands("daemon", args) {
+            Ok(()) => ServiceExitCode::Win32(0),
+            Err(e) => {
+                error!("{}", e);
+                let code = e.downcast_ref::<FetchError>().map(|e| e.exit_code()).unwrap_or(EXIT_ERROR);
+                ServiceExitCode::ServiceSpecific(code as u32)
+            }
+        };
+        let _ = done.send(exit_code);
+    });
+
+    let exit_code = stopped.recv().unwrap_or(ServiceExitCode::Win32(0));
+    info!("Stopping");
+    set_status(ServiceState::Stopped, ServiceControlAccept::empty(), exit_code)?;
+    Ok(())
+}
+
+// Writes each log line as an event, with the type going by its level
+struct EventLog {
+    handle: isize,
+}
+
+impl EventLog {
+    fn open() -> io::Result<EventLog> {
+        let handle = unsafe { RegisterEventSourceW(ptr::null(), wide(NAME).as_ptr()) };
+        if handle == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(EventLog { handle })
+    }
+}
+
+impl<'a> MakeWriter<'a> for EventLog {
+    type Writer = Event<'a>;
+
+    fn make_writer(&'a self) -> Event<'a> {
+        Event {
+            log: self,
+            kind: EVENTLOG_INFORMATION_TYPE,
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Event<'a> {
+        let kind = match *meta.level() {
+            Level::ERROR => EVENTLOG_ERROR_TYPE,
+            Level::WARN => EVENTLOG_WARNING_TYPE,
+            _ => EVENTLOG_INFORMATION_TYPE,
+        };
+        Event { log: self, kind }
+    }
+}
+
+// The log formatter writes each line in one go, see redact.rs
+struct Event<'a> {
+    log: &'a EventLog,
+    kind: REPORT_EVENT_TYPE,
+}
+
+impl Write for Event<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = wide(redact::redact(&String::from_utf8_lossy(buf)).trim_end());
+        let strings = [text.as_ptr()];
+        let reported = unsafe {
+            ReportEventW(self.log.handle, self.kind, 0, 0, ptr::null_mut(), 1, 0, strings.as_ptr(), ptr::null())
+        };
+        if reported == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// A null terminated UTF-16 string
+fn wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain(Some(0)).collect()
+}
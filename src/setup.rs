@@ -0,0 +1,110 @@
+// Interactive first-run setup
+//
+// Asks for the few things needed to get going, checks that they work, and writes a config file, so
+// nobody has to read up on all the flags just to try the program out.
+
+use crate::password;
+use fios_stats::error::AuthError;
+use fios_stats::{Config, FetchError, FiosClient, Gateway, DEFAULT_GATEWAY};
+use std::io::{self, BufRead, Write};
+use toml::value::{Table, Value};
+use tracing::info;
+
+pub const DEFAULT_CONFIG_FILE: &str = "fios-stats.toml";
+
+pub fn run() -> Result<(), FetchError> {
+    println!("This sets up a configuration file for fios-stats. Press enter to accept the [default].\n");
+
+    let mut config = Config::default();
+    let mut file = Table::new();
+
+    let gateway = ask("Router address", Some(DEFAULT_GATEWAY))?;
+    if gateway != DEFAULT_GATEWAY {
+        file.insert("gateway".to_string(), Value::String(gateway.clone()));
+        config.gateway = Some(gateway);
+    }
+
+    println!("\nThe router uses a self-signed certificate. It can either be trusted the first time we");
+    println!("connect, and then checked on every later connection, or not checked at all.");
+    if ask_yes_no("Trust the certificate on first use?", true)? {
+        file.insert("tofu".to_string(), Value::Boolean(true));
+        config.tofu = true;
+    } else {
+        file.insert("insecure".to_string(), Value::Boolean(true));
+        config.insecure = true;
+    }
+
+    let mut gateway = Gateway::from_config(&config)?;
+    let client = gateway.client_builder().build()?;
+    if config.tofu {
+        gateway.trust_on_first_use(&client)?;
+    }
+
+    let password = loop {
+        let password = match rpassword::read_password_from_tty(Some("\nRouter admin password: ")) {
+            Ok(password) => password,
+            Err(e) => bail!("Could not read password: {}", e),
+        };
+        match FiosClient::login_with(&client, &gateway, &password) {
+            Ok(session) => {
+                session.logout()?;
+                println!("Logged in to the router.");
+                break password;
+            }
+            Err(FetchError::Auth(AuthError::WrongPassword)) => println!("Wrong password, try again."),
+            Err(e) => return Err(e),
+        }
+    };
+    if ask_yes_no("Save the password in the OS keyring?", true)? {
+        password::save_to_keyring(config.gateway(), &password).map_err(simple_error::SimpleError::new)?;
+    } else {
+        println!("The password will have to be given with -p, --password-file, or FIOS_PASSWORD.");
+    }
+
+    println!();
+    let influx_db = ask("InfluxDB write URI, e.g. http://localhost:8086/write?db=fios_data (empty for none)", None)?;
+    if !influx_db.is_empty() {
+        file.insert("influxdb".to_string(), Value::String(influx_db));
+    }
+
+    let daemon = ask_yes_no("\nKeep running and collect continuously (instead of once per run)?", false)?;
+    file.insert("daemon".to_string(), Value::Boolean(daemon));
+
+    let path = ask("\nWhere to save the configuration", Some(DEFAULT_CONFIG_FILE))?;
+    let contents = match toml::to_string(&Value::Table(file)) {
+        Ok(contents) => contents,
+        Err(e) => bail!("Could not create configuration: {}", e),
+    };
+    if let Err(e) = std::fs::write(&path, contents) {
+        bail!("Could not write {}: {}", path, e);
+    }
+    info!("Configuration written to {}", path);
+    println!("\nAll done! Run `fios-stats -c {} check` to check everything works.", path);
+    Ok(())
+}
+
+pub fn ask(question: &str, default: Option<&str>) -> Result<String, FetchError> {
+    match default {
+        Some(default) => print!("{} [{}]: ", question, default),
+        None => print!("{}: ", question),
+    }
+    let mut answer = String::new();
+    let read = io::stdout().flush().and_then(|_| io::stdin().lock().read_line(&mut answer));
+    if let Err(e) = read {
+        bail!("Could not read answer: {}", e);
+    }
+    let answer = answer.trim();
+    Ok(match default {
+        Some(default) if answer.is_empty() => default.to_string(),
+        _ => answer.to_string(),
+    })
+}
+
+fn ask_yes_no(question: &str, default: bool) -> Result<bool, FetchError> {
+    let answer = ask(question, Some(if default { "Y/n" } else { "y/N" }))?;
+    Ok(match answer.to_lowercase().as_str() {
+        "y" | "yes" => true,
+        "n" | "no" => false,
+        _ => default,
+    })
+}
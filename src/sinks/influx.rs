@@ -0,0 +1,168 @@
+// InfluxDB sink, using the 1.x /write endpoint and line protocol
+
+use crate::metrics::{self, Metric};
+use crate::FetchError;
+use serde_json::Value;
+use tracing::{debug, warn};
+use std::collections::{BTreeMap, BTreeSet};
+
+// What the write errors say failed
+const WRITE: &str = "InfluxDB write";
+
+// When some lines are rejected, they are logged and dropped. InfluxDB 1.x has stored the others already
+// ("partial write"), while other servers reject the whole batch, so then the rest is sent again.
+pub fn write(client: &reqwest::blocking::Client, influx_uri: &str, data: &[Metric]) -> Result<(), FetchError> {
+    let influx_data = metrics::to_line_protocol(data);
+    debug!("Influx data:\n{}", influx_data);
+
+    // Not the URI in the log and the errors, it can have the password in it
+    debug!("Saving data to InfluxDB");
+    let (status, body) = post(client, influx_uri, &influx_data)?;
+    if status == reqwest::StatusCode::NO_CONTENT {
+        return Ok(());
+    }
+    let rejected = rejected_lines(&body);
+    if status != reqwest::StatusCode::BAD_REQUEST || rejected.is_empty() {
+        return Err(FetchError::status(WRITE, status, &body));
+    }
+    for line in &rejected {
+        warn!("InfluxDB rejected: {}", line);
+    }
+    if body.contains("partial write") {
+        return Ok(());
+    }
+
+    let rest: String = influx_data
+        .lines()
+        .filter(|line| !rejected.iter().any(|r| r == *line))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    if rest.is_empty() {
+        return Err(FetchError::status(WRITE, status, &body));
+    }
+    let (status, body) = post(client, influx_uri, &rest)?;
+    if status != reqwest::StatusCode::NO_CONTENT {
+        return Err(FetchError::status(WRITE, status, &body));
+    }
+    warn!("Stored the data without the {} rejected lines", rejected.len());
+    Ok(())
+}
+
+fn post(client: &reqwest::blocking::Client, influx_uri: &str, lines: &str) -> Result<(reqwest::StatusCode, String), FetchError> {
+    let response = client.post(influx_uri)
+        .header(reqwest::header::CONTENT_TYPE, "application/json;charset=UTF-8")
+        .body(lines.to_string())
+        .send()?;
+    let status = response.status();
+    Ok((status, response.text()?))
+}
+
+// The lines named in an error like {"error": "partial write: unable to parse 'a,b=c value=x': invalid
+// field format\nunable to parse ..."}
+fn rejected_lines(body: &str) -> Vec<String> {
+    const PREFIX: &str = "unable to parse '";
+    let data: Value = match serde_json::from_str(body) {
+        Ok(data) => data,
+        Err(_) => return Vec::new(),
+    };
+    let error = data["error"].as_str().unwrap_or_default();
+    error
+        .split(PREFIX)
+        .skip(1)
+        .filter_map(|part| part.find("': ").map(|end| part[..end].to_string()))
+        .collect()
+}
+
+// The tags of the series written, by measurement and time
+type Expected<'a> = BTreeMap<(&'a str, u64), BTreeSet<&'a [(String, String)]>>;
+
+// Reads back the points just written, and warns about any that InfluxDB doesn't have, e.g. because they
+// are outside the retention policy, which it can drop without an error. The points need a time to be
+// found by. Returns how many are missing.
+pub fn verify(client: &reqwest::blocking::Client, influx_uri: &str, data: &[Metric]) -> Result<u64, FetchError> {
+    // Points of the same series and time overwrite each other, so those count once
+    let mut expected: Expected = BTreeMap::new();
+    for metric in data {
+        if let Some(time) = metric.time {
+            expected.entry((metric.name.as_str(), time)).or_default().insert(metric.tags.as_slice());
+        }
+    }
+    if expected.is_empty() {
+        return Ok(0);
+    }
+
+    let write_uri = reqwest::Url::parse(influx_uri)?;
+    let prefix = match write_uri.query_pairs().find(|(key, _)| key == "rp") {
+        Some((_, rp)) => format!("{}.", quote(&rp)),
+        None => String::new(),
+    };
+    let statements: Vec<String> = expected
+        .keys()
+        .map(|(name, time)| format!("SELECT count(\"value\") FROM {}{} WHERE time = {}s", prefix, quote(name), time))
+        .collect();
+    let results = query_results(client, influx_uri, &statements.join("; "))?;
+
+    let mut missing = 0;
+    for (i, ((name, time), series)) in expected.iter().enumerate() {
+        let found = results
+            .get(i)
+            .and_then(|result| result.pointer("/series/0/values/0/1"))
+            .and_then(Value::as_u64)
+            .unwrap_or(0);
+        let wanted = series.len() as u64;
+        if found < wanted {
+            warn!(
+                "InfluxDB has {} of the {} {} points just written for {}, check its retention policy",
+                found, wanted, name, time
+            );
+            missing += wanted - found;
+        }
+    }
+    Ok(missing)
+}
+
+// An InfluxQL identifier
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Runs an InfluxQL query on the database of a /write URI, and returns the rows of the first series,
+// with the times in Unix seconds. No rows when there is no data.
+pub fn query(client: &reqwest::blocking::Client, influx_uri: &str, query: &str) -> Result<Vec<Vec<Value>>, FetchError> {
+    let results = query_results(client, influx_uri, query)?;
+    let rows = match results.first().and_then(|result| result.pointer("/series/0/values")).and_then(Value::as_array) {
+        Some(rows) => rows.iter().map(|row| row.as_array().cloned().unwrap_or_default()).collect(),
+        None => Vec::new(),
+    };
+    Ok(rows)
+}
+
+// The result of each statement of a query
+fn query_results(client: &reqwest::blocking::Client, influx_uri: &str, query: &str) -> Result<Vec<Value>, FetchError> {
+    let write_uri = reqwest::Url::parse(influx_uri)?;
+    let mut uri = write_uri.join("query")?;
+    uri.set_query(None);
+    {
+        let mut pairs = uri.query_pairs_mut();
+        // The database and the login, if any
+        for (key, value) in write_uri.query_pairs().filter(|(key, _)| ["db", "u", "p"].contains(&&**key)) {
+            pairs.append_pair(&key, &value);
+        }
+        pairs.append_pair("epoch", "s").append_pair("q", query);
+    }
+    debug!("InfluxDB query: {}", query);
+
+    let response = client.get(uri).send()?;
+    let status = response.status();
+    let body = response.text()?;
+    // Not the URI, it can have the password in it
+    if !status.is_success() {
+        return Err(FetchError::status("InfluxDB query", status, &body));
+    }
+    let data: Value = serde_json::from_str(&body).map_err(|e| FetchError::parse("InfluxDB query", &body, e))?;
+    let results = data["results"].as_array().cloned().unwrap_or_default();
+    if let Some(error) = results.iter().find_map(|result| result["error"].as_str()) {
+        return Err(FetchError::response("InfluxDB query", error, query));
+    }
+    Ok(results)
+}
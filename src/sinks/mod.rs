@@ -0,0 +1,92 @@
+// Where the collected data ends up
+//
+// Each sink is behind its own cargo feature, so minimal builds only pull in what they need.
+
+#[cfg(feature = "influx")]
+pub mod influx;
+#[cfg(feature = "remote-write")]
+pub mod remote_write;
+
+// TODO: Prometheus can't scrape us yet. An endpoint for it should use the names in prometheus.rs,
+// and speak OpenMetrics, giving the points their own time: the router's per minute readings are taken
+// when collected, not when scraped, and without it the scrape timing skews them.
+
+// TODO: there's no Graphite sink either. People with one have their naming schemes, so it should take
+// both tagged series (net_rx;host=...) and a template for plain dotted names, e.g.
+// "fios.{host}.{interface}.rx", with the tags a template leaves out ending up in the name some other
+// way so that series don't collide.
+
+use crate::config::Config;
+use crate::metrics::Metric;
+use crate::FetchError;
+#[cfg(feature = "influx")]
+use tracing::{debug, warn};
+#[cfg(any(feature = "influx", feature = "remote-write"))]
+use tracing::info;
+#[cfg(any(feature = "influx", feature = "remote-write"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Writes the data to every configured sink, or with --dry-run prints what would have been written
+#[cfg_attr(not(any(feature = "influx", feature = "remote-write")), allow(unused_variables))]
+pub fn write(client: &reqwest::blocking::Client, config: &Config, data: &[Metric]) -> Result<(), FetchError> {
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    if let Some(influx_db) = &config.influxdb {
+        #[cfg(feature = "influx")]
+        {
+            if config.dry_run {
+                print!("# InfluxDB: {}\n{}", influx_db, crate::metrics::to_line_protocol(data));
+            } else {
+                // Read back afterwards with verify_writes, for which the points need a time
+                let timed;
+                let data = if config.verify_writes {
+                    timed = with_time(data);
+                    &timed[..]
+                } else {
+                    data
+                };
+                influx::write(client, influx_db, data).map_err(|e| FetchError::Sink(Box::new(e)))?;
+                info!("Data stored to InfluxDB");
+                if config.verify_writes {
+                    match influx::verify(client, influx_db, data) {
+                        Ok(0) => debug!("All the data is in InfluxDB"),
+                        Ok(_) => (),
+                        Err(e) => warn!("Could not read back the data from InfluxDB: {}", e),
+                    }
+                }
+            }
+        }
+        #[cfg(not(feature = "influx"))]
+        bail!("Built without the influx feature, can't write to {}", influx_db);
+    }
+
+    if let Some(uri) = &config.remote_write {
+        #[cfg(feature = "remote-write")]
+        {
+            // Prometheus goes by the time of the samples, so they all need one
+            let data = with_time(data);
+            if config.dry_run {
+                print!("# Remote write: {}\n{}", uri, remote_write::to_text(&data));
+            } else {
+                remote_write::write(client, uri, &data).map_err(|e| FetchError::Sink(Box::new(e)))?;
+                info!("Data stored with remote write");
+            }
+        }
+        #[cfg(not(feature = "remote-write"))]
+        bail!("Built without the remote-write feature, can't write to {}", uri);
+    }
+    Ok(())
+}
+
+// The data, with the current time for the points that don't have one yet
+#[cfg(any(feature = "influx", feature = "remote-write"))]
+fn with_time(data: &[Metric]) -> Vec<Metric> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    let mut data = data.to_vec();
+    for metric in data.iter_mut().filter(|m| m.time.is_none()) {
+        metric.time = Some(now);
+    }
+    data
+}
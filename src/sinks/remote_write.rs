@@ -0,0 +1,137 @@
+// Prometheus remote_write sink, for pushing to Mimir, Thanos, VictoriaMetrics and the like
+//
+// The WriteRequest protobuf is small enough to encode by hand. Snappy is too, by not compressing: a
+// snappy block can hold the data as one literal, which every decoder takes. A cycle's data is a few
+// kB at most, so that costs little, and saves two dependencies. The data gets Prometheus names and
+// units, see prometheus.rs, and their HELP and TYPE go along as metadata.
+
+use crate::metrics::{Kind, Metric};
+use crate::prometheus::{self, Family};
+use crate::FetchError;
+use tracing::debug;
+use std::collections::BTreeMap;
+
+// By family name, the family and its series: their labels, sorted by name, and their samples by time
+// in ms
+type Families = BTreeMap<String, (Family, BTreeMap<Vec<(String, String)>, BTreeMap<u64, f64>>)>;
+
+// The points need a time, see sinks::with_time
+pub fn write(client: &reqwest::blocking::Client, uri: &str, data: &[Metric]) -> Result<(), FetchError> {
+    debug!("Remote write data:\n{}", to_text(data));
+    let body = snappy(&encode(&families(data)));
+
+    debug!("Saving data to {}", uri);
+    let response = client
+        .post(uri)
+        .header(reqwest::header::CONTENT_TYPE, "application/x-protobuf")
+        .header(reqwest::header::CONTENT_ENCODING, "snappy")
+        .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+        .body(body)
+        .send()?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(FetchError::status(uri, status, &response.text()?));
+    }
+    Ok(())
+}
+
+// The data in the Prometheus text format, for --dry-run and the debug log
+pub fn to_text(data: &[Metric]) -> String {
+    let mut text = String::new();
+    for (name, (family, series)) in families(data) {
+        text.push_str(&family.metadata());
+        for (labels, samples) in series {
+            let labels: Vec<String> = labels
+                .iter()
+                .filter(|(key, _)| key != "__name__")
+                .map(|(key, value)| format!("{}=\"{}\"", key, value.replace('\\', "\\\\").replace('"', "\\\"")))
+                .collect();
+            for (time, value) in samples {
+                text.push_str(&format!("{}{{{}}} {} {}\n", name, labels.join(","), value, time));
+            }
+        }
+    }
+    text
+}
+
+fn families(data: &[Metric]) -> Families {
+    let mut families = Families::new();
+    for metric in data {
+        let family = prometheus::family(&metric.name);
+        let value = family.value(metric);
+        let mut labels: Vec<(String, String)> =
+            metric.tags.iter().map(|(key, value)| (prometheus::sanitize(key), value.clone())).collect();
+        labels.push(("__name__".to_string(), family.name.clone()));
+        labels.sort();
+        let (_, series) = families.entry(family.name.clone()).or_insert_with(|| (family, BTreeMap::new()));
+        series.entry(labels).or_default().insert(metric.time.unwrap_or_default() * 1000, value);
+    }
+    families
+}
+
+// A WriteRequest, see prometheus/prompb/remote.proto and types.proto
+fn encode(families: &Families) -> Vec<u8> {
+    let mut request = Vec::new();
+    for (labels, samples) in families.values().flat_map(|(_, series)| series) {
+        let mut timeseries = Vec::new();
+        for (name, value) in labels {
+            let mut label = Vec::new();
+            field(&mut label, 1, name.as_bytes());
+            field(&mut label, 2, value.as_bytes());
+            field(&mut timeseries, 1, &label);
+        }
+        for (time, value) in samples {
+            // The value is field 1, a double, and the time field 2, an int64
+            let mut sample = vec![0x09];
+            sample.extend_from_slice(&value.to_le_bytes());
+            sample.push(0x10);
+            varint(&mut sample, *time);
+            field(&mut timeseries, 2, &sample);
+        }
+        field(&mut request, 1, &timeseries);
+    }
+    // The metadata is field 3, with the type as field 1, the name 2, and the help 4
+    for (name, (family, _)) in families {
+        let mut metadata = vec![0x08];
+        varint(&mut metadata, if family.kind == Kind::Counter { 1 } else { 2 });
+        field(&mut metadata, 2, name.as_bytes());
+        field(&mut metadata, 4, family.help.as_bytes());
+        field(&mut request, 3, &metadata);
+    }
+    request
+}
+
+// A length-delimited field
+fn field(out: &mut Vec<u8>, number: u8, bytes: &[u8]) {
+    out.push((number << 3) | 2);
+    varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+// A snappy block with the data as one literal: the length, and a literal tag holding the length minus
+// one, in the tag itself when it's below 60 and in the 1-4 bytes after it otherwise
+fn snappy(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 10);
+    varint(&mut out, data.len() as u64);
+    if data.is_empty() {
+        return out;
+    }
+    let n = (data.len() - 1) as u32;
+    if n < 60 {
+        out.push((n as u8) << 2);
+    } else {
+        let count = 4 - n.leading_zeros() as usize / 8;
+        out.push(((59 + count) as u8) << 2);
+        out.extend_from_slice(&n.to_le_bytes()[..count]);
+    }
+    out.extend_from_slice(data);
+    out
+}
@@ -0,0 +1,136 @@
+// Starting the daemon at boot
+//
+// `startup` prints a launchd plist on macOS, or a systemd unit elsewhere, that runs the daemon with
+// the flags given along with it, so getting the stats collected every minute from boot on is a matter
+// of saving the file where it says. The config file path is made absolute, as neither starts the
+// program in the current directory.
+
+use std::error::Error;
+use std::ffi::OsString;
+use std::{env, fs};
+use tracing::info;
+
+const LABEL: &str = "com.github.beaufour.fios-stats";
+
+pub fn run(args: &clap::ArgMatches) -> Result<(), Box<dyn Error>> {
+    let mut arguments = vec![fs::canonicalize(env::current_exe()?)?.to_string_lossy().into_owned()];
+    arguments.extend(daemon_arguments(args.value_of("config"))?);
+    arguments.push("daemon".to_string());
+
+    let launchd = match (args.is_present("launchd"), args.is_present("systemd")) {
+        (true, _) => true,
+        (_, true) => false,
+        _ => cfg!(target_os = "macos"),
+    };
+    if launchd {
+        let path = dirs::home_dir().unwrap_or_default().join("Library").join("LaunchAgents").join(format!("{}.plist", LABEL));
+        print!("{}", plist(&arguments));
+        info!("Save this as {} and run `launchctl load -w {}`", path.display(), path.display());
+    } else {
+        print!("{}", systemd_unit(&arguments));
+        info!("Save this as /etc/systemd/system/fios-stats.service and run `systemctl enable --now fios-stats`");
+    }
+    Ok(())
+}
+
+// The flags this was run with, without the startup command and its own. A config file from
+// FIOS_CONFIG is passed on as a flag, as the environment isn't.
+fn daemon_arguments(config: Option<&str>) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut arguments = Vec::new();
+    let mut config_seen = false;
+    let mut command_seen = false;
+    let mut raw = env::args_os().skip(1).map(OsString::into_string);
+    while let Some(argument) = raw.next() {
+        let argument = argument.map_err(|a| format!("Invalid argument: {:?}", a))?;
+        match argument.as_str() {
+            "startup" if !command_seen => command_seen = true,
+            "--launchd" | "--systemd" => (),
+            // It would be readable by anyone in the unit file, and in `ps` output
+            "-p" | "--password" => bail!("Give the password with --password-file or the keyring instead of -p"),
+            a if a.starts_with("--password=") => {
+                bail!("Give the password with --password-file or the keyring instead of --password")
+            }
+            "-c" | "--config" => {
+                let path = match raw.next() {
+                    Some(path) => path.map_err(|a| format!("Invalid argument: {:?}", a))?,
+                    None => bail!("--config needs a file"),
+                };
+                arguments.push(argument);
+                arguments.push(absolute(&path)?);
+                config_seen = true;
+            }
+            a if a.starts_with("--config=") => {
+                arguments.push(format!("--config={}", absolute(&a["--config=".len()..])?));
+                config_seen = true;
+            }
+            _ => arguments.push(argument),
+        }
+    }
+    if let (Some(config), false) = (config, config_seen) {
+        arguments.push("--config".to_string());
+        arguments.push(absolute(config)?);
+    }
+    Ok(arguments)
+}
+
+fn absolute(path: &str) -> Result<String, Box<dyn Error>> {
+    let path = fs::canonicalize(path).map_err(|e| format!("Could not find {}: {}", path, e))?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+fn plist(arguments: &[String]) -> String {
+    let log = dirs::home_dir().unwrap_or_default().join("Library").join("Logs").join("fios-stats.log");
+    let mut plist = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n<dict>\n",
+    );
+    plist.push_str(&format!("    <key>Label</key>\n    <string>{}</string>\n", LABEL));
+    plist.push_str("    <key>ProgramArguments</key>\n    <array>\n");
+    for argument in arguments {
+        plist.push_str(&format!("        <string>{}</string>\n", escape_xml(argument)));
+    }
+    plist.push_str("    </array>\n");
+    plist.push_str("    <key>RunAtLoad</key>\n    <true/>\n");
+    // Started again if it exits, at most every 10s
+    plist.push_str("    <key>KeepAlive</key>\n    <true/>\n");
+    plist.push_str(&format!(
+        "    <key>StandardErrorPath</key>\n    <string>{}</string>\n",
+        escape_xml(&log.to_string_lossy())
+    ));
+    plist.push_str("</dict>\n</plist>\n");
+    plist
+}
+
+fn systemd_unit(arguments: &[String]) -> String {
+    let command: Vec<String> = arguments.iter().map(|a| quote_systemd(a)).collect();
+    format!(
+        "[Unit]\n\
+         Description=Fios router stats collection\n\
+         Wants=network-online.target\n\
+         After=network-online.target\n\
+         \n\
+         [Service]\n\
+         ExecStart={}\n\
+         Restart=on-failure\n\
+         RestartSec=30\n\
+         \n\
+         [Install]\n\
+         WantedBy=multi-user.target\n",
+        command.join(" ")
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// systemd expands % specifiers and $ variables, and splits on spaces unless quoted
+fn quote_systemd(argument: &str) -> String {
+    let escaped = argument.replace('%', "%%").replace('$', "$$");
+    if escaped.is_empty() || escaped.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\' || c == '\'') {
+        format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        escaped
+    }
+}
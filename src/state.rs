@@ -0,0 +1,76 @@
+// Where the files we keep between runs go: the known gateway certificates, the last WAN address and
+// firmware version, the data usage counters, and the MACs of devices that change them
+//
+// They live in a directory of their own, $XDG_STATE_HOME/fios-stats (~/.local/state/fios-stats) on
+// Linux, ~/Library/Application Support/fios-stats on macOS, and %LOCALAPPDATA%\fios-stats on Windows,
+// or wherever state_dir / --state-dir says. Earlier versions kept them as dot files in the home
+// directory, which are moved over the first time they are used.
+
+use crate::config::Config;
+use tracing::{info, warn};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+const NAME: &str = "fios-stats";
+
+pub fn dir(config: &Config) -> PathBuf {
+    if let Some(dir) = &config.state_dir {
+        return PathBuf::from(dir);
+    }
+    match dirs::state_dir().or_else(dirs::data_local_dir) {
+        Some(dir) => dir.join(NAME),
+        None => dirs::home_dir().unwrap_or_default().join(format!(".{}", NAME)),
+    }
+}
+
+// The path of a state file like "usage", making sure the directory is there
+pub fn file(config: &Config, name: &str) -> PathBuf {
+    let dir = dir(config);
+    if let Err(e) = fs::create_dir_all(&dir) {
+        warn!("Could not create the state directory {}: {}", dir.display(), e);
+    }
+    let path = dir.join(name);
+    migrate(&path, name);
+    path
+}
+
+// Moves ~/.fios-stats-<name> from earlier versions to the state directory
+fn migrate(path: &Path, name: &str) {
+    let legacy = match dirs::home_dir() {
+        Some(home) => home.join(format!(".{}-{}", NAME, name)),
+        None => return,
+    };
+    if path.exists() || !legacy.exists() {
+        return;
+    }
+    // A rename doesn't work across file systems
+    let moved = fs::rename(&legacy, path).or_else(|_| fs::copy(&legacy, path).and_then(|_| fs::remove_file(&legacy)));
+    match moved {
+        Ok(()) => info!("Moved {} to {}", legacy.display(), path.display()),
+        Err(e) => warn!("Could not move {} to {}: {}", legacy.display(), path.display(), e),
+    }
+}
+
+// A file of "key value" lines, like the last WAN address of each gateway
+pub fn load_values(path: &Path) -> Result<BTreeMap<String, String>, String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+    };
+    let mut values = BTreeMap::new();
+    for line in contents.lines() {
+        // The value is the rest of the line, a firmware version or a host name can have spaces
+        if let Some((key, value)) = line.trim().split_once(char::is_whitespace) {
+            values.insert(key.to_string(), value.trim().to_string());
+        }
+    }
+    Ok(values)
+}
+
+pub fn save_values(path: &Path, values: &BTreeMap<String, String>) -> Result<(), String> {
+    let contents: String = values.iter().map(|(key, value)| format!("{} {}\n", key, value)).collect();
+    fs::write(path, contents).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
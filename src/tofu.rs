@@ -0,0 +1,33 @@
+// Trust on first use store for router certificates
+//
+// The first time we connect to a gateway its certificate fingerprint is recorded, and from then on
+// it is pinned just like with --pin-sha256. The file has one "host fingerprint" pair per line.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+pub fn load(path: &Path) -> Result<BTreeMap<String, String>, String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+    };
+    let mut known = BTreeMap::new();
+    for line in contents.lines() {
+        let mut parts = line.split_whitespace();
+        if let (Some(host), Some(fingerprint)) = (parts.next(), parts.next()) {
+            known.insert(host.to_string(), fingerprint.to_string());
+        }
+    }
+    Ok(known)
+}
+
+pub fn save(path: &Path, known: &BTreeMap<String, String>) -> Result<(), String> {
+    let contents: String = known
+        .iter()
+        .map(|(host, fingerprint)| format!("{} {}\n", host, fingerprint))
+        .collect();
+    fs::write(path, contents).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
@@ -0,0 +1,171 @@
+// Month to date data usage, from the bandwidth collector's data
+//
+// The bandwidth collector gives the bytes of the last minute, so the usage is estimated by counting
+// that rate for the time since the previous reading. Longer gaps than MAX_GAP, e.g. while nothing was
+// running, aren't counted. The totals for each gateway are kept in a file, so they add up across
+// one-off runs and daemon restarts. Months are calendar months in UTC. With a data cap, the share used
+// so far and the usage projected for the whole month at the current rate are reported too.
+
+use crate::config::Config;
+use crate::metrics::Metric;
+use crate::state;
+use crate::FetchError;
+use tracing::{debug, info};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+const MAX_GAP: u64 = 15 * 60;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Usage {
+    // "2024-03"
+    month: String,
+    rx: u64,
+    tx: u64,
+    // When the usage was last updated, in Unix time
+    updated: u64,
+}
+
+pub struct Tracker {
+    gateway: String,
+    path: PathBuf,
+    // In bytes
+    cap: Option<u64>,
+}
+
+impl Tracker {
+    // None when usage isn't tracked
+    pub fn from_config(config: &Config) -> Option<Tracker> {
+        if !config.track_usage && config.data_cap.is_none() {
+            return None;
+        }
+        Some(Tracker {
+            gateway: config.gateway().to_string(),
+            path: config.usage_file.as_ref().map(PathBuf::from).unwrap_or_else(|| state::file(config, "usage")),
+            cap: config.data_cap.map(|gb| gb * 1_000_000_000),
+        })
+    }
+
+    // Adds the net_rx and net_tx rates in the data to this month's usage, and returns the usage data
+    // points. Nothing is returned when the data has no rates, i.e. the bandwidth collector didn't run.
+    // Has to be called before the units are converted.
+    pub fn update(&self, data: &[Metric]) -> Result<Vec<Metric>, FetchError> {
+        let counter = |name: &str| data.iter().find(|m| m.name == name).map(|m| m.value);
+        let (rx, tx) = match (counter("net_rx"), counter("net_tx")) {
+            (Some(rx), Some(tx)) => (rx, tx),
+            _ => return Ok(Vec::new()),
+        };
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+        let (year, month, month_start, month_length) = month_of(now);
+        let this_month = format!("{:04}-{:02}", year, month);
+
+        let mut all = load(&self.path).map_err(simple_error::SimpleError::new)?;
+        let usage = all.entry(self.gateway.clone()).or_insert_with(|| {
+            debug!("Starting to track data usage of {}", self.gateway);
+            Usage { month: this_month.clone(), ..Usage::default() }
+        });
+        if usage.month != this_month {
+            info!("Data usage for {}: {} bytes received, {} sent", usage.month, usage.rx, usage.tx);
+            usage.month = this_month;
+            usage.rx = 0;
+            usage.tx = 0;
+        }
+        // The first reading, or one after a long gap, only counts its own minute
+        let elapsed = match now.checked_sub(usage.updated) {
+            Some(elapsed) if elapsed <= MAX_GAP => elapsed,
+            _ => 60,
+        };
+        usage.rx += rx * elapsed / 60;
+        usage.tx += tx * elapsed / 60;
+        usage.updated = now;
+        let usage = usage.clone();
+        save(&self.path, &all).map_err(simple_error::SimpleError::new)?;
+
+        let total = usage.rx + usage.tx;
+        let mut metrics = vec![
+            Metric::new("usage_month_rx_bytes", usage.rx),
+            Metric::new("usage_month_tx_bytes", usage.tx),
+            Metric::new("usage_month_bytes", total),
+        ];
+        if let Some(cap) = self.cap {
+            let elapsed = (now - month_start).max(1);
+            let projected = (u128::from(total) * u128::from(month_length) / u128::from(elapsed)) as u64;
+            metrics.push(Metric::new("usage_cap_bytes", cap));
+            metrics.push(Metric::new("usage_cap_percent", (u128::from(total) * 100 / u128::from(cap.max(1))) as u64));
+            metrics.push(Metric::new("usage_projected_bytes", projected));
+            metrics.push(Metric::new("usage_projected_overage_bytes", projected.saturating_sub(cap)));
+        }
+        Ok(metrics)
+    }
+}
+
+// The year, month, start of the month, and its length in seconds, for a Unix time
+fn month_of(seconds: u64) -> (i64, u32, u64, u64) {
+    let days = (seconds / SECONDS_PER_DAY) as i64;
+    let (year, month, day) = civil_from_days(days);
+    let start = (days - i64::from(day - 1)) as u64 * SECONDS_PER_DAY;
+    let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    let next_start = days_from_civil(next_year, next_month) as u64 * SECONDS_PER_DAY;
+    (year, month, start, next_start - start)
+}
+
+// Howard Hinnant's date algorithms, from days since 1970-01-01 to (year, month, day) and back
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// One "gateway month rx tx updated" line per gateway
+fn load(path: &Path) -> Result<BTreeMap<String, Usage>, String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+    };
+    let mut all = BTreeMap::new();
+    for line in contents.lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() != 5 {
+            continue;
+        }
+        let number = |i: usize| parts[i].parse::<u64>().map_err(|_| format!("Invalid data usage in {}: {}", path.display(), line));
+        all.insert(parts[0].to_string(), Usage {
+            month: parts[1].to_string(),
+            rx: number(2)?,
+            tx: number(3)?,
+            updated: number(4)?,
+        });
+    }
+    Ok(all)
+}
+
+fn save(path: &Path, all: &BTreeMap<String, Usage>) -> Result<(), String> {
+    let contents: String = all
+        .iter()
+        .map(|(gateway, u)| format!("{} {} {} {} {}\n", gateway, u.month, u.rx, u.tx, u.updated))
+        .collect();
+    fs::write(path, contents).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
@@ -0,0 +1,78 @@
+// Watching the router's public (WAN) address
+//
+// The last address seen for each gateway is kept in a file, so changes are noticed across one-off
+// runs as well as in daemon mode. A change is reported as a wan_ip_changed data point tagged with the
+// old and new address, sent as a notification (see notify.rs), and optionally updated in dynamic DNS
+// (see ddns.rs).
+
+use crate::config::{Config, Ddns};
+use crate::ddns;
+use crate::metrics::Metric;
+use crate::notify::{self, Event, Notifier};
+use crate::state;
+use crate::{FetchError, FiosClient};
+use tracing::{debug, info};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub old: String,
+    pub new: String,
+}
+
+pub struct Watcher {
+    gateway: String,
+    path: PathBuf,
+    notifier: Notifier,
+    ddns: Option<Ddns>,
+}
+
+impl Watcher {
+    // None when the address isn't watched
+    pub fn from_config(config: &Config) -> Result<Option<Watcher>, String> {
+        if !config.watch_wan_ip && config.wan_ip_webhook.is_none() && config.ddns.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Watcher {
+            gateway: config.gateway().to_string(),
+            path: config.wan_ip_file.as_ref().map(PathBuf::from).unwrap_or_else(|| state::file(config, "wan-ip")),
+            notifier: Notifier::from_config(config)?,
+            ddns: config.ddns.clone(),
+        }))
+    }
+
+    // Compares the current address with the last one seen, and reports a change as a data point and
+    // a notification. The first time around there is nothing to compare with, so it's just recorded.
+    // When a notification or the DNS update fails the new address isn't recorded, so they are tried
+    // again the next time. The DNS name is also updated the first time, in case it's out of date.
+    pub fn check(&self, client: &reqwest::blocking::Client, session: &FiosClient) -> Result<Vec<Metric>, FetchError> {
+        let ip = session.wan_ip()?;
+        let mut known = state::load_values(&self.path).map_err(simple_error::SimpleError::new)?;
+        let change = match known.get(&self.gateway) {
+            Some(old) if *old == ip => return Ok(Vec::new()),
+            Some(old) => Some(Change { old: old.clone(), new: ip.clone() }),
+            None => None,
+        };
+
+        if let Some(change) = &change {
+            info!("WAN IP changed from {} to {}", change.old, change.new);
+            let event = Event::new(notify::WAN_IP, "WAN IP changed", &format!("{} is now {}, was {}", self.gateway, change.new, change.old))
+                .field("gateway", self.gateway.as_str())
+                .field("old_ip", change.old.as_str())
+                .field("new_ip", change.new.as_str());
+            self.notifier.send(client, &event)?;
+        } else {
+            debug!("WAN IP is {}", ip);
+        }
+        if let Some(ddns) = &self.ddns {
+            ddns::update(client, ddns, &ip)?;
+        }
+        known.insert(self.gateway.clone(), ip);
+        state::save_values(&self.path, &known).map_err(simple_error::SimpleError::new)?;
+
+        Ok(change
+            .map(|change| Metric::new("wan_ip_changed", 1).tag("old_ip", &change.old).tag("new_ip", &change.new))
+            .into_iter()
+            .collect())
+    }
+}
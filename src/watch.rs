@@ -0,0 +1,221 @@
+// The `watch` command: a live view of the router in the terminal
+//
+// Shows the traffic of the last readings as sparklines, the devices with their WiFi signal, and
+// whether the router and its WAN address can be reached. Nothing is stored, so it's useful before any
+// database is set up. The router is read on the bandwidth collector's interval, or --refresh.
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::execute;
+use fios_stats::collectors::{bandwidth, Collector};
+use fios_stats::hostnames::{self, Nickname};
+use fios_stats::router::Device;
+use fios_stats::{Config, FetchError, FiosClient, Gateway};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Sparkline, Table};
+use ratatui::{Frame, Terminal};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::time::{Duration, Instant};
+
+// How many readings the sparklines go back
+const HISTORY: usize = 120;
+
+type Client = reqwest::blocking::Client;
+
+#[derive(Default)]
+struct State {
+    // Bytes per minute
+    rx: VecDeque<u64>,
+    tx: VecDeque<u64>,
+    devices: Vec<Device>,
+    wan_ip: Option<String>,
+    // Why the last refresh failed
+    error: Option<String>,
+    updated: Option<Instant>,
+}
+
+pub fn run(
+    client: &Client,
+    gateway: &Gateway,
+    password: &str,
+    config: &Config,
+    refresh: Option<Duration>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let refresh = match refresh {
+        Some(refresh) => refresh,
+        None => config
+            .collector_intervals()?
+            .into_iter()
+            .find(|(collector, _)| collector.name() == bandwidth::NAME)
+            .map(|(_, interval)| interval)
+            .unwrap_or_else(|| bandwidth::Bandwidth.default_interval()),
+    };
+    let nicknames = hostnames::nicknames(config)?;
+    let mut session = Some(FiosClient::login_with(client, gateway, password)?);
+
+    terminal::enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    let result = show(client, gateway, password, config.gateway(), refresh, &nicknames, &mut session);
+    // Put the terminal back the way it was, even when something failed
+    terminal::disable_raw_mode()?;
+    execute!(io::stdout(), LeaveAlternateScreen)?;
+
+    if let Some(session) = session {
+        session.logout()?;
+    }
+    result
+}
+
+fn show(
+    client: &Client,
+    gateway: &Gateway,
+    password: &str,
+    name: &str,
+    refresh: Duration,
+    nicknames: &HashMap<String, Nickname>,
+    session: &mut Option<FiosClient>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    let mut state = State::default();
+    let mut next_refresh = Instant::now();
+    loop {
+        if Instant::now() >= next_refresh {
+            if session.is_none() {
+                match FiosClient::login_with(client, gateway, password) {
+                    Ok(new_session) => *session = Some(new_session),
+                    Err(e) => state.error = Some(e.to_string()),
+                }
+            }
+            if let Some(current) = session.as_ref() {
+                if let Err(e) = update(current, nicknames, &mut state) {
+                    state.error = Some(e.to_string());
+                    // Most likely the session expired, so log in again the next time
+                    *session = None;
+                }
+            }
+            next_refresh = Instant::now() + refresh;
+        }
+
+        terminal.draw(|frame| draw(frame, name, refresh, &state))?;
+
+        let timeout = next_refresh.saturating_duration_since(Instant::now());
+        if event::poll(timeout)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    KeyCode::Char('r') => next_refresh = Instant::now(),
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+fn update(session: &FiosClient, nicknames: &HashMap<String, Nickname>, state: &mut State) -> Result<(), FetchError> {
+    let stats = session.network_stats()?;
+    let mut devices = session.devices()?;
+    hostnames::rename(&mut devices, nicknames);
+    let wan_ip = session.wan_ip()?;
+
+    for (history, value) in [(&mut state.rx, stats.rx_bytes), (&mut state.tx, stats.tx_bytes)] {
+        if history.len() == HISTORY {
+            history.pop_front();
+        }
+        history.push_back(value);
+    }
+    // Connected first, then by name
+    devices.sort_by_key(|d| (!d.connected, d.name.clone().unwrap_or_default().to_lowercase()));
+    state.devices = devices;
+    state.wan_ip = Some(wan_ip);
+    state.error = None;
+    state.updated = Some(Instant::now());
+    Ok(())
+}
+
+fn draw(frame: &mut Frame, name: &str, refresh: Duration, state: &State) {
+    let areas = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(4),
+            Constraint::Length(6),
+            Constraint::Length(6),
+            Constraint::Min(4),
+        ])
+        .split(frame.size());
+
+    let status = match (&state.error, state.updated) {
+        (Some(error), _) => Line::styled(format!("Error: {}", error), Style::default().fg(Color::Red)),
+        (None, Some(updated)) => Line::styled(
+            format!("Up, updated {}s ago, every {}s", updated.elapsed().as_secs(), refresh.as_secs()),
+            Style::default().fg(Color::Green),
+        ),
+        (None, None) => Line::raw("Connecting..."),
+    };
+    let header = Paragraph::new(vec![
+        status,
+        Line::raw(format!("WAN IP: {}", state.wan_ip.as_deref().unwrap_or("unknown"))),
+    ])
+    .block(Block::default().borders(Borders::ALL).title(format!(" {}  (q to quit, r to refresh) ", name)));
+    frame.render_widget(header, areas[0]);
+
+    for (area, name, history, color) in [
+        (areas[1], "Download", &state.rx, Color::Cyan),
+        (areas[2], "Upload", &state.tx, Color::Magenta),
+    ] {
+        let data: Vec<u64> = history.iter().cloned().collect();
+        let title = format!(" {} {} ", name, rate(history.back().cloned().unwrap_or(0)));
+        let sparkline = Sparkline::default()
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .data(&data)
+            .style(Style::default().fg(color));
+        frame.render_widget(sparkline, area);
+    }
+
+    let connected = state.devices.iter().filter(|d| d.connected).count();
+    let rows = state.devices.iter().map(|d| {
+        let row = Row::new(vec![
+            d.name.clone().unwrap_or_default(),
+            d.ip.clone().unwrap_or_default(),
+            d.mac.clone().unwrap_or_default(),
+            d.signal.map(|s| format!("{} dBm", s)).unwrap_or_default(),
+        ]);
+        if d.connected {
+            row
+        } else {
+            row.style(Style::default().fg(Color::DarkGray))
+        }
+    });
+    let widths = [
+        Constraint::Percentage(35),
+        Constraint::Percentage(20),
+        Constraint::Percentage(25),
+        Constraint::Percentage(20),
+    ];
+    let table = Table::new(rows, widths)
+        .header(Row::new(vec!["Name", "IP", "MAC", "Signal"]).style(Style::default().add_modifier(Modifier::BOLD)))
+        .block(Block::default().borders(Borders::ALL).title(format!(
+            " Devices: {} connected, {} known ",
+            connected,
+            state.devices.len()
+        )));
+    frame.render_widget(table, areas[3]);
+}
+
+// Bytes per minute as bits per second
+fn rate(bytes_per_minute: u64) -> String {
+    let bits = bytes_per_minute as f64 * 8.0 / 60.0;
+    if bits >= 1e9 {
+        format!("{:.1} Gbit/s", bits / 1e9)
+    } else if bits >= 1e6 {
+        format!("{:.1} Mbit/s", bits / 1e6)
+    } else {
+        format!("{:.0} kbit/s", bits / 1e3)
+    }
+}
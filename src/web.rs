@@ -0,0 +1,134 @@
+// A small web dashboard for daemon mode, for when there's no Grafana around
+//
+// The daemon keeps the last day of bandwidth readings and the latest device list in memory, and
+// serves them along with a page graphing them. Nothing survives a restart, for history use a sink.
+// The server (axum, on its own thread) is only built with the web feature.
+
+use crate::metrics::Metric;
+use crate::router::Device;
+use crate::FetchError;
+#[cfg(feature = "web")]
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// How far back the bandwidth graph goes, in seconds
+const RETENTION: u64 = 24 * 60 * 60;
+
+#[cfg(feature = "web")]
+const INDEX: &str = include_str!("web/index.html");
+
+#[derive(Default)]
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+struct History {
+    // Unix time, and rx/tx bytes per minute
+    bandwidth: VecDeque<(u64, u64, u64)>,
+    devices: Vec<Device>,
+    // Unix time of the last device list
+    devices_updated: Option<u64>,
+}
+
+#[cfg(feature = "web")]
+impl History {
+    fn bandwidth_json(&self) -> Value {
+        Value::Array(
+            self.bandwidth
+                .iter()
+                .map(|(time, rx, tx)| json!({"time": time, "rx": rx, "tx": tx}))
+                .collect(),
+        )
+    }
+
+    fn devices_json(&self) -> Value {
+        let devices: Vec<Value> = self
+            .devices
+            .iter()
+            .map(|d| json!({"name": d.name, "mac": d.mac, "ip": d.ip, "connected": d.connected, "signal": d.signal}))
+            .collect();
+        json!({"updated": self.devices_updated, "devices": devices})
+    }
+}
+
+#[derive(Clone)]
+pub struct Dashboard {
+    history: Arc<Mutex<History>>,
+}
+
+impl Dashboard {
+    // Starts serving the dashboard on an address like 127.0.0.1:8080
+    pub fn start(address: &str) -> Result<Dashboard, FetchError> {
+        if cfg!(not(feature = "web")) {
+            bail!("Built without the web feature, can't serve the dashboard on {}", address);
+        }
+        let dashboard = Dashboard {
+            history: Arc::new(Mutex::new(History::default())),
+        };
+        #[cfg(feature = "web")]
+        serve(address, dashboard.clone())?;
+        Ok(dashboard)
+    }
+
+    // Records a cycle's bandwidth reading. Has to be called before the units are converted.
+    pub fn record(&self, data: &[Metric]) {
+        let value = |name: &str| data.iter().find(|m| m.name == name).map(|m| m.value);
+        let (rx, tx) = match (value("net_rx"), value("net_tx")) {
+            (Some(rx), Some(tx)) => (rx, tx),
+            _ => return,
+        };
+        let now = now();
+        let mut history = self.history.lock().unwrap();
+        history.bandwidth.push_back((now, rx, tx));
+        while history.bandwidth.front().is_some_and(|(time, _, _)| *time + RETENTION < now) {
+            history.bandwidth.pop_front();
+        }
+    }
+
+    pub fn set_devices(&self, devices: &[Device]) {
+        let mut history = self.history.lock().unwrap();
+        history.devices = devices.to_vec();
+        history.devices_updated = Some(now());
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+// The address is bound here, so a port in use is an error at startup rather than on the thread
+#[cfg(feature = "web")]
+fn serve(address: &str, dashboard: Dashboard) -> Result<(), FetchError> {
+    use axum::extract::State;
+    use axum::response::{Html, Json};
+    use axum::routing::get;
+    use tracing::{error, info};
+
+    let listener = match std::net::TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(e) => bail!("Could not listen on {}: {}", address, e),
+    };
+    if let Err(e) = listener.set_nonblocking(true) {
+        bail!("Could not listen on {}: {}", address, e);
+    }
+    let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => bail!("Could not start the dashboard: {}", e),
+    };
+    info!("Serving the dashboard on http://{}/", address);
+
+    let app = axum::Router::new()
+        .route("/", get(|| async { Html(INDEX) }))
+        .route("/api/bandwidth", get(|State(d): State<Dashboard>| async move { Json(d.history.lock().unwrap().bandwidth_json()) }))
+        .route("/api/devices", get(|State(d): State<Dashboard>| async move { Json(d.history.lock().unwrap().devices_json()) }))
+        .with_state(dashboard);
+    std::thread::spawn(move || {
+        let result = runtime.block_on(async {
+            let listener = tokio::net::TcpListener::from_std(listener)?;
+            axum::serve(listener, app).await
+        });
+        if let Err(e) = result {
+            error!("The dashboard stopped: {}", e);
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,149 @@
+// Runs the collectors against saved router responses, one case per directory under tests/fixtures
+// (see tests/fixtures/README.md), and checks the metrics they produce. This catches firmware
+// versions whose responses we no longer parse the same way.
+
+use fios_stats::collectors;
+use fios_stats::metrics::Metric;
+use fios_stats::{Config, FiosClient};
+use httptest::matchers::{eq, request};
+use httptest::responders::{json_encoded, status_code};
+use httptest::{all_of, Expectation, Server};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize)]
+struct Case {
+    collectors: Vec<String>,
+    // API path -> the file to answer it with
+    responses: BTreeMap<String, String>,
+    metrics: Vec<ExpectedMetric>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct ExpectedMetric {
+    name: String,
+    tags: BTreeMap<String, String>,
+    value: u64,
+    unit: String,
+}
+
+impl From<Metric> for ExpectedMetric {
+    fn from(metric: Metric) -> ExpectedMetric {
+        ExpectedMetric {
+            name: metric.name,
+            tags: metric.tags.into_iter().collect(),
+            value: metric.value,
+            unit: metric.unit.name().to_string(),
+        }
+    }
+}
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures")
+}
+
+fn expect_login(server: &Server, model: &str) {
+    match model {
+        "g1100" => {
+            server.expect(
+                Expectation::matching(request::method_path("GET", "/api/login"))
+                    .respond_with(json_encoded(json!({"passwordSalt": "salt"}))),
+            );
+            server.expect(
+                Expectation::matching(request::method_path("POST", "/api/login")).respond_with(
+                    status_code(200)
+                        .append_header("Set-Cookie", "XSRF-TOKEN=token; Path=/")
+                        .append_header("Set-Cookie", "Session=session-id; Path=/"),
+                ),
+            );
+        }
+        "g3100" => {
+            server.expect(
+                Expectation::matching(request::method_path("GET", "/loginStatus.cgi"))
+                    .respond_with(json_encoded(json!({"passwordSalt": "salt", "loginToken": "token"}))),
+            );
+            server.expect(
+                Expectation::matching(request::method_path("POST", "/login.cgi"))
+                    .respond_with(status_code(200).append_header("Set-Cookie", "sysauth=session-id; Path=/")),
+            );
+        }
+        _ => panic!("No login handshake for the {} fixtures", model),
+    }
+}
+
+fn expect_response(server: &Server, path: &str, body: String) {
+    let responder = status_code(200).append_header("Content-Type", "application/json").body(body);
+    match path.split_once('?') {
+        Some((path, query)) => server.expect(
+            Expectation::matching(all_of![request::method_path("GET", eq(path.to_string())), request::query(eq(query.to_string()))])
+                .respond_with(responder),
+        ),
+        None => server.expect(Expectation::matching(request::method_path("GET", eq(path.to_string()))).respond_with(responder)),
+    }
+}
+
+fn run_case(model: &str, dir: &Path) {
+    let read = |name: &str| {
+        let path = dir.join(name);
+        fs::read_to_string(&path).unwrap_or_else(|e| panic!("Can't read {}: {}", path.display(), e))
+    };
+    let case: Case = serde_json::from_str(&read("case.json")).unwrap_or_else(|e| panic!("{}: {}", dir.display(), e));
+
+    let server = Server::run();
+    expect_login(&server, model);
+    for (path, file) in &case.responses {
+        expect_response(&server, path, read(file));
+    }
+
+    let config = Config {
+        gateway: Some(format!("http://{}", server.addr())),
+        model: Some(model.to_string()),
+        ..Config::default()
+    };
+    let client = FiosClient::login(&config, "password").unwrap();
+    let mut metrics = Vec::new();
+    for name in &case.collectors {
+        let collector = collectors::by_name(name).unwrap_or_else(|| panic!("{}: no collector {}", dir.display(), name));
+        let collected = collector.collect(&client, &config).unwrap_or_else(|e| panic!("{}: {} failed: {}", dir.display(), name, e));
+        // Everything written has to be in the schema
+        for metric in &collected {
+            let measurement = collector.measurements().iter().find(|m| m.name == metric.name);
+            let measurement = measurement.unwrap_or_else(|| panic!("{}: {} isn't in the {} measurements", dir.display(), metric.name, name));
+            for (key, _) in &metric.tags {
+                assert!(measurement.tags.contains(&key.as_str()), "{}: {} has no {} tag in the schema", dir.display(), metric.name, key);
+            }
+        }
+        metrics.extend(collected.into_iter().map(ExpectedMetric::from));
+    }
+
+    let mut expected = case.metrics;
+    expected.sort();
+    metrics.sort();
+    assert_eq!(metrics, expected, "{}", dir.display());
+}
+
+fn run_cases(model: &str) {
+    let mut dirs: Vec<PathBuf> = fs::read_dir(fixtures_dir().join(model))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+    assert!(!dirs.is_empty(), "No {} fixtures", model);
+    for dir in dirs {
+        run_case(model, &dir);
+    }
+}
+
+#[test]
+fn g1100_fixtures() {
+    run_cases("g1100");
+}
+
+#[test]
+fn g3100_fixtures() {
+    run_cases("g3100");
+}
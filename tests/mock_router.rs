@@ -0,0 +1,1034 @@
+// Runs the client against a mock G1100 and InfluxDB, to check the login handshake, the parsing of
+// the responses, and the error handling without a real router
+
+use fios_stats::collectors::{self, bandwidth::Bandwidth, devices::Devices, presence::Presence, ErrorCounts};
+use fios_stats::error::AuthError;
+use fios_stats::metrics::Metric;
+use fios_stats::router::auth::PasswordHash;
+use fios_stats::router::schema;
+#[cfg(any(feature = "influx", feature = "remote-write"))]
+use fios_stats::sinks;
+use fios_stats::{redact, Config, FetchError, FiosClient, Gateway};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+#[cfg(feature = "influx")]
+use httptest::matchers::url_decoded;
+use httptest::matchers::{contains, json_decoded, key, matches, not, request};
+use httptest::responders::{json_encoded, status_code};
+use httptest::{all_of, cycle, Expectation, Server};
+use serde_json::json;
+use std::io::Write;
+
+const PASSWORD: &str = "secret_password";
+
+fn config(server: &Server) -> Config {
+    Config {
+        gateway: Some(format!("http://{}", server.addr())),
+        model: Some("g1100".to_string()),
+        ..Config::default()
+    }
+}
+
+fn expect_login_info(server: &Server) {
+    server.expect(
+        Expectation::matching(request::method_path("GET", "/api/login"))
+            .respond_with(json_encoded(json!({
+                "passwordSalt": "salt",
+                "requirePassword": true,
+                "error": 0,
+                "denyState": 0,
+                "denyTimeout": 0,
+            }))),
+    );
+}
+
+fn expect_login(server: &Server) {
+    expect_login_info(server);
+    server.expect(
+        Expectation::matching(request::method_path("POST", "/api/login")).respond_with(
+            status_code(200)
+                .append_header("Set-Cookie", "XSRF-TOKEN=token; Path=/")
+                .append_header("Set-Cookie", "Session=session-id; Path=/"),
+        ),
+    );
+}
+
+// A request made with the session cookie and XSRF token from the login
+macro_rules! authed {
+    ($method:expr, $path:expr) => {
+        all_of![
+            request::method_path($method, $path),
+            request::headers(contains(("x-xsrf-token", "token"))),
+            request::headers(contains(("cookie", matches("Session=session-id")))),
+        ]
+    };
+}
+
+#[test]
+fn collects_network_stats() {
+    let server = Server::run();
+    expect_login(&server);
+    server.expect(
+        Expectation::matching(authed!("GET", "/api/network/1")).respond_with(json_encoded(json!({
+            "bandwidth": {"minutesRx": [1000, 2000], "minutesTx": [300, 400]},
+            "rxErrors": 2,
+            "rxDropped": 1,
+        }))),
+    );
+    server.expect(Expectation::matching(authed!("GET", "/api/logout")).respond_with(status_code(200)));
+
+    let client = FiosClient::login(&config(&server), PASSWORD).unwrap();
+    let stats = client.network_stats().unwrap();
+    assert_eq!(stats.rx_bytes, 1000);
+    assert_eq!(stats.tx_bytes, 300);
+    assert_eq!(stats.rx_errors, Some(2));
+    assert_eq!(stats.rx_dropped, Some(1));
+    client.logout().unwrap();
+}
+
+#[test]
+fn rotated_xsrf_token() {
+    let server = Server::run();
+    expect_login(&server);
+    server.expect(
+        Expectation::matching(authed!("GET", "/api/wireless/sitesurvey")).respond_with(
+            status_code(200)
+                .append_header("Set-Cookie", "XSRF-TOKEN=token2; Path=/")
+                .body("[]"),
+        ),
+    );
+    server.expect(
+        Expectation::matching(all_of![
+            request::method_path("GET", "/api/devices"),
+            request::headers(contains(("x-xsrf-token", "token2"))),
+        ])
+        .respond_with(json_encoded(json!([]))),
+    );
+
+    let client = FiosClient::login(&config(&server), PASSWORD).unwrap();
+    client.wifi_networks().unwrap();
+    client.devices().unwrap();
+}
+
+#[test]
+fn session_is_redacted() {
+    let server = Server::run();
+    expect_login(&server);
+
+    FiosClient::login(&config(&server), PASSWORD).unwrap();
+    assert_eq!(redact::redact("Logged in with session session-id"), "Logged in with session [redacted]");
+    redact::register_uri("http://localhost:8086/write?db=fios_data&u=fios&p=influx-password");
+    assert_eq!(redact::redact("p=influx-password"), "p=[redacted]");
+}
+
+#[test]
+fn config_secrets_are_redacted() {
+    let mut config: Config = toml::from_str(
+        r#"
+        alert_webhook = "https://example.com/hooks/alert-secret"
+
+        [[notifications]]
+        type = "slack"
+        url = "https://hooks.slack.com/services/T000/B000/slack-secret"
+
+        [[notifications]]
+        type = "email"
+        url = "smtps://smtp.example.com"
+        password = "smtp-password"
+        "#,
+    )
+    .unwrap();
+    config.wan_ip_webhook = Some("https://example.com/hooks/wan-secret".to_string());
+    redact::register_config(&config);
+
+    let error = "error sending request for url (https://hooks.slack.com/services/T000/B000/slack-secret)";
+    assert_eq!(redact::redact(error), "error sending request for url ([redacted])");
+    assert_eq!(redact::redact("POST https://example.com/hooks/alert-secret"), "POST [redacted]");
+    assert_eq!(redact::redact("POST https://example.com/hooks/wan-secret"), "POST [redacted]");
+    assert_eq!(redact::redact("login with smtp-password"), "login with [redacted]");
+    // The mail server isn't a secret
+    assert_eq!(redact::redact("smtps://smtp.example.com"), "smtps://smtp.example.com");
+}
+
+// A gateway with a path, e.g. behind a reverse proxy, keeps it for the CGI scripts
+#[test]
+fn g3100_gateway_with_path() {
+    let server = Server::run();
+    server.expect(
+        Expectation::matching(request::method_path("GET", "/fios/loginStatus.cgi"))
+            .respond_with(json_encoded(json!({"passwordSalt": "salt", "loginToken": "token"}))),
+    );
+    server.expect(
+        Expectation::matching(request::method_path("POST", "/fios/login.cgi"))
+            .respond_with(status_code(200).append_header("Set-Cookie", "sysauth=session-id; Path=/")),
+    );
+    server.expect(Expectation::matching(request::method_path("GET", "/fios/logout.cgi")).respond_with(status_code(200)));
+
+    let config = Config {
+        gateway: Some(format!("http://{}/fios/", server.addr())),
+        model: Some("g3100".to_string()),
+        ..Config::default()
+    };
+    FiosClient::login(&config, PASSWORD).unwrap().logout().unwrap();
+}
+
+#[test]
+fn cr1000a_gateway_with_path() {
+    let server = Server::run();
+    server.expect(
+        Expectation::matching(request::method_path("GET", "/fios/loginStatus.cgi"))
+            .times(2)
+            .respond_with(json_encoded(json!({"passwordSalt": "salt", "loginToken": "token"}))),
+    );
+    server.expect(Expectation::matching(request::method_path("POST", "/fios/login.cgi")).respond_with(status_code(200)));
+    server.expect(Expectation::matching(request::method_path("GET", "/fios/logout.cgi")).respond_with(status_code(200)));
+
+    let config = Config {
+        gateway: Some(format!("http://{}/fios/", server.addr())),
+        model: Some("cr1000a".to_string()),
+        ..Config::default()
+    };
+    FiosClient::login(&config, PASSWORD).unwrap().logout().unwrap();
+}
+
+#[test]
+fn wrong_password() {
+    let server = Server::run();
+    expect_login_info(&server);
+    server.expect(
+        Expectation::matching(request::method_path("POST", "/api/login"))
+            .respond_with(status_code(401).body(r#"{"error": 1}"#)),
+    );
+
+    match FiosClient::login(&config(&server), "wrong") {
+        Err(FetchError::Auth(AuthError::WrongPassword)) => (),
+        Err(e) => panic!("Unexpected error: {}", e),
+        Ok(_) => panic!("Login with the wrong password succeeded"),
+    }
+}
+
+#[test]
+fn password_hashes() {
+    let hash = |name: &str| PasswordHash::from_name(name).unwrap().hash(PASSWORD, "salt");
+    assert_eq!(
+        hash("sha512"),
+        "99c3da41016b9bb651d0d945f88f4f7c923a4631ec07a3dce9fa1535e0a5312f13e80d17b6517ab3bf45711832dd7815f96a235555ec9825e761009f86e33295"
+    );
+    assert_eq!(hash("sha256"), "d7abe09c5c8de6faff70febbc1c093fdae225afa8cc344a2046c67e9626fb454");
+    assert_eq!(hash("hmac-sha256"), "88e0d8b26abe60a7cd16146ceaa29d447b8c7bb016d4a6dcb4f73c40bf83549b");
+}
+
+#[test]
+fn configured_password_hash() {
+    let server = Server::run();
+    expect_login_info(&server);
+    server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/api/login"),
+            request::body(r#"{"password":"d7abe09c5c8de6faff70febbc1c093fdae225afa8cc344a2046c67e9626fb454"}"#),
+        ])
+        .respond_with(
+            status_code(200)
+                .append_header("Set-Cookie", "XSRF-TOKEN=token; Path=/")
+                .append_header("Set-Cookie", "Session=session-id; Path=/"),
+        ),
+    );
+
+    let config = Config {
+        password_hash: Some("sha256".to_string()),
+        ..config(&server)
+    };
+    FiosClient::login(&config, PASSWORD).unwrap();
+}
+
+#[test]
+fn login_with_username() {
+    let server = Server::run();
+    expect_login_info(&server);
+    server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/api/login"),
+            request::body(json_decoded(|body: &serde_json::Value| body["username"] == "operator")),
+        ])
+        .respond_with(
+            status_code(200)
+                .append_header("Set-Cookie", "XSRF-TOKEN=token; Path=/")
+                .append_header("Set-Cookie", "Session=session-id; Path=/"),
+        ),
+    );
+
+    let config = Config {
+        username: Some("operator".to_string()),
+        ..config(&server)
+    };
+    FiosClient::login(&config, PASSWORD).unwrap();
+}
+
+#[test]
+fn login_without_password() {
+    let server = Server::run();
+    server.expect(
+        Expectation::matching(request::method_path("GET", "/api/login"))
+            .respond_with(json_encoded(json!({"requirePassword": false, "error": 0, "denyState": 0}))),
+    );
+    server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/api/login"),
+            request::body(r#"{"password":""}"#),
+        ])
+        .respond_with(
+            status_code(200)
+                .append_header("Set-Cookie", "XSRF-TOKEN=token; Path=/")
+                .append_header("Set-Cookie", "Session=session-id; Path=/"),
+        ),
+    );
+
+    FiosClient::login(&config(&server), "").unwrap();
+}
+
+#[test]
+fn locked_out() {
+    let server = Server::run();
+    server.expect(
+        Expectation::matching(request::method_path("GET", "/api/login")).respond_with(json_encoded(json!({
+            "passwordSalt": "salt",
+            "denyState": 1,
+            "denyTimeout": 60,
+        }))),
+    );
+
+    match FiosClient::login(&config(&server), PASSWORD) {
+        Err(FetchError::Auth(AuthError::LockedOut(60))) => (),
+        Err(e) => panic!("Unexpected error: {}", e),
+        Ok(_) => panic!("Login while locked out succeeded"),
+    }
+}
+
+#[test]
+fn missing_session_cookie() {
+    let server = Server::run();
+    expect_login_info(&server);
+    server.expect(
+        Expectation::matching(request::method_path("POST", "/api/login"))
+            .respond_with(status_code(200).append_header("Set-Cookie", "XSRF-TOKEN=token; Path=/")),
+    );
+
+    match FiosClient::login(&config(&server), PASSWORD) {
+        Err(FetchError::Auth(AuthError::MissingCookie(ref name))) if name == "Session" => (),
+        Err(e) => panic!("Unexpected error: {}", e),
+        Ok(_) => panic!("Login without a session cookie succeeded"),
+    }
+}
+
+#[test]
+fn malformed_json() {
+    let server = Server::run();
+    expect_login(&server);
+    server.expect(
+        Expectation::matching(authed!("GET", "/api/network/1")).respond_with(status_code(200).body("{\"bandwidth\": ")),
+    );
+
+    let client = FiosClient::login(&config(&server), PASSWORD).unwrap();
+    match client.network_stats() {
+        Err(FetchError::Parse { ref api, .. }) if api == "network/1" => (),
+        Err(e) => panic!("Unexpected error: {}", e),
+        Ok(stats) => panic!("Parsed malformed JSON as {:?}", stats),
+    }
+}
+
+#[test]
+fn failing_collector_is_skipped() {
+    let server = Server::run();
+    expect_login(&server);
+    server.expect(
+        Expectation::matching(authed!("GET", "/api/network/1")).respond_with(status_code(200).body("{\"bandwidth\": ")),
+    );
+    server.expect(
+        Expectation::matching(authed!("GET", "/api/devices"))
+            .respond_with(json_encoded(json!([{"mac": "aa:bb:cc:dd:ee:ff", "status": true}]))),
+    );
+
+    let config = config(&server);
+    let client = FiosClient::login(&config, PASSWORD).unwrap();
+    let mut errors = ErrorCounts::default();
+    let (data, failures) = collectors::run(&[&Bandwidth, &Devices], &client, &config, &mut errors);
+    assert_eq!(failures.len(), 1);
+    assert!(!failures[0].is_session_lost());
+    let value = |name: &str, collector: Option<&str>| {
+        data.iter()
+            .find(|m| m.name == name && collector.is_none_or(|c| m.tags.contains(&("collector".to_string(), c.to_string()))))
+            .map(|m| m.value)
+    };
+    assert_eq!(value("devices_known", None), Some(1));
+    assert_eq!(value("net_rx", None), None);
+    assert_eq!(value("collector_success", Some("bandwidth")), Some(0));
+    assert_eq!(value("collector_errors", Some("bandwidth")), Some(1));
+    assert_eq!(value("collector_success", Some("devices")), Some(1));
+    assert_eq!(value("collector_errors", Some("devices")), Some(0));
+}
+
+#[test]
+fn expired_session() {
+    let server = Server::run();
+    expect_login(&server);
+    server.expect(Expectation::matching(authed!("GET", "/api/network/1")).respond_with(status_code(403)));
+
+    let config = config(&server);
+    let client = FiosClient::login(&config, PASSWORD).unwrap();
+    let (_, failures) = collectors::run(&[&Bandwidth], &client, &config, &mut ErrorCounts::default());
+    assert_eq!(failures.len(), 1);
+    assert!(failures[0].is_session_lost());
+}
+
+// Like the daemon: every cycle runs the due collectors against the one session. The G1100 is
+// detected rather than configured, so the collectors it doesn't support are only left out once it is.
+#[test]
+fn detected_g1100_skips_unsupported_collectors() {
+    let server = Server::run();
+    server.expect(
+        Expectation::matching(request::method_path("GET", "/api/login"))
+            .times(2)
+            .respond_with(json_encoded(json!({"passwordSalt": "salt", "requirePassword": true}))),
+    );
+    server.expect(
+        Expectation::matching(request::method_path("POST", "/api/login")).respond_with(
+            status_code(200)
+                .append_header("Set-Cookie", "XSRF-TOKEN=token; Path=/")
+                .append_header("Set-Cookie", "Session=session-id; Path=/"),
+        ),
+    );
+    server.expect(
+        Expectation::matching(authed!("GET", "/api/network/1"))
+            .times(2)
+            .respond_with(json_encoded(json!({"bandwidth": {"minutesRx": [1000], "minutesTx": [300]}}))),
+    );
+    server.expect(Expectation::matching(authed!("GET", "/api/devices")).times(2..).respond_with(json_encoded(json!([]))));
+    server.expect(
+        Expectation::matching(authed!("GET", "/api/wireless/sitesurvey")).times(2).respond_with(json_encoded(json!([]))),
+    );
+
+    let config = Config {
+        gateway: Some(format!("http://{}", server.addr())),
+        ..Config::default()
+    };
+    let due: Vec<_> = config.collector_intervals().unwrap().into_iter().map(|(collector, _)| collector).collect();
+    let client = FiosClient::login(&config, PASSWORD).unwrap();
+    let mut errors = ErrorCounts::default();
+    for _ in 0..2 {
+        let (data, failures) = collectors::run(&due, &client, &config, &mut errors);
+        assert!(failures.is_empty(), "{:?}", failures);
+        let collectors: Vec<&str> = data
+            .iter()
+            .filter(|m| m.name == "collector_success")
+            .flat_map(|m| m.tags.iter().filter(|(key, _)| key == "collector").map(|(_, value)| value.as_str()))
+            .collect();
+        assert_eq!(collectors, ["bandwidth", "devices", "site_survey"]);
+    }
+}
+
+#[test]
+fn missing_field() {
+    let server = Server::run();
+    expect_login(&server);
+    server.expect(
+        Expectation::matching(authed!("GET", "/api/network/1")).respond_with(json_encoded(json!({"rxErrors": 0}))),
+    );
+
+    let client = FiosClient::login(&config(&server), PASSWORD).unwrap();
+    match client.network_stats() {
+        Err(FetchError::Response { .. }) => (),
+        Err(e) => panic!("Unexpected error: {}", e),
+        Ok(stats) => panic!("Parsed a response without the counters as {:?}", stats),
+    }
+}
+
+#[test]
+fn missing_error_counters() {
+    let server = Server::run();
+    expect_login(&server);
+    server.expect(
+        Expectation::matching(authed!("GET", "/api/network/1")).respond_with(json_encoded(json!({
+            "bandwidth": {"minutesRx": [1000], "minutesTx": [300]},
+            "rxErrors": 2,
+            "rxDroppedPackets": 1,
+        }))),
+    );
+
+    let client = FiosClient::login(&config(&server), PASSWORD).unwrap();
+    let stats = client.network_stats().unwrap();
+    assert_eq!(stats.rx_bytes, 1000);
+    assert_eq!(stats.rx_errors, Some(2));
+    assert_eq!(stats.rx_dropped, None);
+}
+
+#[test]
+fn unknown_fields_are_noted() {
+    let server = Server::run();
+    expect_login(&server);
+    server.expect(
+        Expectation::matching(authed!("GET", "/api/wireless/sitesurvey"))
+            .respond_with(json_encoded(json!([{"ssid": "neighbor", "channel": 6, "band": "2.4GHz"}]))),
+    );
+
+    let client = FiosClient::login(&config(&server), PASSWORD).unwrap();
+    client.wifi_networks().unwrap();
+    assert_eq!(schema::unknown_fields("wireless/sitesurvey"), vec!["band".to_string()]);
+}
+
+#[test]
+fn unchanged_responses_are_reused() {
+    let server = Server::run();
+    expect_login(&server);
+    server.expect(
+        Expectation::matching(all_of![
+            authed!("GET", "/api/wireless/sitesurvey"),
+            request::headers(not(contains(key("if-none-match")))),
+        ])
+        .respond_with(
+            status_code(200)
+                .append_header("ETag", "\"v1\"")
+                .body(json!([{"ssid": "neighbor", "channel": 6}]).to_string()),
+        ),
+    );
+    server.expect(
+        Expectation::matching(all_of![
+            authed!("GET", "/api/wireless/sitesurvey"),
+            request::headers(contains(("if-none-match", "\"v1\""))),
+        ])
+        .times(2)
+        .respond_with(status_code(304)),
+    );
+
+    let client = FiosClient::login(&config(&server), PASSWORD).unwrap();
+    for _ in 0..3 {
+        let networks = client.wifi_networks().unwrap();
+        assert_eq!(networks.len(), 1);
+        assert_eq!(networks[0].channel, Some(6));
+    }
+}
+
+#[test]
+fn gzipped_responses() {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(json!([{"mac": "aa:bb:cc:dd:ee:ff", "status": true}]).to_string().as_bytes()).unwrap();
+    let body = encoder.finish().unwrap();
+
+    let server = Server::run();
+    expect_login(&server);
+    server.expect(
+        Expectation::matching(all_of![
+            authed!("GET", "/api/devices"),
+            request::headers(contains(("accept-encoding", matches("gzip")))),
+        ])
+        .respond_with(status_code(200).append_header("Content-Encoding", "gzip").body(body)),
+    );
+
+    let client = FiosClient::login(&config(&server), PASSWORD).unwrap();
+    let devices = client.devices().unwrap();
+    assert_eq!(devices.len(), 1);
+    assert!(devices[0].connected);
+}
+
+#[cfg(feature = "influx")]
+fn influx_config(server: &Server) -> Config {
+    Config {
+        influxdb: Some(server.url_str("/write?db=fios_data")),
+        ..Config::default()
+    }
+}
+
+#[test]
+#[cfg(feature = "influx")]
+fn writes_to_influx() {
+    let server = Server::run();
+    server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/write"),
+            request::query(matches("db=fios_data")),
+            request::body("net_rx,host=myfiosgateway.com value=8000i\n"),
+        ])
+        .respond_with(status_code(204)),
+    );
+
+    let config = influx_config(&server);
+    let client = Gateway::from_config(&config).unwrap().client_builder().build().unwrap();
+    let data = vec![Metric::new("net_rx", 8000).tag("host", "myfiosgateway.com")];
+    sinks::write(&client, &config, &data).unwrap();
+}
+
+#[test]
+#[cfg(feature = "remote-write")]
+fn writes_to_remote_write() {
+    let server = Server::run();
+    server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/api/v1/push"),
+            request::headers(contains(("content-encoding", "snappy"))),
+            request::headers(contains(("content-type", "application/x-protobuf"))),
+            request::body(matches("__name__\\x12\\x25fios_network_receive_bytes_per_second")),
+            request::body(matches("Received in the last minute")),
+            request::body(matches("host\\x12\\x11myfiosgateway\\.com")),
+        ])
+        .respond_with(status_code(204)),
+    );
+
+    let config = Config {
+        remote_write: Some(server.url_str("/api/v1/push")),
+        ..Config::default()
+    };
+    let client = Gateway::from_config(&config).unwrap().client_builder().build().unwrap();
+    let data = vec![Metric::new("net_rx", 8000).tag("host", "myfiosgateway.com")];
+    sinks::write(&client, &config, &data).unwrap();
+}
+
+#[test]
+#[cfg(feature = "influx")]
+fn influx_write_failure() {
+    let server = Server::run();
+    server.expect(
+        Expectation::matching(request::method_path("POST", "/write"))
+            .respond_with(status_code(400).body(r#"{"error": "unable to parse"}"#)),
+    );
+
+    let config = Config {
+        influxdb: Some(server.url_str("/write?db=fios_data&u=fios&p=influx-secret")),
+        ..Config::default()
+    };
+    let client = Gateway::from_config(&config).unwrap().client_builder().build().unwrap();
+    let data = vec![Metric::new("net_rx", 8000)];
+    match sinks::write(&client, &config, &data) {
+        // Not the URI, with the password
+        Err(FetchError::Sink(e)) => assert!(e.to_string().starts_with("Unexpected status 400 Bad Request from InfluxDB write"), "{}", e),
+        Err(e) => panic!("Unexpected error: {}", e),
+        Ok(()) => panic!("Write that InfluxDB rejected succeeded"),
+    }
+}
+
+#[test]
+#[cfg(feature = "influx")]
+fn influx_rejected_lines_are_dropped() {
+    let server = Server::run();
+    server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/write"),
+            request::body("net_rx value=8000i\nnet_tx value=1i\n"),
+        ])
+        .respond_with(status_code(400).body(r#"{"error": "unable to parse 'net_tx value=1i': invalid field format"}"#)),
+    );
+    server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/write"),
+            request::body("net_rx value=8000i\n"),
+        ])
+        .respond_with(status_code(204)),
+    );
+
+    let config = influx_config(&server);
+    let client = Gateway::from_config(&config).unwrap().client_builder().build().unwrap();
+    let data = vec![Metric::new("net_rx", 8000), Metric::new("net_tx", 1)];
+    sinks::write(&client, &config, &data).unwrap();
+}
+
+#[test]
+fn config_from_environment() {
+    let secrets = std::env::temp_dir().join(format!("fios-stats-secrets-{}", std::process::id()));
+    std::fs::create_dir_all(&secrets).unwrap();
+    std::fs::write(secrets.join("fios_password"), "12345\n").unwrap();
+    std::fs::write(secrets.join("other_service_token"), "not ours").unwrap();
+
+    let variables = vec![
+        ("FIOS_GATEWAY", "192.168.1.1"),
+        ("FIOS_RATE_LIMIT", "0.5"),
+        ("FIOS_TOFU", "true"),
+        ("FIOS_COLLECTORS", r#"["bandwidth", "devices"]"#),
+        ("FIOS_INTERVALS__DEVICES", "10m"),
+        ("FIOS_INFLUX_URI", "read by the flag"),
+        ("HOME", "/root"),
+    ];
+    let config = Config::from_env(variables.into_iter().map(|(k, v)| (k.to_string(), v.to_string())), &secrets).unwrap();
+    std::fs::remove_dir_all(&secrets).unwrap();
+
+    assert_eq!(config.gateway.as_deref(), Some("192.168.1.1"));
+    assert_eq!(config.rate_limit, Some(0.5));
+    assert!(config.tofu);
+    assert_eq!(config.collectors, vec!["bandwidth", "devices"]);
+    assert_eq!(config.intervals.get("devices").map(String::as_str), Some("10m"));
+    // A secret is a string, even when it looks like a number
+    assert_eq!(config.password.as_deref(), Some("12345"));
+
+    let unknown = vec![("FIOS_NO_SUCH_SETTING".to_string(), "1".to_string())];
+    assert!(Config::from_env(unknown, std::path::Path::new("/nonexistent")).is_err());
+}
+
+#[test]
+fn numbers_for_string_settings_from_environment() {
+    let variables = vec![
+        ("FIOS_PASSWORD", "12345"),
+        ("FIOS_USERNAME", "true"),
+        ("FIOS_TAGS__ZIP", "02139"),
+        ("FIOS_TAGS__FLOOR", "2"),
+        ("FIOS_PROBE_COUNT", "3"),
+    ];
+    let variables = variables.into_iter().map(|(k, v)| (k.to_string(), v.to_string()));
+    let config = Config::from_env(variables, std::path::Path::new("/nonexistent")).unwrap();
+
+    assert_eq!(config.password.as_deref(), Some("12345"));
+    assert_eq!(config.username.as_deref(), Some("true"));
+    assert_eq!(config.tags.get("zip").map(String::as_str), Some("02139"));
+    assert_eq!(config.tags.get("floor").map(String::as_str), Some("2"));
+    // Settings that take numbers still get one
+    assert_eq!(config.probe_count, Some(3));
+}
+
+#[test]
+#[cfg(feature = "influx")]
+fn influx_write_is_verified() {
+    let server = Server::run();
+    server.expect(Expectation::matching(request::method_path("POST", "/write")).respond_with(status_code(204)));
+    // Of the two net_rx series only one made it
+    server.expect(
+        Expectation::matching(all_of![
+            request::method_path("GET", "/query"),
+            request::query(url_decoded(contains(("db", "fios_data")))),
+            request::query(url_decoded(contains((
+                "q",
+                r#"SELECT count("value") FROM "net_rx" WHERE time = 1700000000s; SELECT count("value") FROM "net_tx" WHERE time = 1700000000s"#
+            )))),
+        ])
+        .respond_with(json_encoded(json!({"results": [
+            {"statement_id": 0, "series": [{"name": "net_rx", "columns": ["time", "count"], "values": [[0, 1]]}]},
+            {"statement_id": 1, "series": [{"name": "net_tx", "columns": ["time", "count"], "values": [[0, 1]]}]},
+        ]}))),
+    );
+
+    let config = influx_config(&server);
+    let client = Gateway::from_config(&config).unwrap().client_builder().build().unwrap();
+    let mut data = vec![
+        Metric::new("net_rx", 8000).tag("interface", "wan"),
+        Metric::new("net_rx", 100).tag("interface", "lan"),
+        Metric::new("net_tx", 1),
+    ];
+    fios_stats::metrics::set_time(&mut data, 1_700_000_000);
+    sinks::write(&client, &config, &data).unwrap();
+    assert_eq!(sinks::influx::verify(&client, &config.influxdb.unwrap(), &data).unwrap(), 1);
+}
+
+#[test]
+fn hourly_rollups() {
+    let mut rollup = fios_stats::rollup::Rollup::default();
+    let cycle = |value, time| {
+        let mut data = vec![Metric::new("net_rx", value).tag("interface", "wan")];
+        fios_stats::metrics::set_time(&mut data, time);
+        data
+    };
+    // 2023-11-14 22:00 UTC
+    let hour = 1_699_999_200;
+    assert!(rollup.add(&cycle(100, hour + 60)).is_empty());
+    assert!(rollup.add(&cycle(300, hour + 120)).is_empty());
+
+    let rollups = rollup.add(&cycle(50, hour + 3600));
+    let value = |name: &str| rollups.iter().find(|m| m.name == name).map(|m| (m.value, m.time));
+    assert_eq!(value("net_rx_hourly"), Some((200, Some(hour))));
+    assert_eq!(value("net_rx_hourly_max"), Some((300, Some(hour))));
+    assert!(rollups.iter().all(|m| m.tags == vec![("interface".to_string(), "wan".to_string())]));
+}
+
+#[test]
+fn firmware_update_is_noticed() {
+    let state_dir = std::env::temp_dir().join(format!("fios-stats-firmware-{}", std::process::id()));
+    let server = Server::run();
+    expect_login(&server);
+    server.expect(
+        Expectation::matching(authed!("GET", "/api/settings/system"))
+            .times(3)
+            .respond_with(cycle![
+                json_encoded(json!({"firmwareVersion": "02.02.00.13"})),
+                json_encoded(json!({"firmwareVersion": "02.02.00.13"})),
+                json_encoded(json!({"firmwareVersion": "02.03.00.08"})),
+            ]),
+    );
+
+    let config = Config {
+        watch_firmware: true,
+        state_dir: Some(state_dir.to_string_lossy().to_string()),
+        ..config(&server)
+    };
+    let client = Gateway::from_config(&config).unwrap().client_builder().build().unwrap();
+    let session = FiosClient::login(&config, PASSWORD).unwrap();
+    let watcher = fios_stats::firmware::Watcher::from_config(&config).unwrap().unwrap();
+    // The first version is only recorded
+    assert!(watcher.check(&client, &session).unwrap().is_empty());
+    assert!(watcher.check(&client, &session).unwrap().is_empty());
+    let changed = watcher.check(&client, &session).unwrap();
+    std::fs::remove_dir_all(&state_dir).unwrap();
+
+    assert_eq!(changed.len(), 1);
+    assert_eq!(changed[0].name, "firmware_changed");
+    assert_eq!(
+        changed[0].tags,
+        vec![
+            ("old_version".to_string(), "02.02.00.13".to_string()),
+            ("new_version".to_string(), "02.03.00.08".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn device_hostname_tags() {
+    let server = Server::run();
+    expect_login(&server);
+    // Once by the presence collector and once for the host names
+    server.expect(
+        Expectation::matching(authed!("GET", "/api/devices")).times(2).respond_with(json_encoded(json!([
+            {"name": "pixel", "mac": "AA:BB:CC:DD:EE:01", "ip": "192.168.1.10", "status": true},
+            {"name": "", "mac": "aa:bb:cc:dd:ee:02", "ip": "192.168.1.11", "status": true},
+        ]))),
+    );
+
+    let mut config = Config { hostname_tags: true, ..config(&server) };
+    config.presence.insert("alice".to_string(), "aa-bb-cc-dd-ee-01".to_string());
+    config.presence.insert("bob".to_string(), "aa:bb:cc:dd:ee:02".to_string());
+    config.device_names.insert("AA:BB:CC:DD:EE:02".to_string(), "bobs-laptop".to_string());
+    let session = FiosClient::login(&config, PASSWORD).unwrap();
+    let (data, _) = collectors::run(&[&Presence], &session, &config, &mut ErrorCounts::default());
+
+    let hostname = |device: &str| {
+        data.iter()
+            .find(|m| m.name == "device_present" && m.tags.contains(&("device".to_string(), device.to_string())))
+            .and_then(|m| m.tags.iter().find(|(key, _)| key == "hostname"))
+            .map(|(_, value)| value.clone())
+    };
+    assert_eq!(hostname("alice").as_deref(), Some("pixel"));
+    assert_eq!(hostname("bob").as_deref(), Some("bobs-laptop"));
+    // Nothing else has a MAC to look up
+    assert!(data.iter().filter(|m| m.name.starts_with("collector_")).all(|m| m.tags.len() == 1));
+}
+
+#[test]
+fn mac_vendors() {
+    let table = fios_stats::oui::parse(
+        "OUI/MA-L\t\t\tOrganization\n\
+         00-1A-2B   (hex)\t\tExample Corp\n\
+         001A2B     (base 16)\t\tExample Corp\n\
+         \t\t\t\t1 Example Street\n",
+    );
+    assert_eq!(table.len(), 1);
+    assert_eq!(fios_stats::oui::vendor(&table, "00-1A-2B-01-02-03"), Some("Example Corp"));
+    assert_eq!(fios_stats::oui::vendor(&table, "00:1a:2c:01:02:03"), None);
+    // A made up MAC, which has the locally administered bit set
+    assert_eq!(fios_stats::oui::vendor(&table, "02:1a:2b:01:02:03"), None);
+}
+
+#[test]
+fn device_nickname_file() {
+    let path = std::env::temp_dir().join(format!("fios-stats-devices-{}.csv", std::process::id()));
+    std::fs::write(&path, "# MAC,name,category\nAA-BB-CC-DD-EE-01, alices-pixel, phone\naa:bb:cc:dd:ee:02,thermostat\n\n").unwrap();
+    let config = Config {
+        device_file: Some(path.to_string_lossy().to_string()),
+        device_names: vec![("aa:bb:cc:dd:ee:02".to_string(), "hallway-thermostat".to_string())].into_iter().collect(),
+        ..Config::default()
+    };
+    let nicknames = fios_stats::hostnames::nicknames(&config).unwrap();
+
+    std::fs::write(&path, "aa:bb:cc:dd:ee:03\n").unwrap();
+    let broken = fios_stats::hostnames::load_file(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    let pixel = &nicknames["aa:bb:cc:dd:ee:01"];
+    assert_eq!((pixel.name.as_str(), pixel.category.as_deref()), ("alices-pixel", Some("phone")));
+    assert_eq!(nicknames["aa:bb:cc:dd:ee:02"].name, "hallway-thermostat");
+    assert!(broken.unwrap_err().contains("line 1"));
+
+    let mut devices = vec![fios_stats::Device {
+        name: Some("android-9f3a".to_string()),
+        mac: Some("aa:bb:cc:dd:ee:01".to_string()),
+        ip: None,
+        connected: true,
+        signal: None,
+    }];
+    fios_stats::hostnames::rename(&mut devices, &nicknames);
+    assert_eq!(devices[0].name.as_deref(), Some("alices-pixel"));
+}
+
+#[test]
+fn devices_changing_mac_are_correlated() {
+    let state_dir = std::env::temp_dir().join(format!("fios-stats-correlate-{}", std::process::id()));
+    let server = Server::run();
+    expect_login(&server);
+    // The phone comes back with a new made up MAC, while the printer keeps its own
+    server.expect(
+        Expectation::matching(authed!("GET", "/api/devices")).times(2).respond_with(cycle![
+            json_encoded(json!([
+                {"name": "alices-iphone", "mac": "da:00:00:00:00:01", "status": true},
+                {"name": "printer", "mac": "00:1a:2b:00:00:01", "status": true},
+            ])),
+            json_encoded(json!([
+                {"name": "Alices-iPhone", "mac": "de:00:00:00:00:02", "status": true},
+                {"name": "printer", "mac": "00:1a:2b:00:00:01", "status": true},
+            ])),
+        ]),
+    );
+
+    let config = Config {
+        correlate_devices: true,
+        state_dir: Some(state_dir.to_string_lossy().to_string()),
+        ..config(&server)
+    };
+    let session = FiosClient::login(&config, PASSWORD).unwrap();
+    let first = fios_stats::correlate::devices(&session, &config).unwrap();
+    let second = fios_stats::correlate::devices(&session, &config).unwrap();
+    let correlator = fios_stats::correlate::Correlator::from_config(&config).unwrap();
+    let first_macs = correlator.first_macs().unwrap();
+    std::fs::remove_dir_all(&state_dir).unwrap();
+
+    assert_eq!(first[0].mac.as_deref(), Some("da:00:00:00:00:01"));
+    assert_eq!(second[0].mac.as_deref(), Some("da:00:00:00:00:01"));
+    assert_eq!(second[1].mac.as_deref(), Some("00:1a:2b:00:00:01"));
+    assert_eq!(first_macs.get("de:00:00:00:00:02").map(String::as_str), Some("da:00:00:00:00:01"));
+    assert!(!first_macs.contains_key("00:1a:2b:00:00:01"));
+}
+
+#[test]
+fn devices_past_the_limit_are_put_together() {
+    let present = |mac: &str, value| Metric::new("device_present", value).tag("device", mac).tag("mac", mac);
+    let mut data = vec![
+        present("02:00:00:00:00:01", 1),
+        present("02:00:00:00:00:02", 1),
+        present("02:00:00:00:00:03", 1),
+        Metric::new("devices_known", 3),
+    ];
+    fios_stats::cardinality::limit(&mut data, 1);
+    // The first device keeps its series on the next cycle too
+    let mut next = vec![present("02:00:00:00:00:02", 0), present("02:00:00:00:00:01", 1)];
+    fios_stats::cardinality::limit(&mut next, 1);
+
+    let macs = |data: &[Metric]| {
+        data.iter()
+            .filter(|m| m.name == "device_present")
+            .map(|m| (m.tags.iter().find(|(key, _)| key == "mac").unwrap().1.clone(), m.value, m.tags.len()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        macs(&data),
+        vec![("02:00:00:00:00:01".to_string(), 1, 2), ("other".to_string(), 2, 1)]
+    );
+    assert!(data.iter().any(|m| m.name == "devices_known"));
+    assert_eq!(macs(&next), vec![("02:00:00:00:00:01".to_string(), 1, 2), ("other".to_string(), 0, 1)]);
+}
+
+#[test]
+fn dns_probe() {
+    let dns = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = dns.local_addr().unwrap();
+    let answering = std::thread::spawn(move || {
+        let mut buffer = [0u8; 512];
+        let (length, from) = dns.recv_from(&mut buffer).unwrap();
+        // The name is in the question, after the 12 byte header
+        assert_eq!(&buffer[12..length], b"\x07example\x03com\x00\x00\x01\x00\x01");
+        // The same ID, with the response bit set
+        buffer[2] |= 0x80;
+        dns.send_to(&buffer[..length], from).unwrap();
+    });
+
+    let server = Server::run();
+    expect_login(&server);
+    let config = Config {
+        dns_probe: Some("example.com".to_string()),
+        dns_server: Some(address.to_string()),
+        ..config(&server)
+    };
+    let session = FiosClient::login(&config, PASSWORD).unwrap();
+    let (data, failures) = collectors::run(&[&fios_stats::collectors::dns_probe::DnsProbe], &session, &config, &mut ErrorCounts::default());
+    answering.join().unwrap();
+
+    assert!(failures.is_empty());
+    let success = data.iter().find(|m| m.name == "dns_probe_success").unwrap();
+    assert_eq!(success.value, 1);
+    assert_eq!(success.tags, vec![("server".to_string(), "127.0.0.1".to_string())]);
+    assert!(data.iter().any(|m| m.name == "dns_latency_ms"));
+}
+
+#[test]
+fn latency_probe() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let target = listener.local_addr().unwrap().to_string();
+
+    let server = Server::run();
+    expect_login(&server);
+    let config = Config {
+        probe_target: Some(target.clone()),
+        probe_count: Some(2),
+        ..config(&server)
+    };
+    let session = FiosClient::login(&config, PASSWORD).unwrap();
+    let (data, failures) =
+        collectors::run(&[&fios_stats::collectors::latency_probe::LatencyProbe], &session, &config, &mut ErrorCounts::default());
+
+    assert!(failures.is_empty());
+    let loss = data.iter().find(|m| m.name == "probe_loss_percent").unwrap();
+    assert_eq!(loss.value, 0);
+    assert_eq!(loss.tags, vec![("target".to_string(), target)]);
+    assert!(data.iter().any(|m| m.name == "probe_latency_ms"));
+    assert!(data.iter().any(|m| m.name == "probe_latency_max_ms"));
+}
+
+#[test]
+fn router_diagnostics() {
+    use fios_stats::router::tr181;
+    let objects = |data: serde_json::Value| tr181::parse_named_objects("test", &data).unwrap();
+    let ping = objects(json!({"Objects": [{"ObjName": "Device.IP.Diagnostics.IPPing.", "Param": [
+        {"ParamName": "DiagnosticsState", "ParamValue": "Complete"},
+        {"ParamName": "Host", "ParamValue": "1.1.1.1"},
+        {"ParamName": "SuccessCount", "ParamValue": "3"},
+        {"ParamName": "FailureCount", "ParamValue": "1"},
+        {"ParamName": "AverageResponseTime", "ParamValue": "11"},
+        {"ParamName": "MinimumResponseTime", "ParamValue": "9"},
+        {"ParamName": "MaximumResponseTime", "ParamValue": "14"},
+    ]}]}));
+    let hops = objects(json!({"Objects": [
+        {"ObjName": "Device.IP.Diagnostics.TraceRoute.RouteHops.1.", "Param": [
+            {"ParamName": "Host", "ParamValue": "gateway.example.net"},
+            {"ParamName": "HostAddress", "ParamValue": "100.64.0.1"},
+            {"ParamName": "RTTimes", "ParamValue": "3,2, 4"},
+        ]},
+    ]}));
+
+    let ping: Vec<_> = ping.into_iter().map(|(_, object)| object).collect();
+    let diagnostics = tr181::diagnostics(&ping, hops);
+    let ping = diagnostics.ping.unwrap();
+    assert_eq!((ping.host.as_str(), ping.successes, ping.failures), ("1.1.1.1", 3, 1));
+    assert_eq!((ping.min_ms, ping.average_ms, ping.max_ms), (Some(9), Some(11), Some(14)));
+    assert_eq!(diagnostics.traceroute.len(), 1);
+    assert_eq!(diagnostics.traceroute[0].rtt_ms, vec![3, 2, 4]);
+
+    // Never run
+    let never = vec![[("DiagnosticsState".to_string(), "None".to_string())].iter().cloned().collect()];
+    assert!(tr181::diagnostics(&never, Vec::new()).ping.is_none());
+}
+
+#[test]
+fn interface_state_changes() {
+    use fios_stats::collectors::interfaces::changes;
+    use fios_stats::Interface;
+    let wan = |up, last_change| Interface { name: "WAN".to_string(), kind: "wan".to_string(), up, last_change };
+    let change = |data: &[Metric]| {
+        data.iter()
+            .find(|m| m.name == "interface_state_change")
+            .map(|m| (m.value, m.tags.iter().find(|(key, _)| key == "state").unwrap().1.clone()))
+    };
+
+    // Up for 100s when first seen, and still up a minute later
+    let (data, states) = changes("gw", &Default::default(), &[wan(true, Some(100))], 1000);
+    assert_eq!(change(&data), None);
+    let (data, states) = changes("gw", &states, &[wan(true, Some(160))], 1060);
+    assert_eq!(change(&data), None);
+    // Down after being up since 900
+    let (data, states) = changes("gw", &states, &[wan(false, Some(30))], 1120);
+    assert_eq!(change(&data), Some((190, "down".to_string())));
+    // Up again, then down and back up between two polls
+    let (_, states) = changes("gw", &states, &[wan(true, Some(10))], 1180);
+    let (data, _) = changes("gw", &states, &[wan(true, Some(5))], 1240);
+    assert_eq!(change(&data), Some((65, "flap".to_string())));
+    assert_eq!(data[0].name, "interface_up");
+    assert_eq!(data[0].value, 1);
+}
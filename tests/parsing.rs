@@ -0,0 +1,88 @@
+// Feeds arbitrary and truncated JSON to the response parsers. Whatever a router firmware sends,
+// they must return an error rather than panic.
+
+use fios_stats::router::g1100;
+use fios_stats::router::tr181::{self, Counters};
+use proptest::prelude::*;
+use proptest::sample::Index;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+// Mostly the keys the parsers look for, so the generated values get past the first lookups
+fn key() -> impl Strategy<Value = String> {
+    prop_oneof![
+        prop::sample::select(vec![
+            "bandwidth", "minutesRx", "minutesTx", "rxErrors", "rxDropped", "name", "mac", "ip", "status", "ssid",
+            "channel", "Objects", "ObjName", "Param", "ParamName", "ParamValue",
+        ])
+        .prop_map(String::from),
+        "[a-zA-Z]{0,8}",
+    ]
+}
+
+fn json() -> impl Strategy<Value = Value> {
+    let leaf = prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::from),
+        any::<u64>().prop_map(Value::from),
+        any::<i64>().prop_map(Value::from),
+        any::<f64>().prop_map(Value::from),
+        ".{0,16}".prop_map(Value::from),
+    ];
+    leaf.prop_recursive(4, 64, 8, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..8).prop_map(Value::from),
+            prop::collection::btree_map(key(), inner, 0..8).prop_map(|m| Value::Object(m.into_iter().collect())),
+        ]
+    })
+}
+
+fn parse_all(data: &Value) {
+    let _ = g1100::parse_network_stats(data);
+    let _ = g1100::parse_devices(data);
+    let _ = g1100::parse_wifi_networks(data);
+    let _ = g1100::parse_minute_history(data);
+    if let Ok(named) = tr181::parse_named_objects("test", data) {
+        tr181::diagnostics(&[], named);
+    }
+    if let Ok(objects) = tr181::parse_objects("test", data) {
+        let _ = Counters::from_stats("test", &objects);
+        tr181::diagnostics(&objects, Vec::new());
+        for object in objects {
+            tr181::device(object.clone());
+            tr181::wifi_network(object);
+        }
+    }
+}
+
+fn fixture(path: &str) -> String {
+    fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(path)).unwrap()
+}
+
+proptest! {
+    #[test]
+    fn arbitrary_json(data in json()) {
+        parse_all(&data);
+    }
+
+    #[test]
+    fn truncated_json(file in prop::sample::select(vec![
+        "g1100/extra-fields/network_1.json",
+        "g1100/extra-fields/devices.json",
+        "g1100/extra-fields/sitesurvey.json",
+        "g3100/basic/hosts.json",
+    ]), at in any::<Index>()) {
+        let body = fixture(file);
+        let body = body.trim_end();
+        let body = &body[..at.index(body.len())];
+        // Anything short of the whole response is invalid JSON, which fetch_json() reports
+        prop_assert!(serde_json::from_str::<Value>(body).is_err());
+    }
+
+    #[test]
+    fn network_stats_without_counters(data in json()) {
+        prop_assume!(data.pointer("/bandwidth/minutesRx/0").and_then(Value::as_u64).is_none());
+        prop_assert!(g1100::parse_network_stats(&data).is_err());
+    }
+}